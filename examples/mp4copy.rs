@@ -6,6 +6,7 @@ use std::path::Path;
 
 use mp4::{
     AacConfig,
+    ChunkPolicy,
     AvcConfig,
     HevcConfig,
     Vp9Config,
@@ -14,7 +15,9 @@ use mp4::{
     MediaType,
     Mp4Config,
     Result,
-    TrackConfig};
+    SampleOrder,
+    TrackConfig,
+    TrackHeader};
 
 fn main() {
     let args: Vec<String> = env::args().collect();
@@ -45,10 +48,11 @@ fn copy<P: AsRef<Path>>(src_filename: &P, dst_filename: &P) -> Result<()> {
             minor_version: mp4_reader.minor_version(),
             compatible_brands: mp4_reader.compatible_brands().to_vec(),
             timescale: mp4_reader.timescale(),
+            creation_time: mp4::creation_time(mp4_reader.moov.mvhd.creation_time),
+            modification_time: mp4::creation_time(mp4_reader.moov.mvhd.modification_time),
         },
     )?;
 
-    // TODO interleaving
     for track_idx in 0..mp4_reader.tracks().len() {
         if let Some(ref track) = mp4_reader.tracks().get(track_idx) {
             let media_conf = match track.media_type()? {
@@ -57,21 +61,32 @@ fn copy<P: AsRef<Path>>(src_filename: &P, dst_filename: &P) -> Result<()> {
                     height: track.height(),
                     seq_param_set: track.sequence_parameter_set()?.to_vec(),
                     pic_param_set: track.picture_parameter_set()?.to_vec(),
+                    seq_param_set_ext: track
+                        .sequence_parameter_set_ext()
+                        .unwrap_or_default()
+                        .to_vec(),
+                    box_type: track.avc_box_type()?,
                 }),
                 MediaType::H265 => MediaConfig::HevcConfig(HevcConfig {
                     width: track.width(),
                     height: track.height(),
+                    video_param_set: track.video_parameter_set()?.to_vec(),
+                    seq_param_set: track.sequence_parameter_set()?.to_vec(),
+                    pic_param_set: track.picture_parameter_set()?.to_vec(),
+                    box_type: track.hevc_box_type()?,
                 }),
                 MediaType::VP9 => MediaConfig::Vp9Config(Vp9Config {
                     width: track.width(),
                     height: track.height(),
                 }),
+                MediaType::AV1 => MediaConfig::Av1Config(track.av1_config()?),
                 MediaType::AAC => MediaConfig::AacConfig(AacConfig {
                     bitrate: track.bitrate(),
                     profile: track.audio_profile()?,
                     freq_index: track.sample_freq_index()?,
                     chan_conf: track.channel_config()?,
                 }),
+                MediaType::OPUS => MediaConfig::OpusConfig(track.opus_config()?),
                 MediaType::TTXT => MediaConfig::TtxtConfig(TtxtConfig {}),
             };
 
@@ -80,21 +95,21 @@ fn copy<P: AsRef<Path>>(src_filename: &P, dst_filename: &P) -> Result<()> {
                 timescale: track.timescale(),
                 language: track.language().to_string(),
                 media_conf,
+                edit_list: Vec::new(),
+                chunk_policy: ChunkPolicy::default(),
+                header: TrackHeader::from(track.track_type()?),
             };
 
-            mp4_writer.add_track(&track_conf)?;
+            mp4_writer.add_track_with_id(track.track_id(), &track_conf)?;
         } else {
             unreachable!()
         }
+    }
 
-        let track_id = track_idx as u32 + 1;
-        let sample_count = mp4_reader.sample_count(track_id)?;
-        for sample_idx in 0..sample_count {
-            let sample_id = sample_idx + 1;
-            let sample = mp4_reader.read_sample(track_id, sample_id)?.unwrap();
-            mp4_writer.write_sample(track_id, &sample)?;
-            // println!("copy {}:({})", sample_id, sample);
-        }
+    for sample in mp4_reader.interleaved_samples(SampleOrder::DecodeTime) {
+        let (track_id, sample) = sample?;
+        mp4_writer.write_sample(track_id, &sample)?;
+        // println!("copy {}:({})", track_id, sample);
     }
 
     mp4_writer.write_end()?;
@@ -57,13 +57,18 @@ fn get_boxes(file: File) -> Result<Vec<Box>> {
         if let Some(mehd) = &mvex.mehd {
             boxes.push(build_box(mehd));
         }
-        boxes.push(build_box(&mvex.trex));
+        for trex in mvex.trexs.iter() {
+            boxes.push(build_box(trex));
+        }
     }
 
     // trak.
     for track in mp4.tracks().iter() {
         boxes.push(build_box(&track.trak));
         boxes.push(build_box(&track.trak.tkhd));
+        if let Some(ref tref) = track.trak.tref {
+            boxes.push(build_box(tref));
+        }
         if let Some(ref edts) = track.trak.edts {
             boxes.push(build_box(edts));
             if let Some(ref elst) = edts.elst {
@@ -91,14 +96,8 @@ fn get_boxes(file: File) -> Result<Vec<Box>> {
         let stbl = &track.trak.mdia.minf.stbl;
         boxes.push(build_box(stbl));
         boxes.push(build_box(&stbl.stsd));
-        if let Some(ref avc1) = &stbl.stsd.avc1 {
-            boxes.push(build_box(avc1));
-        }
-        if let Some(ref hev1) = &stbl.stsd.hev1 {
-            boxes.push(build_box(hev1));
-        }
-        if let Some(ref mp4a) = &stbl.stsd.mp4a {
-            boxes.push(build_box(mp4a));
+        for entry in stbl.stsd.entries.iter() {
+            boxes.push(build_box(entry));
         }
         boxes.push(build_box(&stbl.stts));
         if let Some(ref ctts) = &stbl.ctts {
@@ -124,6 +123,9 @@ fn get_boxes(file: File) -> Result<Vec<Box>> {
         for traf in moof.trafs.iter() {
             boxes.push(build_box(traf));
             boxes.push(build_box(&traf.tfhd));
+            if let Some(ref tfdt) = &traf.tfdt {
+                boxes.push(build_box(tfdt));
+            }
             if let Some(ref trun) = &traf.trun {
                 boxes.push(build_box(trun));
             }
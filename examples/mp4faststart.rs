@@ -0,0 +1,33 @@
+use std::env;
+use std::fs::File;
+use std::io::prelude::*;
+use std::io::{self, BufReader, BufWriter};
+use std::path::Path;
+
+use mp4::Result;
+
+fn main() {
+    let args: Vec<String> = env::args().collect();
+
+    if args.len() < 3 {
+        println!("Usage: mp4faststart <source file> <target file>");
+        std::process::exit(1);
+    }
+
+    if let Err(err) = faststart(&args[1], &args[2]) {
+        let _ = writeln!(io::stderr(), "{}", err);
+    }
+}
+
+fn faststart<P: AsRef<Path>>(src_filename: &P, dst_filename: &P) -> Result<()> {
+    let src_file = File::open(src_filename)?;
+    let size = src_file.metadata()?.len();
+    let reader = BufReader::new(src_file);
+
+    let dst_file = File::create(dst_filename)?;
+    let mut writer = BufWriter::new(dst_file);
+
+    mp4::relocate_moov(reader, size, &mut writer)?;
+    writer.flush()?;
+    Ok(())
+}
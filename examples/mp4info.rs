@@ -62,7 +62,7 @@ fn info<P: AsRef<Path>>(filename: &P) -> Result<()> {
 }
 
 fn video_info(track: &Mp4Track) -> Result<String> {
-    if track.trak.mdia.minf.stbl.stsd.avc1.is_some() {
+    if track.trak.mdia.minf.stbl.stsd.avc().is_some() {
         Ok(format!(
             "{} ({}) ({:?}), {}x{}, {} kb/s, {:.2} fps",
             track.media_type()?,
@@ -87,7 +87,7 @@ fn video_info(track: &Mp4Track) -> Result<String> {
 }
 
 fn audio_info(track: &Mp4Track) -> Result<String> {
-    if let Some(ref mp4a) = track.trak.mdia.minf.stbl.stsd.mp4a {
+    if let Some(mp4a) = track.trak.mdia.minf.stbl.stsd.mp4a() {
         if mp4a.esds.is_some() {
 
             let profile = match track.audio_profile() {
@@ -117,13 +117,22 @@ fn audio_info(track: &Mp4Track) -> Result<String> {
                 track.bitrate() / 1000
             ))
         }
+    } else if let Some(opus) = track.trak.mdia.minf.stbl.stsd.opus() {
+        Ok(format!(
+            "{} ({:?}), {} Hz, {} channels, {} kb/s",
+            track.media_type()?,
+            track.box_type()?,
+            opus.dops.input_sample_rate,
+            opus.dops.output_channel_count,
+            track.bitrate() / 1000
+        ))
     } else {
         Err(Error::InvalidData("mp4a box not found"))
     }
 }
 
 fn subtitle_info(track: &Mp4Track) -> Result<String> {
-    if track.trak.mdia.minf.stbl.stsd.tx3g.is_some() {
+    if track.trak.mdia.minf.stbl.stsd.tx3g().is_some() {
         Ok(format!(
             "{} ({:?})",
             track.media_type()?,
@@ -12,6 +12,8 @@ fn main() -> mp4::Result<()> {
             str::parse("mp41").unwrap(),
         ],
         timescale: 1000,
+        creation_time: 0,
+        modification_time: 0,
     };
 
     let data = Cursor::new(Vec::<u8>::new());
@@ -87,8 +87,7 @@ where
                 Err(DecoderError::NOT_ENOUGH_BITS) => {
                     let sample_result = self.mp4_reader.read_sample(self.track_id, self.position);
                     let sample = sample_result.expect("Error reading sample")?;
-                    let tracks = self.mp4_reader.tracks();
-                    let track = tracks.get(self.track_id as usize - 1).expect("No track ID");
+                    let track = self.mp4_reader.track(self.track_id).expect("No track ID");
                     let adts_header = construct_adts_header(track, &sample).expect("ADTS bytes");
                     let adts_bytes = mp4::Bytes::copy_from_slice(&adts_header);
                     let bytes = [adts_bytes, sample.bytes].concat();
@@ -0,0 +1,256 @@
+use std::io::{Read, Seek, SeekFrom, Write};
+
+use crate::mp4box::*;
+use crate::*;
+
+/// Edits the moov box of an existing file, e.g. to change a track's language
+/// or flags, without rewriting the media data.
+///
+/// The moov is written back in place if it fits into the space of the old
+/// moov and any free boxes right after it, padding the rest with a free box.
+/// Otherwise it's appended to the end of the file and the old moov is turned
+/// into a free box. mdat never moves, so chunk offsets stay valid.
+#[derive(Debug)]
+pub struct Mp4Editor<F> {
+    file: F,
+    end: u64,
+    pub moov: MoovBox,
+    moov_pos: u64,
+    // Size of the moov box plus the free boxes following it.
+    moov_space: u64,
+    has_moof: bool,
+    // Position and type of a box with size 0, which extends to the end of
+    // the file.
+    open_box: Option<(u64, BoxType)>,
+}
+
+impl<F> Mp4Editor<F> {
+    /// Consume self, returning the inner file.
+    pub fn into_inner(self) -> F {
+        self.file
+    }
+}
+
+impl<F: Read + Write + Seek> Mp4Editor<F> {
+    /// Reads the moov box of the MP4 file of `size` bytes in `file`.
+    pub fn open(mut file: F, size: u64) -> Result<Self> {
+        let start = file.stream_position()?;
+        let end = start + size;
+
+        let mut boxes = Vec::new();
+        let mut open_box = None;
+        let mut current = start;
+        while current + HEADER_SIZE <= end {
+            let mut header = BoxHeader::read(&mut file)?;
+            if header.size == 0 {
+                open_box = Some((current, header.name));
+                header.size = end - current;
+            }
+            boxes.push((current, header));
+            current += header.size;
+            skip_bytes_to(&mut file, current)?;
+        }
+
+        let moov_idx = boxes
+            .iter()
+            .position(|(_, header)| header.name == BoxType::MoovBox)
+            .ok_or(Error::BoxNotFound(BoxType::MoovBox))?;
+        let (moov_pos, moov_header) = boxes[moov_idx];
+        let moov_space = moov_header.size
+            + boxes[moov_idx + 1..]
+                .iter()
+                .take_while(|(_, header)| header.name == BoxType::FreeBox)
+                .map(|(_, header)| header.size)
+                .sum::<u64>();
+        let has_moof = boxes
+            .iter()
+            .any(|(_, header)| header.name == BoxType::MoofBox);
+
+        skip_bytes_to(&mut file, moov_pos + moov_header.header_size)?;
+        let moov = MoovBox::read_box(&mut file, moov_header.read_size())?;
+
+        Ok(Self {
+            file,
+            end,
+            moov,
+            moov_pos,
+            moov_space,
+            has_moof,
+            open_box,
+        })
+    }
+
+    /// Writes [Mp4Editor::moov] back to the file.
+    pub fn write_moov(&mut self) -> Result<()> {
+        let moov_size = self.moov.box_size();
+        let fits = moov_size == self.moov_space || moov_size + HEADER_SIZE <= self.moov_space;
+        let at_end = self.moov_pos + self.moov_space == self.end;
+
+        if fits {
+            self.file.seek(SeekFrom::Start(self.moov_pos))?;
+            self.moov.write_box(&mut self.file)?;
+            if moov_size < self.moov_space {
+                BoxHeader::new(BoxType::FreeBox, self.moov_space - moov_size)
+                    .write(&mut self.file)?;
+            }
+        } else if at_end && moov_size > self.moov_space {
+            // Nothing follows, so moov can just grow.
+            self.file.seek(SeekFrom::Start(self.moov_pos))?;
+            self.moov.write_box(&mut self.file)?;
+            self.moov_space = moov_size;
+            self.end = self.moov_pos + moov_size;
+        } else {
+            if self.has_moof {
+                return Err(Error::InvalidData(
+                    "moving moov in fragmented files is not supported",
+                ));
+            }
+            // The box reaching the end of the file must stop short of the new
+            // moov, and its header only has room for a 32-bit size.
+            if let Some((pos, name)) = self.open_box {
+                let header = BoxHeader::new(name, self.end - pos);
+                if header.size > u32::MAX as u64 {
+                    return Err(Error::InvalidData(
+                        "can't append moov after a box of size 0 larger than 4 GiB",
+                    ));
+                }
+                self.file.seek(SeekFrom::Start(pos))?;
+                header.write(&mut self.file)?;
+                self.open_box = None;
+            }
+
+            self.file.seek(SeekFrom::Start(self.moov_pos))?;
+            BoxHeader::new(BoxType::FreeBox, self.moov_space).write(&mut self.file)?;
+
+            self.file.seek(SeekFrom::Start(self.end))?;
+            self.moov.write_box(&mut self.file)?;
+            self.moov_pos = self.end;
+            self.moov_space = moov_size;
+            self.end += moov_size;
+        }
+        self.file.flush()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mp4box::{raw::RawBox, stsd::SampleEntry, tkhd::TrackFlag};
+    use crate::test_util::{read_all_samples, test_config, top_level_boxes, write_two_tracks};
+    use std::io::Cursor;
+
+    fn write_file(fast_start: bool) -> Vec<u8> {
+        let config = test_config();
+        let mut writer = Mp4Writer::write_start(Cursor::new(Vec::new()), &config).unwrap();
+        write_two_tracks(&mut writer);
+        if fast_start {
+            writer.write_end_fast_start().unwrap();
+        } else {
+            writer.write_end().unwrap();
+        }
+        writer.into_writer().into_inner()
+    }
+
+    fn padding(size: usize) -> RawBox {
+        RawBox {
+            name: BoxType::UnknownBox(u32::from_be_bytes(*b"test")),
+            usertype: None,
+            data: vec![0; size],
+        }
+    }
+
+    fn edit<E: FnOnce(&mut MoovBox)>(buf: Vec<u8>, edit: E) -> Vec<u8> {
+        let size = buf.len() as u64;
+        let mut editor = Mp4Editor::open(Cursor::new(buf), size).unwrap();
+        edit(&mut editor.moov);
+        editor.write_moov().unwrap();
+        editor.into_inner().into_inner()
+    }
+
+    #[test]
+    fn test_edit_in_place() {
+        let src = write_file(false);
+        let dst = edit(src.clone(), |moov| {
+            moov.traks[1].mdia.mdhd.language = String::from("eng");
+            moov.traks[1].tkhd.flags &= !(TrackFlag::TrackEnabled as u32);
+        });
+        assert_eq!(dst.len(), src.len());
+        assert_eq!(read_all_samples(&dst), read_all_samples(&src));
+
+        let size = dst.len() as u64;
+        let mp4 = Mp4Reader::read_header(Cursor::new(&dst), size).unwrap();
+        assert_eq!(mp4.tracks()[1].language(), "eng");
+        assert_eq!(
+            mp4.tracks()[1].trak.tkhd.flags & TrackFlag::TrackEnabled as u32,
+            0
+        );
+
+        // moov is last, so it can grow.
+        let dst = edit(dst, |moov| moov.unknown_boxes.push(padding(100)));
+        assert_eq!(dst.len(), src.len() + 108);
+        assert_eq!(read_all_samples(&dst), read_all_samples(&src));
+    }
+
+    #[test]
+    fn test_edit_fast_start() {
+        let src = write_file(true);
+        let boxes = vec![BoxType::FtypBox, BoxType::MoovBox, BoxType::MdatBox];
+        assert_eq!(top_level_boxes(&src), boxes);
+
+        // Doesn't fit in front of mdat, so moov moves to the end.
+        let grown = edit(src.clone(), |moov| moov.unknown_boxes.push(padding(100)));
+        assert_eq!(
+            top_level_boxes(&grown),
+            vec![
+                BoxType::FtypBox,
+                BoxType::FreeBox,
+                BoxType::MdatBox,
+                BoxType::MoovBox
+            ]
+        );
+        assert_eq!(read_all_samples(&grown), read_all_samples(&src));
+
+        // Shrinking leaves a free box as padding.
+        let shrunk = edit(src.clone(), |moov| {
+            moov.traks[0].mdia.minf.stbl.stsd.entries[0] = SampleEntry::Unknown(padding(0));
+        });
+        assert_eq!(shrunk.len(), src.len());
+        assert_eq!(
+            top_level_boxes(&shrunk),
+            vec![
+                BoxType::FtypBox,
+                BoxType::MoovBox,
+                BoxType::FreeBox,
+                BoxType::MdatBox
+            ]
+        );
+
+        // Which the next edit can grow into.
+        let regrown = edit(shrunk, |moov| moov.unknown_boxes.push(padding(8)));
+        assert_eq!(regrown.len(), src.len());
+        assert_eq!(read_all_samples(&regrown), read_all_samples(&src));
+    }
+
+    #[test]
+    fn test_edit_fast_start_open_mdat() {
+        let mut src = write_file(true);
+        let mut mdat_pos = 0;
+        loop {
+            let header = BoxHeader::read(&mut Cursor::new(&src[mdat_pos..])).unwrap();
+            if header.name == BoxType::MdatBox {
+                break;
+            }
+            mdat_pos += header.size as usize;
+        }
+        // mdat extends to the end of the file.
+        src[mdat_pos..mdat_pos + 4].copy_from_slice(&[0; 4]);
+
+        // Gets its real size before moov is appended after it.
+        let grown = edit(src.clone(), |moov| moov.unknown_boxes.push(padding(100)));
+        let mdat_size = (src.len() - mdat_pos) as u32;
+        assert_eq!(&grown[mdat_pos..mdat_pos + 4], &mdat_size.to_be_bytes());
+        assert_eq!(top_level_boxes(&grown).last(), Some(&BoxType::MoovBox));
+        assert_eq!(read_all_samples(&grown), read_all_samples(&src));
+    }
+}
@@ -0,0 +1,172 @@
+use std::io::{self, Read, Seek, Write};
+
+use crate::mp4box::co64::Co64Box;
+use crate::mp4box::*;
+use crate::*;
+
+/// Copies the MP4 file of `size` bytes in `reader` to `writer` with its moov
+/// box moved in front of the media data, adjusting every chunk offset, like
+/// qt-faststart. Files whose moov already precedes mdat are copied unchanged.
+pub fn relocate_moov<R: Read + Seek, W: Write>(mut reader: R, size: u64, mut writer: W) -> Result<()> {
+    let start = reader.stream_position()?;
+    let end = start + size;
+
+    let mut boxes = Vec::new();
+    let mut current = start;
+    while current + HEADER_SIZE <= end {
+        let header = BoxHeader::read_in(&mut reader, end)?;
+        boxes.push((current, header));
+        current += header.size;
+        skip_bytes_to(&mut reader, current)?;
+    }
+
+    let moov_idx = boxes
+        .iter()
+        .position(|(_, header)| header.name == BoxType::MoovBox)
+        .ok_or(Error::BoxNotFound(BoxType::MoovBox))?;
+    let mdat_idx = boxes
+        .iter()
+        .position(|(_, header)| header.name == BoxType::MdatBox);
+    let mdat_idx = match mdat_idx {
+        Some(mdat_idx) if mdat_idx < moov_idx => mdat_idx,
+        _ => {
+            skip_bytes_to(&mut reader, start)?;
+            io::copy(&mut reader.take(size), &mut writer)?;
+            return Ok(());
+        }
+    };
+    if boxes.iter().any(|(_, header)| header.name == BoxType::MoofBox) {
+        return Err(Error::InvalidData(
+            "relocating moov in fragmented files is not supported",
+        ));
+    }
+
+    let (moov_pos, moov_header) = boxes[moov_idx];
+    skip_bytes_to(&mut reader, moov_pos + moov_header.header_size)?;
+    let mut moov = MoovBox::read_box(&mut reader, moov_header.read_size())?;
+
+    // Data between the first mdat and the old moov moves down by the size of
+    // the new moov, data after the old moov by the difference.
+    let insert_pos = boxes[mdat_idx].0;
+    shift_chunk_offsets(&mut moov, |offset, moov_size| {
+        if offset < insert_pos {
+            offset
+        } else if offset < moov_pos {
+            offset + moov_size
+        } else {
+            offset + moov_size - moov_header.size
+        }
+    });
+
+    for (i, (pos, header)) in boxes.iter().enumerate() {
+        if i == mdat_idx {
+            moov.write_box(&mut writer)?;
+        }
+        if i != moov_idx {
+            skip_bytes_to(&mut reader, *pos)?;
+            io::copy(&mut (&mut reader).take(header.size), &mut writer)?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Maps every chunk offset in `moov` through `shift`, which is also given the
+/// resulting moov size. Tracks are switched from stco to co64 when a shifted
+/// offset needs 64 bits; as that grows moov, the check is repeated until the
+/// size settles.
+pub(crate) fn shift_chunk_offsets<F: Fn(u64, u64) -> u64>(moov: &mut MoovBox, shift: F) {
+    loop {
+        let moov_size = moov.box_size();
+        let mut switched = false;
+        for trak in moov.traks.iter_mut() {
+            let stbl = &mut trak.mdia.minf.stbl;
+            if let Some(ref stco) = stbl.stco {
+                let overflow = stco
+                    .entries
+                    .iter()
+                    .any(|offset| shift(*offset as u64, moov_size) > u32::MAX as u64);
+                if overflow {
+                    stbl.co64 = Some(Co64Box::from(stco));
+                    stbl.stco = None;
+                    switched = true;
+                }
+            }
+        }
+        if switched {
+            continue;
+        }
+
+        for trak in moov.traks.iter_mut() {
+            let stbl = &mut trak.mdia.minf.stbl;
+            if let Some(ref mut stco) = stbl.stco {
+                for offset in stco.entries.iter_mut() {
+                    *offset = shift(*offset as u64, moov_size) as u32;
+                }
+            }
+            if let Some(ref mut co64) = stbl.co64 {
+                for offset in co64.entries.iter_mut() {
+                    *offset = shift(*offset, moov_size);
+                }
+            }
+        }
+        return;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mp4box::{stco::StcoBox, trak::TrakBox};
+    use crate::test_util::{read_all_samples, test_config, top_level_boxes, write_two_tracks};
+    use std::io::Cursor;
+
+    #[test]
+    fn test_relocate_moov() {
+        let config = test_config();
+        let mut writer = Mp4Writer::write_start(Cursor::new(Vec::new()), &config).unwrap();
+        write_two_tracks(&mut writer);
+        writer.write_end().unwrap();
+        let src = writer.into_writer().into_inner();
+        assert_eq!(
+            top_level_boxes(&src),
+            vec![BoxType::FtypBox, BoxType::FreeBox, BoxType::MdatBox, BoxType::MoovBox]
+        );
+
+        let mut dst = Vec::new();
+        relocate_moov(Cursor::new(&src), src.len() as u64, &mut dst).unwrap();
+        assert_eq!(dst.len(), src.len());
+        assert_eq!(
+            top_level_boxes(&dst),
+            vec![BoxType::FtypBox, BoxType::FreeBox, BoxType::MoovBox, BoxType::MdatBox]
+        );
+        assert_eq!(read_all_samples(&dst), read_all_samples(&src));
+
+        // Already fast-start, so copied as is.
+        let mut again = Vec::new();
+        relocate_moov(Cursor::new(&dst), dst.len() as u64, &mut again).unwrap();
+        assert_eq!(again, dst);
+    }
+
+    #[test]
+    fn test_shift_chunk_offsets() {
+        let mut moov = MoovBox::default();
+        moov.traks.push(TrakBox::default());
+        moov.traks[0].mdia.minf.stbl.stco = Some(StcoBox {
+            version: 0,
+            flags: 0,
+            entries: vec![100, u32::MAX - 100],
+        });
+        let size = moov.box_size();
+
+        shift_chunk_offsets(&mut moov, |offset, moov_size| offset + moov_size);
+        let stbl = &moov.traks[0].mdia.minf.stbl;
+        assert!(stbl.stco.is_none());
+        let moov_size = size + 8; // two 8-byte instead of 4-byte entries
+        assert_eq!(moov.box_size(), moov_size);
+        assert_eq!(
+            stbl.co64.as_ref().unwrap().entries,
+            vec![100 + moov_size, u32::MAX as u64 - 100 + moov_size]
+        );
+    }
+}
@@ -0,0 +1,505 @@
+use bytes::BytesMut;
+use std::io::Write;
+use std::time::Duration;
+
+use crate::mp4box::*;
+use crate::mp4box::{
+    mfhd::MfhdBox, mvex::MvexBox, tfdt::TfdtBox, tfhd::TfhdBox, traf::TrafBox, trex::TrexBox,
+    trun::TrunBox,
+};
+use crate::track::{Mp4TrackWriter, SAMPLE_IS_NON_SYNC_SAMPLE};
+use crate::*;
+
+// sample_depends_on values of the sample flags.
+const SAMPLE_DEPENDS_ON_OTHERS: u32 = 0x01000000;
+const SAMPLE_DEPENDS_ON_NO_OTHER: u32 = 0x02000000;
+
+/// Writer for fragmented MP4, such as CMAF, DASH or HLS segments.
+///
+/// Writes an init segment (ftyp and a moov with empty sample tables) followed
+/// by moof and mdat fragments. Samples are buffered until the fragment is
+/// cut, which happens at the first sync sample of the reference track once
+/// the fragment spans at least the fragment duration. The reference track is
+/// the first video track, or the first track if there is no video.
+#[derive(Debug)]
+pub struct Mp4FragmentedWriter<W> {
+    writer: W,
+    ftyp: FtypBox,
+    timescale: u32,
+    creation_time: u64,
+    modification_time: u64,
+    fragment_duration: Duration,
+    tracks: Vec<FragmentTrack>,
+    init_written: bool,
+    sequence_number: u32,
+}
+
+#[derive(Debug)]
+struct FragmentTrack {
+    track: Mp4TrackWriter,
+    timescale: u32,
+    is_video: bool,
+    decode_time: u64,
+    sample_description_index: u32,
+
+    // Samples of the fragment being built.
+    data: BytesMut,
+    durations: Vec<u32>,
+    sizes: Vec<u32>,
+    flags: Vec<u32>,
+    cts: Vec<i32>,
+}
+
+impl FragmentTrack {
+    fn buffered_duration(&self) -> Duration {
+        let duration: u64 = self.durations.iter().map(|d| *d as u64).sum();
+        Duration::from_micros(duration * 1_000_000 / self.timescale as u64)
+    }
+
+    /// Builds a traf for the buffered samples, leaving its data offset for
+    /// the caller to fill in.
+    fn traf(&self) -> TrafBox {
+        let mut flags = TrunBox::FLAG_DATA_OFFSET
+            | TrunBox::FLAG_SAMPLE_DURATION
+            | TrunBox::FLAG_SAMPLE_SIZE
+            | TrunBox::FLAG_SAMPLE_FLAGS;
+        if self.cts.iter().any(|cts| *cts != 0) {
+            flags |= TrunBox::FLAG_SAMPLE_CTS;
+        }
+        // Version 1 makes the composition offsets signed.
+        let version = if self.cts.iter().any(|cts| *cts < 0) {
+            1
+        } else {
+            0
+        };
+        let sample_cts = if flags & TrunBox::FLAG_SAMPLE_CTS > 0 {
+            self.cts.iter().map(|cts| *cts as u32).collect()
+        } else {
+            Vec::new()
+        };
+
+        let mut tfhd = TfhdBox {
+            flags: TfhdBox::FLAG_DEFAULT_BASE_IS_MOOF,
+            track_id: self.track.track_id(),
+            ..TfhdBox::default()
+        };
+        // trex defaults to the first sample description.
+        if self.sample_description_index != 1 {
+            tfhd.flags |= TfhdBox::FLAG_SAMPLE_DESCRIPTION_INDEX;
+            tfhd.sample_description_index = Some(self.sample_description_index);
+        }
+
+        TrafBox {
+            tfhd,
+            tfdt: Some(TfdtBox {
+                version: 1,
+                flags: 0,
+                base_media_decode_time: self.decode_time,
+            }),
+            trun: Some(TrunBox {
+                version,
+                flags,
+                sample_count: self.sizes.len() as u32,
+                data_offset: Some(0),
+                first_sample_flags: None,
+                sample_durations: self.durations.clone(),
+                sample_sizes: self.sizes.clone(),
+                sample_flags: self.flags.clone(),
+                sample_cts,
+            }),
+            unknown_boxes: Vec::new(),
+        }
+    }
+
+    /// Drops the buffered samples once they've been written.
+    fn clear(&mut self) {
+        self.decode_time += self.durations.iter().map(|d| *d as u64).sum::<u64>();
+        self.data.clear();
+        self.durations.clear();
+        self.sizes.clear();
+        self.flags.clear();
+        self.cts.clear();
+    }
+}
+
+impl<W> Mp4FragmentedWriter<W> {
+    /// Consume self, returning the inner writer.
+    pub fn into_writer(self) -> W {
+        self.writer
+    }
+
+    /// Gives access to the inner writer, e.g. to split segments into files
+    /// after [Mp4FragmentedWriter::write_init_segment] or a fragment.
+    pub fn writer_mut(&mut self) -> &mut W {
+        &mut self.writer
+    }
+}
+
+impl<W: Write> Mp4FragmentedWriter<W> {
+    pub fn write_start(writer: W, config: &Mp4Config, fragment_duration: Duration) -> Result<Self> {
+        let ftyp = FtypBox {
+            major_brand: config.major_brand,
+            minor_version: config.minor_version,
+            compatible_brands: config.compatible_brands.clone(),
+        };
+
+        Ok(Self {
+            writer,
+            ftyp,
+            timescale: config.timescale,
+            creation_time: mp4_time(config.creation_time),
+            modification_time: mp4_time(config.modification_time),
+            fragment_duration,
+            tracks: Vec::new(),
+            init_written: false,
+            sequence_number: 0,
+        })
+    }
+
+    /// Adds a track with the next free track ID and returns that ID. Tracks
+    /// must be added before the init segment is written.
+    pub fn add_track(&mut self, config: &TrackConfig) -> Result<u32> {
+        if self.init_written {
+            return Err(Error::InvalidData(
+                "tracks must be added before the init segment",
+            ));
+        }
+        let track_id = self
+            .tracks
+            .iter()
+            .map(|track| track.track.track_id())
+            .max()
+            .unwrap_or(0)
+            + 1;
+        let mut track = Mp4TrackWriter::new(track_id, config)?;
+        track.set_times(self.creation_time, self.modification_time);
+        self.tracks.push(FragmentTrack {
+            track,
+            timescale: config.timescale,
+            is_video: config.track_type == TrackType::Video,
+            decode_time: 0,
+            sample_description_index: 1,
+            data: BytesMut::new(),
+            durations: Vec::new(),
+            sizes: Vec::new(),
+            flags: Vec::new(),
+            cts: Vec::new(),
+        });
+        Ok(track_id)
+    }
+
+    /// Adds another sample description to a track and returns its index.
+    /// Like tracks, descriptions must be added before the init segment is
+    /// written. A fragment is cut whenever the description changes.
+    pub fn add_sample_description(&mut self, track_id: u32, media_conf: &MediaConfig) -> Result<u32> {
+        if self.init_written {
+            return Err(Error::InvalidData(
+                "sample descriptions must be added before the init segment",
+            ));
+        }
+        let track = self
+            .tracks
+            .iter_mut()
+            .find(|track| track.track.track_id() == track_id)
+            .ok_or(Error::TrakNotFound(track_id))?;
+        track.track.add_sample_description(media_conf)
+    }
+
+    /// Writes ftyp and moov. Called by the first [Mp4FragmentedWriter::write_sample]
+    /// if not done explicitly.
+    pub fn write_init_segment(&mut self) -> Result<()> {
+        if self.init_written {
+            return Ok(());
+        }
+        self.ftyp.write_box(&mut self.writer)?;
+
+        let mut moov = MoovBox::default();
+        moov.mvhd.timescale = self.timescale;
+        moov.mvhd.creation_time = self.creation_time;
+        moov.mvhd.modification_time = self.modification_time;
+        moov.mvhd.next_track_id = self.tracks.len() as u32 + 1;
+        let mut mvex = MvexBox::default();
+        for track in self.tracks.iter() {
+            let mut trak = track.track.trak().clone();
+            trak.edts = track.track.edts(self.timescale);
+            moov.traks.push(trak);
+            mvex.trexs.push(TrexBox {
+                track_id: track.track.track_id(),
+                default_sample_description_index: 1,
+                ..TrexBox::default()
+            });
+        }
+        moov.mvex = Some(mvex);
+        moov.write_box(&mut self.writer)?;
+
+        self.init_written = true;
+        Ok(())
+    }
+
+    pub fn write_sample(&mut self, track_id: u32, sample: &Mp4Sample) -> Result<()> {
+        self.write_init_segment()?;
+
+        let idx = self
+            .tracks
+            .iter()
+            .position(|track| track.track.track_id() == track_id)
+            .ok_or(Error::TrakNotFound(track_id))?;
+        let description_count = self.tracks[idx].track.trak().mdia.minf.stbl.stsd.entries.len();
+        if sample.sample_description_index == 0
+            || sample.sample_description_index as usize > description_count
+        {
+            return Err(Error::InvalidData("invalid sample description index"));
+        }
+        // A traf only refers to one sample description.
+        if !self.tracks[idx].sizes.is_empty()
+            && self.tracks[idx].sample_description_index != sample.sample_description_index
+        {
+            self.write_fragment()?;
+        }
+        let reference = self
+            .tracks
+            .iter()
+            .position(|track| track.is_video)
+            .unwrap_or(0);
+        if idx == reference
+            && sample.is_sync
+            && self.tracks[idx].buffered_duration() >= self.fragment_duration
+        {
+            self.write_fragment()?;
+        }
+
+        let track = &mut self.tracks[idx];
+        track.sample_description_index = sample.sample_description_index;
+        track.data.extend_from_slice(&sample.bytes);
+        track.durations.push(sample.duration);
+        track.sizes.push(sample.bytes.len() as u32);
+        track.flags.push(if sample.is_sync {
+            SAMPLE_DEPENDS_ON_NO_OTHER
+        } else {
+            SAMPLE_DEPENDS_ON_OTHERS | SAMPLE_IS_NON_SYNC_SAMPLE
+        });
+        track.cts.push(sample.rendering_offset);
+        Ok(())
+    }
+
+    /// Writes the buffered samples as a moof and mdat, if there are any.
+    pub fn write_fragment(&mut self) -> Result<()> {
+        self.write_init_segment()?;
+
+        let data_size: u64 = self
+            .tracks
+            .iter()
+            .map(|track| track.data.len() as u64)
+            .sum();
+        if data_size == 0 {
+            return Ok(());
+        }
+        let mdat = BoxHeader::for_data(BoxType::MdatBox, data_size);
+
+        let mut moof = MoofBox {
+            mfhd: MfhdBox {
+                version: 0,
+                flags: 0,
+                sequence_number: self.sequence_number + 1,
+            },
+            trafs: Vec::new(),
+            unknown_boxes: Vec::new(),
+        };
+        // Data offsets are relative to the moof, so they're filled in once
+        // its size is known. Nothing is dropped until the fragment is written,
+        // so the samples stay buffered if that fails.
+        for track in self.tracks.iter() {
+            if !track.sizes.is_empty() {
+                moof.trafs.push(track.traf());
+            }
+        }
+        let mut data_offset = moof.box_size() + mdat.header_size;
+        for traf in moof.trafs.iter_mut() {
+            let trun = traf.trun.as_mut().unwrap();
+            if data_offset > i32::MAX as u64 {
+                return Err(Error::InvalidData("fragment too large"));
+            }
+            trun.data_offset = Some(data_offset as i32);
+            data_offset += trun
+                .sample_sizes
+                .iter()
+                .map(|size| *size as u64)
+                .sum::<u64>();
+        }
+        moof.write_box(&mut self.writer)?;
+
+        mdat.write(&mut self.writer)?;
+        for track in self.tracks.iter() {
+            self.writer.write_all(&track.data)?;
+        }
+
+        self.sequence_number += 1;
+        for track in self.tracks.iter_mut() {
+            track.clear();
+        }
+        Ok(())
+    }
+
+    /// Writes the last fragment.
+    pub fn write_end(&mut self) -> Result<()> {
+        self.write_fragment()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bytes::Bytes;
+    use std::io::{self, Cursor};
+
+    // Fails every write while `fail` is set.
+    struct FailingWriter {
+        buf: Vec<u8>,
+        fail: bool,
+    }
+
+    impl Write for FailingWriter {
+        fn write(&mut self, data: &[u8]) -> io::Result<usize> {
+            if self.fail {
+                return Err(io::Error::new(io::ErrorKind::Other, "write failed"));
+            }
+            self.buf.write(data)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_fragmented_writer() {
+        let config = Mp4Config {
+            major_brand: str::parse("iso6").unwrap(),
+            minor_version: 0,
+            compatible_brands: vec![str::parse("iso6").unwrap()],
+            timescale: 1000,
+            creation_time: 0,
+            modification_time: 0,
+        };
+        let mut writer =
+            Mp4FragmentedWriter::write_start(Vec::new(), &config, Duration::from_secs(2)).unwrap();
+        let audio = writer
+            .add_track(&TrackConfig {
+                track_type: TrackType::Audio,
+                timescale: 48000,
+                ..TrackConfig::from(TtxtConfig {})
+            })
+            .unwrap();
+        let video = writer
+            .add_track(&TrackConfig {
+                track_type: TrackType::Video,
+                ..TrackConfig::from(TtxtConfig {})
+            })
+            .unwrap();
+
+        writer.write_init_segment().unwrap();
+        let init_size = writer.writer_mut().len();
+
+        let mut expected = Vec::new();
+        for i in 0..10u64 {
+            // A video sync sample every 3rd second, so fragments start at 0s,
+            // 3s, 6s and 9s.
+            let sample = Mp4Sample {
+                start_time: i * 1000,
+                duration: 1000,
+                rendering_offset: if i % 3 == 1 { -500 } else { 0 },
+                is_sync: i % 3 == 0,
+                sample_description_index: 1,
+                bytes: Bytes::from(vec![i as u8; 10 + i as usize]),
+            };
+            writer.write_sample(video, &sample).unwrap();
+            expected.push((video, sample));
+
+            let sample = Mp4Sample {
+                start_time: i * 48000,
+                duration: 48000,
+                rendering_offset: 0,
+                is_sync: true,
+                sample_description_index: 1,
+                bytes: Bytes::from(vec![0xA0 + i as u8; 4]),
+            };
+            writer.write_sample(audio, &sample).unwrap();
+            expected.push((audio, sample));
+        }
+        writer.write_end().unwrap();
+        let buf = writer.into_writer();
+
+        let size = buf.len() as u64;
+        let mut mp4 = Mp4Reader::read_header(Cursor::new(&buf), size).unwrap();
+        assert_eq!(mp4.moofs.len(), 4);
+        assert!(mp4.moov.mvex.is_some());
+        assert!(mp4.moofs[0].trafs.iter().all(|traf| traf.tfdt.is_some()));
+        assert_eq!(
+            mp4.moofs[1].trafs[0]
+                .tfdt
+                .as_ref()
+                .unwrap()
+                .base_media_decode_time,
+            3 * 48000
+        );
+        assert_eq!(mp4.sample_count(video).unwrap(), 10);
+
+        for (track_id, sample) in expected {
+            let sample_id = (sample.start_time / sample.duration as u64) as u32 + 1;
+            let read = mp4.read_sample(track_id, sample_id).unwrap().unwrap();
+            assert_eq!(read, sample);
+            assert_eq!(read.bytes, sample.bytes);
+        }
+
+        // The init segment is readable on its own.
+        let init = &buf[..init_size];
+        let mp4 = Mp4Reader::read_header(Cursor::new(init), init_size as u64).unwrap();
+        assert_eq!(mp4.tracks().len(), 2);
+    }
+
+    #[test]
+    fn test_fragmented_writer_failed_fragment() {
+        let config = Mp4Config {
+            major_brand: str::parse("iso6").unwrap(),
+            minor_version: 0,
+            compatible_brands: vec![str::parse("iso6").unwrap()],
+            timescale: 1000,
+            creation_time: 0,
+            modification_time: 0,
+        };
+        let writer = FailingWriter {
+            buf: Vec::new(),
+            fail: false,
+        };
+        let mut writer =
+            Mp4FragmentedWriter::write_start(writer, &config, Duration::from_secs(10)).unwrap();
+        let track_id = writer.add_track(&TrackConfig::from(TtxtConfig {})).unwrap();
+        for i in 0..3u64 {
+            let sample = Mp4Sample {
+                start_time: i * 1000,
+                duration: 1000,
+                rendering_offset: 0,
+                is_sync: true,
+                sample_description_index: 1,
+                bytes: Bytes::from(vec![i as u8; 8]),
+            };
+            writer.write_sample(track_id, &sample).unwrap();
+        }
+
+        // The samples stay buffered and are written by the next attempt.
+        writer.writer_mut().fail = true;
+        assert!(writer.write_fragment().is_err());
+        writer.writer_mut().fail = false;
+        writer.write_end().unwrap();
+        let buf = writer.into_writer().buf;
+
+        let size = buf.len() as u64;
+        let mut mp4 = Mp4Reader::read_header(Cursor::new(&buf), size).unwrap();
+        assert_eq!(mp4.moofs.len(), 1);
+        assert_eq!(mp4.moofs[0].mfhd.sequence_number, 1);
+        assert_eq!(mp4.sample_count(track_id).unwrap(), 3);
+        let sample = mp4.read_sample(track_id, 3).unwrap().unwrap();
+        assert_eq!(sample.start_time, 2000);
+        assert_eq!(sample.bytes.as_ref(), &[2; 8]);
+    }
+}
@@ -84,10 +84,25 @@ mod track;
 pub use track::{Mp4Track, TrackConfig};
 
 mod reader;
-pub use reader::Mp4Reader;
+pub use reader::{InterleavedSampleIter, Mp4Reader, ReadOptions, ReadWarning, SampleIter};
 
 mod writer;
-pub use writer::{Mp4Config, Mp4Writer};
+pub use writer::{Mp4Config, Mp4Writer, WriteOptions};
+
+mod fragmented_writer;
+pub use fragmented_writer::Mp4FragmentedWriter;
+
+mod stream;
+pub use stream::{Mp4StreamParser, StreamEvent};
+
+mod faststart;
+pub use faststart::relocate_moov;
+
+mod editor;
+pub use editor::Mp4Editor;
+
+#[cfg(test)]
+mod test_util;
 
 pub fn read_mp4(f: File) -> Result<Mp4Reader<BufReader<File>>> {
     let size = f.metadata()?.len();
@@ -0,0 +1,437 @@
+use byteorder::{BigEndian, ReadBytesExt, WriteBytesExt};
+use std::io::{Read, Seek, Write};
+use serde::{Serialize};
+
+use crate::mp4box::*;
+use crate::mp4box::btrt::BtrtBox;
+use crate::mp4box::raw::{child_order, Child, RawBox};
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Av01Box {
+    pub data_reference_index: u16,
+    pub width: u16,
+    pub height: u16,
+
+    #[serde(with = "value_u32")]
+    pub horizresolution: FixedPointU16,
+
+    #[serde(with = "value_u32")]
+    pub vertresolution: FixedPointU16,
+    pub frame_count: u16,
+    pub depth: u16,
+    pub av1c: Av1CBox,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub btrt: Option<BtrtBox>,
+
+    #[serde(skip)]
+    pub unknown_boxes: Vec<RawBox>,
+
+    /// Types of all children in the order they were read, so unknown boxes
+    /// are written back where they were. Empty when there are none.
+    #[serde(skip)]
+    pub child_order: Vec<BoxType>,
+}
+
+impl Default for Av01Box {
+    fn default() -> Self {
+        Av01Box {
+            data_reference_index: 0,
+            width: 0,
+            height: 0,
+            horizresolution: FixedPointU16::new(0x48),
+            vertresolution: FixedPointU16::new(0x48),
+            frame_count: 1,
+            depth: 0x0018,
+            av1c: Av1CBox::default(),
+            btrt: None,
+            unknown_boxes: Vec::new(),
+            child_order: Vec::new(),
+        }
+    }
+}
+
+impl Av01Box {
+    pub fn new(config: &Av1Config) -> Self {
+        Av01Box {
+            data_reference_index: 1,
+            width: config.width,
+            height: config.height,
+            horizresolution: FixedPointU16::new(0x48),
+            vertresolution: FixedPointU16::new(0x48),
+            frame_count: 1,
+            depth: 0x0018,
+            av1c: Av1CBox::new(config),
+            btrt: None,
+            unknown_boxes: Vec::new(),
+            child_order: Vec::new(),
+        }
+    }
+
+    pub fn get_type(&self) -> BoxType {
+        BoxType::Av01Box
+    }
+
+    pub fn get_size(&self) -> u64 {
+        let mut size = HEADER_SIZE + 8 + 70 + self.av1c.box_size();
+        if let Some(ref btrt) = self.btrt {
+            size += btrt.box_size();
+        }
+        for unknown in self.unknown_boxes.iter() {
+            size += unknown.box_size();
+        }
+        size
+    }
+}
+
+impl Mp4Box for Av01Box {
+    fn box_type(&self) -> BoxType {
+        self.get_type()
+    }
+
+    fn box_size(&self) -> u64 {
+        self.get_size()
+    }
+
+    fn to_json(&self) -> Result<String> {
+        Ok(serde_json::to_string(&self).unwrap())
+    }
+
+    fn summary(&self) -> Result<String> {
+        let s = format!("data_reference_index={} width={} height={} frame_count={}",
+            self.data_reference_index, self.width, self.height, self.frame_count);
+        Ok(s)
+    }
+}
+
+impl<R: Read + Seek> ReadBox<&mut R> for Av01Box {
+    fn read_box(reader: &mut R, size: u64) -> Result<Self> {
+        let start = box_start(reader)?;
+
+        reader.read_u32::<BigEndian>()?; // reserved
+        reader.read_u16::<BigEndian>()?; // reserved
+        let data_reference_index = reader.read_u16::<BigEndian>()?;
+
+        reader.read_u32::<BigEndian>()?; // pre-defined, reserved
+        reader.read_u64::<BigEndian>()?; // pre-defined
+        reader.read_u32::<BigEndian>()?; // pre-defined
+        let width = reader.read_u16::<BigEndian>()?;
+        let height = reader.read_u16::<BigEndian>()?;
+        let horizresolution = FixedPointU16::new_raw(reader.read_u32::<BigEndian>()?);
+        let vertresolution = FixedPointU16::new_raw(reader.read_u32::<BigEndian>()?);
+        reader.read_u32::<BigEndian>()?; // reserved
+        let frame_count = reader.read_u16::<BigEndian>()?;
+        skip_bytes(reader, 32)?; // compressorname
+        let depth = reader.read_u16::<BigEndian>()?;
+        reader.read_i16::<BigEndian>()?; // pre-defined
+
+        let mut av1c = None;
+        let mut btrt = None;
+        let mut unknown_boxes = Vec::new();
+        let mut child_order = Vec::new();
+
+        let mut current = reader.stream_position()?;
+        let end = start + size;
+        while current + HEADER_SIZE <= end {
+            let header = BoxHeader::read_in(reader, end)?;
+            let BoxHeader { name, .. } = header;
+            let s = header.read_size();
+            child_order.push(name);
+
+            match name {
+                BoxType::Av1CBox => {
+                    av1c = Some(Av1CBox::read_box(reader, s)?);
+                }
+                BoxType::BtrtBox => {
+                    btrt = Some(BtrtBox::read_box(reader, s)?);
+                }
+                _ => {
+                    unknown_boxes.push(RawBox::read(reader, &header)?);
+                }
+            }
+
+            current = reader.stream_position()?;
+        }
+        if unknown_boxes.is_empty() {
+            child_order.clear();
+        }
+
+        let av1c = av1c.ok_or(Error::InvalidData("av1c not found"))?;
+
+        skip_bytes_to(reader, start + size)?;
+
+        Ok(Av01Box {
+            data_reference_index,
+            width,
+            height,
+            horizresolution,
+            vertresolution,
+            frame_count,
+            depth,
+            av1c,
+            btrt,
+            unknown_boxes,
+            child_order,
+        })
+    }
+}
+
+impl<W: Write> WriteBox<&mut W> for Av01Box {
+    fn write_box(&self, writer: &mut W) -> Result<u64> {
+        let size = self.box_size();
+        BoxHeader::new(self.box_type(), size).write(writer)?;
+
+        writer.write_u32::<BigEndian>(0)?; // reserved
+        writer.write_u16::<BigEndian>(0)?; // reserved
+        writer.write_u16::<BigEndian>(self.data_reference_index)?;
+
+        writer.write_u32::<BigEndian>(0)?; // pre-defined, reserved
+        writer.write_u64::<BigEndian>(0)?; // pre-defined
+        writer.write_u32::<BigEndian>(0)?; // pre-defined
+        writer.write_u16::<BigEndian>(self.width)?;
+        writer.write_u16::<BigEndian>(self.height)?;
+        writer.write_u32::<BigEndian>(self.horizresolution.raw_value())?;
+        writer.write_u32::<BigEndian>(self.vertresolution.raw_value())?;
+        writer.write_u32::<BigEndian>(0)?; // reserved
+        writer.write_u16::<BigEndian>(self.frame_count)?;
+        // skip compressorname
+        write_zeros(writer, 32)?;
+        writer.write_u16::<BigEndian>(self.depth)?;
+        writer.write_i16::<BigEndian>(-1)?; // pre-defined
+
+        let known = [BoxType::Av1CBox, BoxType::BtrtBox];
+        for child in child_order(&self.child_order, &known, self.unknown_boxes.len()) {
+            match child {
+                Child::Known(BoxType::Av1CBox) => {
+                    self.av1c.write_box(writer)?;
+                }
+                Child::Known(BoxType::BtrtBox) => {
+                    if let Some(ref btrt) = self.btrt {
+                        btrt.write_box(writer)?;
+                    }
+                }
+                Child::Known(_) => {}
+                Child::Unknown(i) => {
+                    self.unknown_boxes[i].write_box(writer)?;
+                }
+            }
+        }
+
+        Ok(size)
+    }
+}
+
+/// AV1CodecConfigurationRecord. The fields mirror the sequence header OBU,
+/// which can also be carried in `config_obus`.
+#[derive(Debug, Clone, PartialEq, Default, Serialize)]
+pub struct Av1CBox {
+    pub version: u8,
+    pub seq_profile: u8,
+    pub seq_level_idx_0: u8,
+    pub seq_tier_0: bool,
+    pub high_bitdepth: bool,
+    pub twelve_bit: bool,
+    pub monochrome: bool,
+    pub chroma_subsampling_x: bool,
+    pub chroma_subsampling_y: bool,
+    pub chroma_sample_position: u8,
+    pub initial_presentation_delay_minus_one: Option<u8>,
+    pub config_obus: Vec<u8>,
+}
+
+impl Av1CBox {
+    pub fn new(config: &Av1Config) -> Self {
+        Self {
+            version: 1,
+            seq_profile: config.seq_profile,
+            seq_level_idx_0: config.seq_level_idx,
+            seq_tier_0: config.seq_tier,
+            high_bitdepth: config.bit_depth > 8,
+            twelve_bit: config.bit_depth == 12,
+            monochrome: config.monochrome,
+            chroma_subsampling_x: config.chroma_subsampling_x,
+            chroma_subsampling_y: config.chroma_subsampling_y,
+            chroma_sample_position: config.chroma_sample_position,
+            initial_presentation_delay_minus_one: None,
+            config_obus: config.config_obus.clone(),
+        }
+    }
+
+    pub fn bit_depth(&self) -> u8 {
+        match (self.high_bitdepth, self.twelve_bit) {
+            (false, _) => 8,
+            (true, false) => 10,
+            (true, true) => 12,
+        }
+    }
+}
+
+impl Mp4Box for Av1CBox {
+    fn box_type(&self) -> BoxType {
+        BoxType::Av1CBox
+    }
+
+    fn box_size(&self) -> u64 {
+        HEADER_SIZE + 4 + self.config_obus.len() as u64
+    }
+
+    fn to_json(&self) -> Result<String> {
+        Ok(serde_json::to_string(&self).unwrap())
+    }
+
+    fn summary(&self) -> Result<String> {
+        let s = format!("seq_profile={} seq_level_idx_0={} bit_depth={}",
+            self.seq_profile, self.seq_level_idx_0, self.bit_depth());
+        Ok(s)
+    }
+}
+
+impl<R: Read + Seek> ReadBox<&mut R> for Av1CBox {
+    fn read_box(reader: &mut R, size: u64) -> Result<Self> {
+        let start = box_start(reader)?;
+
+        let params = reader.read_u8()?;
+        if params >> 7 != 1 {
+            return Err(Error::InvalidData("av1c marker not set"));
+        }
+        let version = params & 0x7F;
+        let params = reader.read_u8()?;
+        let seq_profile = params >> 5;
+        let seq_level_idx_0 = params & 0x1F;
+        let params = reader.read_u8()?;
+        let seq_tier_0 = params >> 7 == 1;
+        let high_bitdepth = (params >> 6) & 0x1 == 1;
+        let twelve_bit = (params >> 5) & 0x1 == 1;
+        let monochrome = (params >> 4) & 0x1 == 1;
+        let chroma_subsampling_x = (params >> 3) & 0x1 == 1;
+        let chroma_subsampling_y = (params >> 2) & 0x1 == 1;
+        let chroma_sample_position = params & 0x3;
+        let params = reader.read_u8()?;
+        let initial_presentation_delay_minus_one = if (params >> 4) & 0x1 == 1 {
+            Some(params & 0xF)
+        } else {
+            None
+        };
+
+        let header_size = HEADER_SIZE + 4;
+        let len = size.saturating_sub(header_size);
+        let mut config_obus = Vec::new();
+        reader.by_ref().take(len).read_to_end(&mut config_obus)?;
+        if config_obus.len() as u64 != len {
+            return Err(Error::InvalidData("av1c extends past its container"));
+        }
+
+        skip_bytes_to(reader, start + size)?;
+
+        Ok(Av1CBox {
+            version,
+            seq_profile,
+            seq_level_idx_0,
+            seq_tier_0,
+            high_bitdepth,
+            twelve_bit,
+            monochrome,
+            chroma_subsampling_x,
+            chroma_subsampling_y,
+            chroma_sample_position,
+            initial_presentation_delay_minus_one,
+            config_obus,
+        })
+    }
+}
+
+impl<W: Write> WriteBox<&mut W> for Av1CBox {
+    fn write_box(&self, writer: &mut W) -> Result<u64> {
+        let size = self.box_size();
+        BoxHeader::new(self.box_type(), size).write(writer)?;
+
+        writer.write_u8(0x80 | self.version)?;
+        writer.write_u8(self.seq_profile << 5 | self.seq_level_idx_0 & 0x1F)?;
+        writer.write_u8(
+            (self.seq_tier_0 as u8) << 7
+                | (self.high_bitdepth as u8) << 6
+                | (self.twelve_bit as u8) << 5
+                | (self.monochrome as u8) << 4
+                | (self.chroma_subsampling_x as u8) << 3
+                | (self.chroma_subsampling_y as u8) << 2
+                | self.chroma_sample_position & 0x3,
+        )?;
+        match self.initial_presentation_delay_minus_one {
+            Some(delay) => writer.write_u8(0x10 | delay & 0xF)?,
+            None => writer.write_u8(0)?,
+        }
+        writer.write_all(&self.config_obus)?;
+
+        Ok(size)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mp4box::BoxHeader;
+    use std::io::Cursor;
+
+    #[test]
+    fn test_av01() {
+        let src_box = Av01Box::new(&Av1Config {
+            width: 1920,
+            height: 1080,
+            seq_profile: 0,
+            seq_level_idx: 8,
+            bit_depth: 10,
+            // Sequence header OBU.
+            config_obus: vec![0x0A, 0x0B, 0x00, 0x00, 0x00, 0x42, 0xAB, 0xBF, 0xC3, 0x70, 0x0B, 0xE0, 0x80],
+            ..Av1Config::default()
+        });
+        let mut buf = Vec::new();
+        src_box.write_box(&mut buf).unwrap();
+        assert_eq!(buf.len(), src_box.box_size() as usize);
+
+        let mut reader = Cursor::new(&buf);
+        let header = BoxHeader::read(&mut reader).unwrap();
+        assert_eq!(header.name, BoxType::Av01Box);
+        assert_eq!(src_box.box_size(), header.size);
+
+        let dst_box = Av01Box::read_box(&mut reader, header.size).unwrap();
+        assert_eq!(src_box, dst_box);
+        assert_eq!(dst_box.av1c.bit_depth(), 10);
+        assert!(dst_box.av1c.chroma_subsampling_x && dst_box.av1c.chroma_subsampling_y);
+    }
+
+    #[test]
+    fn test_av1c() {
+        let src_box = Av1CBox {
+            version: 1,
+            seq_profile: 2,
+            seq_level_idx_0: 13,
+            seq_tier_0: true,
+            high_bitdepth: true,
+            twelve_bit: true,
+            monochrome: false,
+            chroma_subsampling_x: true,
+            chroma_subsampling_y: false,
+            chroma_sample_position: 0,
+            initial_presentation_delay_minus_one: Some(3),
+            config_obus: Vec::new(),
+        };
+        let mut buf = Vec::new();
+        src_box.write_box(&mut buf).unwrap();
+        assert_eq!(buf, [0, 0, 0, 12, b'a', b'v', b'1', b'C', 0x81, 0x4D, 0xE8, 0x13]);
+
+        let mut reader = Cursor::new(&buf);
+        let header = BoxHeader::read(&mut reader).unwrap();
+        assert_eq!(header.name, BoxType::Av1CBox);
+        assert_eq!(src_box.box_size(), header.size);
+
+        let dst_box = Av1CBox::read_box(&mut reader, header.size).unwrap();
+        assert_eq!(src_box, dst_box);
+        assert_eq!(dst_box.bit_depth(), 12);
+
+        // A size past the end of the input isn't allocated up front.
+        buf[..4].copy_from_slice(&u32::MAX.to_be_bytes());
+        let mut reader = Cursor::new(&buf);
+        let header = BoxHeader::read(&mut reader).unwrap();
+        assert!(Av1CBox::read_box(&mut reader, header.size).is_err());
+    }
+}
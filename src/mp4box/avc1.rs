@@ -3,6 +3,8 @@ use std::io::{Read, Seek, Write};
 use serde::{Serialize};
 
 use crate::mp4box::*;
+use crate::mp4box::btrt::BtrtBox;
+use crate::mp4box::raw::{child_order, Child, RawBox};
 
 #[derive(Debug, Clone, PartialEq, Serialize)]
 pub struct Avc1Box {
@@ -18,6 +20,17 @@ pub struct Avc1Box {
     pub frame_count: u16,
     pub depth: u16,
     pub avcc: AvcCBox,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub btrt: Option<BtrtBox>,
+
+    #[serde(skip)]
+    pub unknown_boxes: Vec<RawBox>,
+
+    /// Types of all children in the order they were read, so unknown boxes
+    /// are written back where they were. Empty when there are none.
+    #[serde(skip)]
+    pub child_order: Vec<BoxType>,
 }
 
 impl Default for Avc1Box {
@@ -31,6 +44,9 @@ impl Default for Avc1Box {
             frame_count: 1,
             depth: 0x0018,
             avcc: AvcCBox::default(),
+            btrt: None,
+            unknown_boxes: Vec::new(),
+            child_order: Vec::new(),
         }
     }
 }
@@ -45,7 +61,14 @@ impl Avc1Box {
             vertresolution: FixedPointU16::new(0x48),
             frame_count: 1,
             depth: 0x0018,
-            avcc: AvcCBox::new(&config.seq_param_set, &config.pic_param_set),
+            avcc: AvcCBox::new(
+                &config.seq_param_set,
+                &config.pic_param_set,
+                &config.seq_param_set_ext,
+            ),
+            btrt: None,
+            unknown_boxes: Vec::new(),
+            child_order: Vec::new(),
         }
     }
 
@@ -54,7 +77,14 @@ impl Avc1Box {
     }
 
     pub fn get_size(&self) -> u64 {
-        HEADER_SIZE + 8 + 70 + self.avcc.box_size()
+        let mut size = HEADER_SIZE + 8 + 70 + self.avcc.box_size();
+        if let Some(ref btrt) = self.btrt {
+            size += btrt.box_size();
+        }
+        for unknown in self.unknown_boxes.iter() {
+            size += unknown.box_size();
+        }
+        size
     }
 }
 
@@ -99,33 +129,69 @@ impl<R: Read + Seek> ReadBox<&mut R> for Avc1Box {
         let depth = reader.read_u16::<BigEndian>()?;
         reader.read_i16::<BigEndian>()?; // pre-defined
 
-        let header = BoxHeader::read(reader)?;
-        let BoxHeader { name, size: s } = header;
-        if name == BoxType::AvcCBox {
-            let avcc = AvcCBox::read_box(reader, s)?;
-
-            skip_bytes_to(reader, start + size)?;
-
-            Ok(Avc1Box {
-                data_reference_index,
-                width,
-                height,
-                horizresolution,
-                vertresolution,
-                frame_count,
-                depth,
-                avcc,
-            })
-        } else {
-            Err(Error::InvalidData("avcc not found"))
+        let mut avcc = None;
+        let mut btrt = None;
+        let mut unknown_boxes = Vec::new();
+        let mut child_order = Vec::new();
+
+        let mut current = reader.stream_position()?;
+        let end = start + size;
+        while current + HEADER_SIZE <= end {
+            let header = BoxHeader::read_in(reader, end)?;
+            let BoxHeader { name, .. } = header;
+            let s = header.read_size();
+            child_order.push(name);
+
+            match name {
+                BoxType::AvcCBox => {
+                    avcc = Some(AvcCBox::read_box(reader, s)?);
+                }
+                BoxType::BtrtBox => {
+                    btrt = Some(BtrtBox::read_box(reader, s)?);
+                }
+                _ => {
+                    unknown_boxes.push(RawBox::read(reader, &header)?);
+                }
+            }
+
+            current = reader.stream_position()?;
+        }
+        if unknown_boxes.is_empty() {
+            child_order.clear();
         }
+
+        let avcc = avcc.ok_or(Error::InvalidData("avcc not found"))?;
+
+        skip_bytes_to(reader, start + size)?;
+
+        Ok(Avc1Box {
+            data_reference_index,
+            width,
+            height,
+            horizresolution,
+            vertresolution,
+            frame_count,
+            depth,
+            avcc,
+            btrt,
+            unknown_boxes,
+            child_order,
+        })
     }
 }
 
 impl<W: Write> WriteBox<&mut W> for Avc1Box {
     fn write_box(&self, writer: &mut W) -> Result<u64> {
+        self.write_entry(writer, self.box_type())
+    }
+}
+
+impl Avc1Box {
+    /// Writes the sample entry as `box_type`, which is one of avc1, avc2,
+    /// avc3 and avc4; all share the same layout.
+    pub(crate) fn write_entry<W: Write>(&self, writer: &mut W, box_type: BoxType) -> Result<u64> {
         let size = self.box_size();
-        BoxHeader::new(self.box_type(), size).write(writer)?;
+        BoxHeader::new(box_type, size).write(writer)?;
 
         writer.write_u32::<BigEndian>(0)?; // reserved
         writer.write_u16::<BigEndian>(0)?; // reserved
@@ -145,7 +211,23 @@ impl<W: Write> WriteBox<&mut W> for Avc1Box {
         writer.write_u16::<BigEndian>(self.depth)?;
         writer.write_i16::<BigEndian>(-1)?; // pre-defined
 
-        self.avcc.write_box(writer)?;
+        let known = [BoxType::AvcCBox, BoxType::BtrtBox];
+        for child in child_order(&self.child_order, &known, self.unknown_boxes.len()) {
+            match child {
+                Child::Known(BoxType::AvcCBox) => {
+                    self.avcc.write_box(writer)?;
+                }
+                Child::Known(BoxType::BtrtBox) => {
+                    if let Some(ref btrt) = self.btrt {
+                        btrt.write_box(writer)?;
+                    }
+                }
+                Child::Known(_) => {}
+                Child::Unknown(i) => {
+                    self.unknown_boxes[i].write_box(writer)?;
+                }
+            }
+        }
 
         Ok(size)
     }
@@ -160,22 +242,147 @@ pub struct AvcCBox {
     pub length_size_minus_one: u8,
     pub sequence_parameter_sets: Vec<NalUnit>,
     pub picture_parameter_sets: Vec<NalUnit>,
+
+    /// Only present for the high profiles, see [AvcCBox::has_ext].
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub ext: Option<AvcCExt>,
+}
+
+#[derive(Debug, Clone, PartialEq, Default, Serialize)]
+pub struct AvcCExt {
+    pub chroma_format: u8,
+    pub bit_depth_luma_minus8: u8,
+    pub bit_depth_chroma_minus8: u8,
+    pub sequence_parameter_set_exts: Vec<NalUnit>,
 }
 
 impl AvcCBox {
-    pub fn new(sps: &[u8], pps: &[u8]) -> Self {
-        Self {
+    /// Creates the configuration for the given parameter sets. For the high
+    /// profiles, the chroma format and bit depths come from the SPS and
+    /// `sps_ext` is added if it's not empty.
+    pub fn new(sps: &[u8], pps: &[u8], sps_ext: &[u8]) -> Self {
+        let mut avcc = Self {
             configuration_version: 1,
             avc_profile_indication: sps[1],
             profile_compatibility: sps[2],
             avc_level_indication: sps[3],
-            length_size_minus_one: 0xff, // length_size = 4
+            length_size_minus_one: 3, // length_size = 4
             sequence_parameter_sets: vec![NalUnit::from(sps)],
             picture_parameter_sets: vec![NalUnit::from(pps)],
+            ext: None,
+        };
+        if avcc.has_ext() {
+            let (chroma_format, bit_depth_luma_minus8, bit_depth_chroma_minus8) =
+                sps_chroma_format(sps).unwrap_or((1, 0, 0));
+            let mut sequence_parameter_set_exts = Vec::new();
+            if !sps_ext.is_empty() {
+                sequence_parameter_set_exts.push(NalUnit::from(sps_ext));
+            }
+            avcc.ext = Some(AvcCExt {
+                chroma_format,
+                bit_depth_luma_minus8,
+                bit_depth_chroma_minus8,
+                sequence_parameter_set_exts,
+            });
+        }
+        avcc
+    }
+
+    /// Whether the profile may carry the chroma format, bit depth and SPS
+    /// extension fields after the PPS list.
+    pub fn has_ext(&self) -> bool {
+        matches!(self.avc_profile_indication, 100 | 110 | 122 | 244)
+    }
+}
+
+// Reads chroma_format_idc, bit_depth_luma_minus8 and bit_depth_chroma_minus8
+// from a high profile SPS.
+fn sps_chroma_format(sps: &[u8]) -> Option<(u8, u8, u8)> {
+    let bytes = rbsp(sps.get(4..)?, 16);
+    let mut reader = BitReader::new(&bytes);
+    reader.read_ue()?; // seq_parameter_set_id
+    let chroma_format_idc = reader.read_ue()?;
+    if chroma_format_idc == 3 {
+        reader.read_bit()?; // separate_colour_plane_flag
+    }
+    let bit_depth_luma_minus8 = reader.read_ue()?;
+    let bit_depth_chroma_minus8 = reader.read_ue()?;
+    Some((
+        chroma_format_idc as u8,
+        bit_depth_luma_minus8 as u8,
+        bit_depth_chroma_minus8 as u8,
+    ))
+}
+
+pub(crate) struct BitReader<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> BitReader<'a> {
+    pub(crate) fn new(bytes: &'a [u8]) -> Self {
+        Self { bytes, pos: 0 }
+    }
+
+    pub(crate) fn read_bit(&mut self) -> Option<u32> {
+        let byte = self.bytes.get(self.pos / 8)?;
+        let bit = (byte >> (7 - self.pos % 8)) & 1;
+        self.pos += 1;
+        Some(bit as u32)
+    }
+
+    // Reads `n` bits, at most 32.
+    pub(crate) fn read_bits(&mut self, n: u32) -> Option<u32> {
+        let mut value = 0;
+        for _ in 0..n {
+            value = value << 1 | self.read_bit()?;
+        }
+        Some(value)
+    }
+
+    pub(crate) fn skip_bits(&mut self, n: usize) -> Option<()> {
+        self.pos += n;
+        if self.pos > self.bytes.len() * 8 {
+            return None;
         }
+        Some(())
+    }
+
+    // Exp-Golomb coded unsigned integer.
+    pub(crate) fn read_ue(&mut self) -> Option<u32> {
+        let mut zeros = 0;
+        while self.read_bit()? == 0 {
+            zeros += 1;
+            if zeros > 31 {
+                return None;
+            }
+        }
+        let mut value = 1u64;
+        for _ in 0..zeros {
+            value = value << 1 | self.read_bit()? as u64;
+        }
+        Some((value - 1) as u32)
     }
 }
 
+// Strips emulation prevention bytes from the first `len` bytes of the payload.
+pub(crate) fn rbsp(nal: &[u8], len: usize) -> Vec<u8> {
+    let mut bytes = Vec::with_capacity(len);
+    let mut zeros = 0;
+    for &b in nal {
+        if bytes.len() == len {
+            break;
+        }
+        if zeros >= 2 && b == 0x03 {
+            zeros = 0;
+            continue;
+        }
+        zeros = if b == 0 { zeros + 1 } else { 0 };
+        bytes.push(b);
+    }
+    bytes
+}
+
 impl Mp4Box for AvcCBox {
     fn box_type(&self) -> BoxType {
         BoxType::AvcCBox
@@ -189,6 +396,12 @@ impl Mp4Box for AvcCBox {
         for pps in self.picture_parameter_sets.iter() {
             size += pps.size() as u64;
         }
+        if let Some(ref ext) = self.ext {
+            size += 4;
+            for sps_ext in ext.sequence_parameter_set_exts.iter() {
+                size += sps_ext.size() as u64;
+            }
+        }
         size
     }
 
@@ -225,6 +438,27 @@ impl<R: Read + Seek> ReadBox<&mut R> for AvcCBox {
             picture_parameter_sets.push(nal_unit);
         }
 
+        // Older files may omit the extension even for the high profiles.
+        let mut ext = None;
+        let current = reader.stream_position()?;
+        if matches!(avc_profile_indication, 100 | 110 | 122 | 244) && current + 4 <= start + size {
+            let chroma_format = reader.read_u8()? & 0x3;
+            let bit_depth_luma_minus8 = reader.read_u8()? & 0x7;
+            let bit_depth_chroma_minus8 = reader.read_u8()? & 0x7;
+            let num_of_sps_exts = reader.read_u8()?;
+            let mut sequence_parameter_set_exts = Vec::with_capacity(num_of_sps_exts as usize);
+            for _ in 0..num_of_sps_exts {
+                let nal_unit = NalUnit::read(reader)?;
+                sequence_parameter_set_exts.push(nal_unit);
+            }
+            ext = Some(AvcCExt {
+                chroma_format,
+                bit_depth_luma_minus8,
+                bit_depth_chroma_minus8,
+                sequence_parameter_set_exts,
+            });
+        }
+
         skip_bytes_to(reader, start + size)?;
 
         Ok(AvcCBox {
@@ -235,6 +469,7 @@ impl<R: Read + Seek> ReadBox<&mut R> for AvcCBox {
             length_size_minus_one,
             sequence_parameter_sets,
             picture_parameter_sets,
+            ext,
         })
     }
 }
@@ -257,6 +492,15 @@ impl<W: Write> WriteBox<&mut W> for AvcCBox {
         for pps in self.picture_parameter_sets.iter() {
             pps.write(writer)?;
         }
+        if let Some(ref ext) = self.ext {
+            writer.write_u8(ext.chroma_format | 0xFC)?;
+            writer.write_u8(ext.bit_depth_luma_minus8 | 0xF8)?;
+            writer.write_u8(ext.bit_depth_chroma_minus8 | 0xF8)?;
+            writer.write_u8(ext.sequence_parameter_set_exts.len() as u8)?;
+            for sps_ext in ext.sequence_parameter_set_exts.iter() {
+                sps_ext.write(writer)?;
+            }
+        }
         Ok(size)
     }
 }
@@ -275,18 +519,18 @@ impl From<&[u8]> for NalUnit {
 }
 
 impl NalUnit {
-    fn size(&self) -> usize {
+    pub(crate) fn size(&self) -> usize {
         2 + self.bytes.len()
     }
 
-    fn read<R: Read + Seek>(reader: &mut R) -> Result<Self> {
+    pub(crate) fn read<R: Read + Seek>(reader: &mut R) -> Result<Self> {
         let length = reader.read_u16::<BigEndian>()? as usize;
         let mut bytes = vec![0u8; length];
         reader.read(&mut bytes)?;
         Ok(NalUnit { bytes })
     }
 
-    fn write<W: Write>(&self, writer: &mut W) -> Result<u64> {
+    pub(crate) fn write<W: Write>(&self, writer: &mut W) -> Result<u64> {
         writer.write_u16::<BigEndian>(self.bytes.len() as u16)?;
         writer.write(&self.bytes)?;
         Ok(self.size() as u64)
@@ -324,7 +568,25 @@ mod tests {
                 picture_parameter_sets: vec![NalUnit {
                     bytes: vec![0x68, 0xEB, 0xE3, 0xCB, 0x22, 0xC0],
                 }],
+                ext: Some(AvcCExt {
+                    chroma_format: 1,
+                    bit_depth_luma_minus8: 0,
+                    bit_depth_chroma_minus8: 0,
+                    sequence_parameter_set_exts: vec![NalUnit {
+                        bytes: vec![0x6D, 0x00],
+                    }],
+                }),
             },
+            btrt: None,
+            unknown_boxes: vec![RawBox {
+                name: BoxType::UnknownBox(u32::from_be_bytes(*b"pasp")),
+                usertype: None,
+                data: vec![0, 0, 0, 1, 0, 0, 0, 1],
+            }],
+            child_order: vec![
+                BoxType::AvcCBox,
+                BoxType::UnknownBox(u32::from_be_bytes(*b"pasp")),
+            ],
         };
         let mut buf = Vec::new();
         src_box.write_box(&mut buf).unwrap();
@@ -338,4 +600,37 @@ mod tests {
         let dst_box = Avc1Box::read_box(&mut reader, header.size).unwrap();
         assert_eq!(src_box, dst_box);
     }
+
+    #[test]
+    fn test_avcc_ext() {
+        // High 4:2:2 profile, 10-bit.
+        let sps = [0x67, 0x7A, 0x00, 0x1F, 0xB6, 0xC0];
+        let avcc = AvcCBox::new(&sps, &[0x68, 0xEB], &[0x6D, 0x00]);
+        let ext = avcc.ext.as_ref().unwrap();
+        assert_eq!(ext.chroma_format, 2);
+        assert_eq!(ext.bit_depth_luma_minus8, 2);
+        assert_eq!(ext.bit_depth_chroma_minus8, 2);
+        assert_eq!(ext.sequence_parameter_set_exts, vec![NalUnit::from(&[0x6D, 0x00][..])]);
+
+        let mut buf = Vec::new();
+        avcc.write_box(&mut buf).unwrap();
+        assert_eq!(buf.len(), avcc.box_size() as usize);
+
+        let mut reader = Cursor::new(&buf);
+        let header = BoxHeader::read(&mut reader).unwrap();
+        let dst_box = AvcCBox::read_box(&mut reader, header.size).unwrap();
+        assert_eq!(avcc, dst_box);
+
+        // Main profile has no extension, and a high profile avcC without one
+        // is still read.
+        assert!(AvcCBox::new(&[0x67, 0x4D, 0x00, 0x1F], &[0x68], &[]).ext.is_none());
+        let mut avcc = AvcCBox::new(&sps, &[0x68, 0xEB], &[]);
+        avcc.ext = None;
+        let mut buf = Vec::new();
+        avcc.write_box(&mut buf).unwrap();
+        let mut reader = Cursor::new(&buf);
+        let header = BoxHeader::read(&mut reader).unwrap();
+        let dst_box = AvcCBox::read_box(&mut reader, header.size).unwrap();
+        assert_eq!(avcc, dst_box);
+    }
 }
@@ -0,0 +1,100 @@
+use byteorder::{BigEndian, ReadBytesExt, WriteBytesExt};
+use std::io::{Read, Seek, Write};
+use serde::{Serialize};
+
+use crate::mp4box::*;
+
+#[derive(Debug, Clone, PartialEq, Default, Serialize)]
+pub struct BtrtBox {
+    pub buffer_size_db: u32,
+    pub max_bitrate: u32,
+    pub avg_bitrate: u32,
+}
+
+impl BtrtBox {
+    pub fn get_type(&self) -> BoxType {
+        BoxType::BtrtBox
+    }
+
+    pub fn get_size(&self) -> u64 {
+        HEADER_SIZE + 12
+    }
+}
+
+impl Mp4Box for BtrtBox {
+    fn box_type(&self) -> BoxType {
+        self.get_type()
+    }
+
+    fn box_size(&self) -> u64 {
+        self.get_size()
+    }
+
+    fn to_json(&self) -> Result<String> {
+        Ok(serde_json::to_string(&self).unwrap())
+    }
+
+    fn summary(&self) -> Result<String> {
+        let s = format!("buffer_size_db={} max_bitrate={} avg_bitrate={}",
+            self.buffer_size_db, self.max_bitrate, self.avg_bitrate);
+        Ok(s)
+    }
+}
+
+impl<R: Read + Seek> ReadBox<&mut R> for BtrtBox {
+    fn read_box(reader: &mut R, size: u64) -> Result<Self> {
+        let start = box_start(reader)?;
+
+        let buffer_size_db = reader.read_u32::<BigEndian>()?;
+        let max_bitrate = reader.read_u32::<BigEndian>()?;
+        let avg_bitrate = reader.read_u32::<BigEndian>()?;
+
+        skip_bytes_to(reader, start + size)?;
+
+        Ok(BtrtBox {
+            buffer_size_db,
+            max_bitrate,
+            avg_bitrate,
+        })
+    }
+}
+
+impl<W: Write> WriteBox<&mut W> for BtrtBox {
+    fn write_box(&self, writer: &mut W) -> Result<u64> {
+        let size = self.box_size();
+        BoxHeader::new(self.box_type(), size).write(writer)?;
+
+        writer.write_u32::<BigEndian>(self.buffer_size_db)?;
+        writer.write_u32::<BigEndian>(self.max_bitrate)?;
+        writer.write_u32::<BigEndian>(self.avg_bitrate)?;
+
+        Ok(size)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mp4box::BoxHeader;
+    use std::io::Cursor;
+
+    #[test]
+    fn test_btrt() {
+        let src_box = BtrtBox {
+            buffer_size_db: 6056,
+            max_bitrate: 2_500_000,
+            avg_bitrate: 1_800_000,
+        };
+        let mut buf = Vec::new();
+        src_box.write_box(&mut buf).unwrap();
+        assert_eq!(buf.len(), src_box.box_size() as usize);
+
+        let mut reader = Cursor::new(&buf);
+        let header = BoxHeader::read(&mut reader).unwrap();
+        assert_eq!(header.name, BoxType::BtrtBox);
+        assert_eq!(src_box.box_size(), header.size);
+
+        let dst_box = BtrtBox::read_box(&mut reader, header.size).unwrap();
+        assert_eq!(src_box, dst_box);
+    }
+}
@@ -3,6 +3,7 @@ use std::io::{Read, Seek, Write};
 use serde::{Serialize};
 
 use crate::mp4box::*;
+use crate::mp4box::stco::StcoBox;
 
 #[derive(Debug, Clone, PartialEq, Default, Serialize)]
 pub struct Co64Box {
@@ -23,6 +24,16 @@ impl Co64Box {
     }
 }
 
+impl From<&StcoBox> for Co64Box {
+    fn from(stco: &StcoBox) -> Self {
+        Self {
+            version: 0,
+            flags: 0,
+            entries: stco.entries.iter().map(|offset| *offset as u64).collect(),
+        }
+    }
+}
+
 impl Mp4Box for Co64Box {
     fn box_type(&self) -> BoxType {
         return self.get_type();
@@ -2,10 +2,14 @@ use std::io::{Read, Seek, Write};
 use serde::{Serialize};
 
 use crate::mp4box::*;
+use crate::mp4box::raw::RawBox;
 
 #[derive(Debug, Clone, PartialEq, Default, Serialize)]
 pub struct DinfBox {
     dref: DrefBox,
+
+    #[serde(skip)]
+    pub unknown_boxes: Vec<RawBox>,
 }
 
 impl DinfBox {
@@ -14,7 +18,11 @@ impl DinfBox {
     }
 
     pub fn get_size(&self) -> u64 {
-        HEADER_SIZE + self.dref.box_size()
+        let mut size = HEADER_SIZE + self.dref.box_size();
+        for unknown in self.unknown_boxes.iter() {
+            size += unknown.box_size();
+        }
+        size
     }
 }
 
@@ -42,21 +50,22 @@ impl<R: Read + Seek> ReadBox<&mut R> for DinfBox {
         let start = box_start(reader)?;
 
         let mut dref = None;
+        let mut unknown_boxes = Vec::new();
 
         let mut current = reader.seek(SeekFrom::Current(0))?;
         let end = start + size;
         while current < end {
             // Get box header.
-            let header = BoxHeader::read(reader)?;
-            let BoxHeader { name, size: s } = header;
+            let header = BoxHeader::read_in(reader, end)?;
+            let BoxHeader { name, .. } = header;
+            let s = header.read_size();
 
             match name {
                 BoxType::DrefBox => {
                     dref = Some(DrefBox::read_box(reader, s)?);
                 }
                 _ => {
-                    // XXX warn!()
-                    skip_box(reader, s)?;
+                    unknown_boxes.push(RawBox::read(reader, &header)?);
                 }
             }
 
@@ -71,6 +80,7 @@ impl<R: Read + Seek> ReadBox<&mut R> for DinfBox {
 
         Ok(DinfBox {
             dref: dref.unwrap(),
+            unknown_boxes,
         })
     }
 }
@@ -80,6 +90,9 @@ impl<W: Write> WriteBox<&mut W> for DinfBox {
         let size = self.box_size();
         BoxHeader::new(self.box_type(), size).write(writer)?;
         self.dref.write_box(writer)?;
+        for unknown in self.unknown_boxes.iter() {
+            unknown.write_box(writer)?;
+        }
         Ok(size)
     }
 }
@@ -154,8 +167,9 @@ impl<R: Read + Seek> ReadBox<&mut R> for DrefBox {
             }
 
             // Get box header.
-            let header = BoxHeader::read(reader)?;
-            let BoxHeader { name, size: s } = header;
+            let header = BoxHeader::read_in(reader, end)?;
+            let BoxHeader { name, .. } = header;
+            let s = header.read_size();
 
             match name {
                 BoxType::UrlBox => {
@@ -52,8 +52,9 @@ impl<R: Read + Seek> ReadBox<&mut R> for EdtsBox {
 
         let mut edts = EdtsBox::new();
 
-        let header = BoxHeader::read(reader)?;
-        let BoxHeader { name, size: s } = header;
+        let header = BoxHeader::read_in(reader, start + size)?;
+        let BoxHeader { name, .. } = header;
+        let s = header.read_size();
 
         match name {
             BoxType::ElstBox => {
@@ -16,11 +16,18 @@ pub struct ElstBox {
 #[derive(Debug, Clone, PartialEq, Default, Serialize)]
 pub struct ElstEntry {
     pub segment_duration: u64,
-    pub media_time: u64,
+    pub media_time: i64,
     pub media_rate: u16,
     pub media_rate_fraction: u16,
 }
 
+impl ElstEntry {
+    /// An empty edit (media_time of -1) inserts a gap in the presentation.
+    pub fn is_empty_edit(&self) -> bool {
+        self.media_time == -1
+    }
+}
+
 impl ElstBox {
     pub fn get_type(&self) -> BoxType {
         BoxType::ElstBox
@@ -68,12 +75,12 @@ impl<R: Read + Seek> ReadBox<&mut R> for ElstBox {
             let (segment_duration, media_time) = if version == 1 {
                 (
                     reader.read_u64::<BigEndian>()?,
-                    reader.read_u64::<BigEndian>()?,
+                    reader.read_i64::<BigEndian>()?,
                 )
             } else {
                 (
                     reader.read_u32::<BigEndian>()? as u64,
-                    reader.read_u32::<BigEndian>()? as u64,
+                    reader.read_i32::<BigEndian>()? as i64,
                 )
             };
 
@@ -107,10 +114,10 @@ impl<W: Write> WriteBox<&mut W> for ElstBox {
         for entry in self.entries.iter() {
             if self.version == 1 {
                 writer.write_u64::<BigEndian>(entry.segment_duration)?;
-                writer.write_u64::<BigEndian>(entry.media_time)?;
+                writer.write_i64::<BigEndian>(entry.media_time)?;
             } else {
                 writer.write_u32::<BigEndian>(entry.segment_duration as u32)?;
-                writer.write_u32::<BigEndian>(entry.media_time as u32)?;
+                writer.write_i32::<BigEndian>(entry.media_time as i32)?;
             }
             writer.write_u16::<BigEndian>(entry.media_rate)?;
             writer.write_u16::<BigEndian>(entry.media_rate_fraction)?;
@@ -175,4 +182,38 @@ mod tests {
         let dst_box = ElstBox::read_box(&mut reader, header.size).unwrap();
         assert_eq!(src_box, dst_box);
     }
+
+    #[test]
+    fn test_elst_empty_edit() {
+        let src_box = ElstBox {
+            version: 0,
+            flags: 0,
+            entries: vec![
+                ElstEntry {
+                    segment_duration: 40,
+                    media_time: -1,
+                    media_rate: 1,
+                    media_rate_fraction: 0,
+                },
+                ElstEntry {
+                    segment_duration: 634634,
+                    media_time: 1024,
+                    media_rate: 1,
+                    media_rate_fraction: 0,
+                },
+            ],
+        };
+        let mut buf = Vec::new();
+        src_box.write_box(&mut buf).unwrap();
+        assert_eq!(buf.len(), src_box.box_size() as usize);
+
+        let mut reader = Cursor::new(&buf);
+        let header = BoxHeader::read(&mut reader).unwrap();
+        assert_eq!(header.name, BoxType::ElstBox);
+        assert_eq!(src_box.box_size(), header.size);
+
+        let dst_box = ElstBox::read_box(&mut reader, header.size).unwrap();
+        assert_eq!(src_box, dst_box);
+        assert!(dst_box.entries[0].is_empty_edit());
+    }
 }
@@ -3,6 +3,9 @@ use std::io::{Read, Seek, Write};
 use serde::{Serialize};
 
 use crate::mp4box::*;
+use crate::mp4box::avc1::{rbsp, BitReader, NalUnit};
+use crate::mp4box::btrt::BtrtBox;
+use crate::mp4box::raw::{child_order, Child, RawBox};
 
 #[derive(Debug, Clone, PartialEq, Serialize)]
 pub struct Hev1Box {
@@ -18,6 +21,17 @@ pub struct Hev1Box {
     pub frame_count: u16,
     pub depth: u16,
     pub hvcc: HvcCBox,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub btrt: Option<BtrtBox>,
+
+    #[serde(skip)]
+    pub unknown_boxes: Vec<RawBox>,
+
+    /// Types of all children in the order they were read, so unknown boxes
+    /// are written back where they were. Empty when there are none.
+    #[serde(skip)]
+    pub child_order: Vec<BoxType>,
 }
 
 impl Default for Hev1Box {
@@ -31,6 +45,9 @@ impl Default for Hev1Box {
             frame_count: 1,
             depth: 0x0018,
             hvcc: HvcCBox::default(),
+            btrt: None,
+            unknown_boxes: Vec::new(),
+            child_order: Vec::new(),
         }
     }
 }
@@ -45,7 +62,14 @@ impl Hev1Box {
             vertresolution: FixedPointU16::new(0x48),
             frame_count: 1,
             depth: 0x0018,
-            hvcc: HvcCBox::new(),
+            hvcc: HvcCBox::new(
+                &config.video_param_set,
+                &config.seq_param_set,
+                &config.pic_param_set,
+            ),
+            btrt: None,
+            unknown_boxes: Vec::new(),
+            child_order: Vec::new(),
         }
     }
 
@@ -54,7 +78,14 @@ impl Hev1Box {
     }
 
     pub fn get_size(&self) -> u64 {
-        HEADER_SIZE + 8 + 70 + self.hvcc.box_size()
+        let mut size = HEADER_SIZE + 8 + 70 + self.hvcc.box_size();
+        if let Some(ref btrt) = self.btrt {
+            size += btrt.box_size();
+        }
+        for unknown in self.unknown_boxes.iter() {
+            size += unknown.box_size();
+        }
+        size
     }
 }
 
@@ -99,33 +130,69 @@ impl<R: Read + Seek> ReadBox<&mut R> for Hev1Box {
         let depth = reader.read_u16::<BigEndian>()?;
         reader.read_i16::<BigEndian>()?; // pre-defined
 
-        let header = BoxHeader::read(reader)?;
-        let BoxHeader { name, size: s } = header;
-        if name == BoxType::HvcCBox {
-            let hvcc = HvcCBox::read_box(reader, s)?;
+        let mut hvcc = None;
+        let mut btrt = None;
+        let mut unknown_boxes = Vec::new();
+        let mut child_order = Vec::new();
+
+        let mut current = reader.stream_position()?;
+        let end = start + size;
+        while current + HEADER_SIZE <= end {
+            let header = BoxHeader::read_in(reader, end)?;
+            let BoxHeader { name, .. } = header;
+            let s = header.read_size();
+            child_order.push(name);
+
+            match name {
+                BoxType::HvcCBox => {
+                    hvcc = Some(HvcCBox::read_box(reader, s)?);
+                }
+                BoxType::BtrtBox => {
+                    btrt = Some(BtrtBox::read_box(reader, s)?);
+                }
+                _ => {
+                    unknown_boxes.push(RawBox::read(reader, &header)?);
+                }
+            }
+
+            current = reader.stream_position()?;
+        }
+        if unknown_boxes.is_empty() {
+            child_order.clear();
+        }
+
+        let hvcc = hvcc.ok_or(Error::InvalidData("hvcc not found"))?;
 
-            skip_bytes_to(reader, start + size)?;
+        skip_bytes_to(reader, start + size)?;
 
-            Ok(Hev1Box {
-                data_reference_index,
-                width,
-                height,
-                horizresolution,
-                vertresolution,
-                frame_count,
-                depth,
-                hvcc,
-            })
-        } else {
-            Err(Error::InvalidData("hvcc not found"))
-        }
+        Ok(Hev1Box {
+            data_reference_index,
+            width,
+            height,
+            horizresolution,
+            vertresolution,
+            frame_count,
+            depth,
+            hvcc,
+            btrt,
+            unknown_boxes,
+            child_order,
+        })
     }
 }
 
 impl<W: Write> WriteBox<&mut W> for Hev1Box {
     fn write_box(&self, writer: &mut W) -> Result<u64> {
+        self.write_entry(writer, self.box_type())
+    }
+}
+
+impl Hev1Box {
+    /// Writes the sample entry as `box_type`, which is either hev1 or hvc1;
+    /// both share the same layout.
+    pub(crate) fn write_entry<W: Write>(&self, writer: &mut W, box_type: BoxType) -> Result<u64> {
         let size = self.box_size();
-        BoxHeader::new(self.box_type(), size).write(writer)?;
+        BoxHeader::new(box_type, size).write(writer)?;
 
         writer.write_u32::<BigEndian>(0)?; // reserved
         writer.write_u16::<BigEndian>(0)?; // reserved
@@ -145,7 +212,23 @@ impl<W: Write> WriteBox<&mut W> for Hev1Box {
         writer.write_u16::<BigEndian>(self.depth)?;
         writer.write_i16::<BigEndian>(-1)?; // pre-defined
 
-        self.hvcc.write_box(writer)?;
+        let known = [BoxType::HvcCBox, BoxType::BtrtBox];
+        for child in child_order(&self.child_order, &known, self.unknown_boxes.len()) {
+            match child {
+                Child::Known(BoxType::HvcCBox) => {
+                    self.hvcc.write_box(writer)?;
+                }
+                Child::Known(BoxType::BtrtBox) => {
+                    if let Some(ref btrt) = self.btrt {
+                        btrt.write_box(writer)?;
+                    }
+                }
+                Child::Known(_) => {}
+                Child::Unknown(i) => {
+                    self.unknown_boxes[i].write_box(writer)?;
+                }
+            }
+        }
 
         Ok(size)
     }
@@ -154,23 +237,147 @@ impl<W: Write> WriteBox<&mut W> for Hev1Box {
 #[derive(Debug, Clone, PartialEq, Default, Serialize)]
 pub struct HvcCBox {
     pub configuration_version: u8,
+    pub general_profile_space: u8,
+    pub general_tier_flag: bool,
+    pub general_profile_idc: u8,
+    pub general_profile_compatibility_flags: u32,
+    pub general_constraint_indicator_flag: u64,
+    pub general_level_idc: u8,
+    pub min_spatial_segmentation_idc: u16,
+    pub parallelism_type: u8,
+    pub chroma_format_idc: u8,
+    pub bit_depth_luma_minus8: u8,
+    pub bit_depth_chroma_minus8: u8,
+    pub avg_frame_rate: u16,
+    pub constant_frame_rate: u8,
+    pub num_temporal_layers: u8,
+    pub temporal_id_nested: bool,
+    pub length_size_minus_one: u8,
+    pub arrays: Vec<HvcCArray>,
 }
 
+pub const HEVC_NAL_VPS: u8 = 32;
+pub const HEVC_NAL_SPS: u8 = 33;
+pub const HEVC_NAL_PPS: u8 = 34;
+
 impl HvcCBox {
-    pub fn new() -> Self {
-        Self {
+    /// Creates the configuration for the given parameter sets, leaving out
+    /// empty ones. The profile, tier, level, chroma format and bit depths
+    /// come from the SPS; 4:2:0 8-bit video is assumed if it can't be parsed.
+    pub fn new(vps: &[u8], sps: &[u8], pps: &[u8]) -> Self {
+        let arrays = [(HEVC_NAL_VPS, vps), (HEVC_NAL_SPS, sps), (HEVC_NAL_PPS, pps)]
+            .iter()
+            .filter(|(_, nalu)| !nalu.is_empty())
+            .map(|&(nal_unit_type, nalu)| HvcCArray::new(nal_unit_type, nalu))
+            .collect();
+        let mut hvcc = Self {
             configuration_version: 1,
+            chroma_format_idc: 1,
+            num_temporal_layers: 1,
+            length_size_minus_one: 3, // length_size = 4
+            arrays,
+            ..Default::default()
+        };
+
+        if let Some((chroma_format_idc, bit_depth_luma_minus8, bit_depth_chroma_minus8)) =
+            sps_chroma_format(sps)
+        {
+            hvcc.chroma_format_idc = chroma_format_idc;
+            hvcc.bit_depth_luma_minus8 = bit_depth_luma_minus8;
+            hvcc.bit_depth_chroma_minus8 = bit_depth_chroma_minus8;
+        }
+
+        // sps_video_parameter_set_id, sps_max_sub_layers_minus1,
+        // sps_temporal_id_nesting_flag and the general profile_tier_level.
+        let ptl = rbsp(sps.get(2..).unwrap_or_default(), 13);
+        if ptl.len() == 13 {
+            hvcc.num_temporal_layers = ((ptl[0] >> 1) & 0x7) + 1;
+            hvcc.temporal_id_nested = ptl[0] & 0x1 == 1;
+            hvcc.general_profile_space = ptl[1] >> 6;
+            hvcc.general_tier_flag = (ptl[1] >> 5) & 0x1 == 1;
+            hvcc.general_profile_idc = ptl[1] & 0x1F;
+            hvcc.general_profile_compatibility_flags =
+                u32::from_be_bytes([ptl[2], ptl[3], ptl[4], ptl[5]]);
+            hvcc.general_constraint_indicator_flag = ptl[6..12]
+                .iter()
+                .fold(0, |flags, &b| flags << 8 | b as u64);
+            hvcc.general_level_idc = ptl[12];
         }
+        hvcc
+    }
+
+    /// Returns the first NAL unit of the given type, e.g. [HEVC_NAL_SPS].
+    pub fn nal_unit(&self, nal_unit_type: u8) -> Option<&NalUnit> {
+        self.arrays
+            .iter()
+            .filter(|array| array.nal_unit_type == nal_unit_type)
+            .flat_map(|array| array.nalus.iter())
+            .next()
     }
 }
 
+// Reads chroma_format_idc, bit_depth_luma_minus8 and bit_depth_chroma_minus8
+// from an SPS.
+fn sps_chroma_format(sps: &[u8]) -> Option<(u8, u8, u8)> {
+    let bytes = rbsp(sps.get(2..)?, sps.len());
+    let mut reader = BitReader::new(&bytes);
+    reader.read_bits(4)?; // sps_video_parameter_set_id
+    let max_sub_layers_minus1 = reader.read_bits(3)?;
+    reader.read_bit()?; // sps_temporal_id_nesting_flag
+
+    // profile_tier_level: the general profile and level, then the optional
+    // ones of each sub-layer.
+    reader.skip_bits(96)?;
+    let mut sub_layers = Vec::new();
+    for _ in 0..max_sub_layers_minus1 {
+        let profile_present = reader.read_bit()? == 1;
+        let level_present = reader.read_bit()? == 1;
+        sub_layers.push((profile_present, level_present));
+    }
+    if max_sub_layers_minus1 > 0 {
+        reader.skip_bits(2 * (8 - max_sub_layers_minus1 as usize))?;
+    }
+    for (profile_present, level_present) in sub_layers {
+        if profile_present {
+            reader.skip_bits(88)?;
+        }
+        if level_present {
+            reader.skip_bits(8)?;
+        }
+    }
+
+    reader.read_ue()?; // sps_seq_parameter_set_id
+    let chroma_format_idc = reader.read_ue()?;
+    if chroma_format_idc == 3 {
+        reader.read_bit()?; // separate_colour_plane_flag
+    }
+    reader.read_ue()?; // pic_width_in_luma_samples
+    reader.read_ue()?; // pic_height_in_luma_samples
+    if reader.read_bit()? == 1 {
+        // conformance window offsets
+        for _ in 0..4 {
+            reader.read_ue()?;
+        }
+    }
+    let bit_depth_luma_minus8 = reader.read_ue()?;
+    let bit_depth_chroma_minus8 = reader.read_ue()?;
+    Some((
+        chroma_format_idc as u8,
+        bit_depth_luma_minus8 as u8,
+        bit_depth_chroma_minus8 as u8,
+    ))
+}
+
 impl Mp4Box for HvcCBox {
     fn box_type(&self) -> BoxType {
         BoxType::HvcCBox
     }
 
     fn box_size(&self) -> u64 {
-        let size = HEADER_SIZE + 1;
+        let mut size = HEADER_SIZE + 23;
+        for array in self.arrays.iter() {
+            size += array.size() as u64;
+        }
         size
     }
 
@@ -179,8 +386,9 @@ impl Mp4Box for HvcCBox {
     }
 
     fn summary(&self) -> Result<String> {
-        let s = format!("configuration_version={}",
-            self.configuration_version);
+        let s = format!("configuration_version={} general_profile_idc={} general_level_idc={} arrays={}",
+            self.configuration_version, self.general_profile_idc, self.general_level_idc,
+            self.arrays.len());
         Ok(s)
     }
 }
@@ -190,11 +398,52 @@ impl<R: Read + Seek> ReadBox<&mut R> for HvcCBox {
         let start = box_start(reader)?;
 
         let configuration_version = reader.read_u8()?;
+        let params = reader.read_u8()?;
+        let general_profile_space = params >> 6;
+        let general_tier_flag = (params >> 5) & 0x1 == 1;
+        let general_profile_idc = params & 0x1F;
+        let general_profile_compatibility_flags = reader.read_u32::<BigEndian>()?;
+        let general_constraint_indicator_flag = reader.read_u48::<BigEndian>()?;
+        let general_level_idc = reader.read_u8()?;
+        let min_spatial_segmentation_idc = reader.read_u16::<BigEndian>()? & 0x0FFF;
+        let parallelism_type = reader.read_u8()? & 0x3;
+        let chroma_format_idc = reader.read_u8()? & 0x3;
+        let bit_depth_luma_minus8 = reader.read_u8()? & 0x7;
+        let bit_depth_chroma_minus8 = reader.read_u8()? & 0x7;
+        let avg_frame_rate = reader.read_u16::<BigEndian>()?;
+        let params = reader.read_u8()?;
+        let constant_frame_rate = params >> 6;
+        let num_temporal_layers = (params >> 3) & 0x7;
+        let temporal_id_nested = (params >> 2) & 0x1 == 1;
+        let length_size_minus_one = params & 0x3;
+
+        let num_of_arrays = reader.read_u8()?;
+        let mut arrays = Vec::with_capacity(num_of_arrays as usize);
+        for _ in 0..num_of_arrays {
+            arrays.push(HvcCArray::read(reader)?);
+        }
 
         skip_bytes_to(reader, start + size)?;
 
         Ok(HvcCBox {
             configuration_version,
+            general_profile_space,
+            general_tier_flag,
+            general_profile_idc,
+            general_profile_compatibility_flags,
+            general_constraint_indicator_flag,
+            general_level_idc,
+            min_spatial_segmentation_idc,
+            parallelism_type,
+            chroma_format_idc,
+            bit_depth_luma_minus8,
+            bit_depth_chroma_minus8,
+            avg_frame_rate,
+            constant_frame_rate,
+            num_temporal_layers,
+            temporal_id_nested,
+            length_size_minus_one,
+            arrays,
         })
     }
 }
@@ -205,10 +454,78 @@ impl<W: Write> WriteBox<&mut W> for HvcCBox {
         BoxHeader::new(self.box_type(), size).write(writer)?;
 
         writer.write_u8(self.configuration_version)?;
+        writer.write_u8(
+            self.general_profile_space << 6
+                | (self.general_tier_flag as u8) << 5
+                | self.general_profile_idc,
+        )?;
+        writer.write_u32::<BigEndian>(self.general_profile_compatibility_flags)?;
+        writer.write_u48::<BigEndian>(self.general_constraint_indicator_flag)?;
+        writer.write_u8(self.general_level_idc)?;
+        writer.write_u16::<BigEndian>(self.min_spatial_segmentation_idc | 0xF000)?;
+        writer.write_u8(self.parallelism_type | 0xFC)?;
+        writer.write_u8(self.chroma_format_idc | 0xFC)?;
+        writer.write_u8(self.bit_depth_luma_minus8 | 0xF8)?;
+        writer.write_u8(self.bit_depth_chroma_minus8 | 0xF8)?;
+        writer.write_u16::<BigEndian>(self.avg_frame_rate)?;
+        writer.write_u8(
+            self.constant_frame_rate << 6
+                | self.num_temporal_layers << 3
+                | (self.temporal_id_nested as u8) << 2
+                | self.length_size_minus_one,
+        )?;
+        writer.write_u8(self.arrays.len() as u8)?;
+        for array in self.arrays.iter() {
+            array.write(writer)?;
+        }
         Ok(size)
     }
 }
 
+#[derive(Debug, Clone, PartialEq, Default, Serialize)]
+pub struct HvcCArray {
+    pub completeness: bool,
+    pub nal_unit_type: u8,
+    pub nalus: Vec<NalUnit>,
+}
+
+impl HvcCArray {
+    pub fn new(nal_unit_type: u8, nal_unit: &[u8]) -> Self {
+        Self {
+            completeness: true,
+            nal_unit_type,
+            nalus: vec![NalUnit::from(nal_unit)],
+        }
+    }
+
+    fn size(&self) -> usize {
+        3 + self.nalus.iter().map(|nalu| nalu.size()).sum::<usize>()
+    }
+
+    fn read<R: Read + Seek>(reader: &mut R) -> Result<Self> {
+        let params = reader.read_u8()?;
+        let num_nalus = reader.read_u16::<BigEndian>()?;
+        let mut nalus = Vec::with_capacity(num_nalus as usize);
+        for _ in 0..num_nalus {
+            nalus.push(NalUnit::read(reader)?);
+        }
+        Ok(HvcCArray {
+            completeness: params >> 7 == 1,
+            nal_unit_type: params & 0x3F,
+            nalus,
+        })
+    }
+
+    fn write<W: Write>(&self, writer: &mut W) -> Result<u64> {
+        writer.write_u8((self.completeness as u8) << 7 | self.nal_unit_type & 0x3F)?;
+        writer.write_u16::<BigEndian>(self.nalus.len() as u16)?;
+        for nalu in self.nalus.iter() {
+            nalu.write(writer)?;
+        }
+        Ok(self.size() as u64)
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -227,7 +544,29 @@ mod tests {
             depth: 24,
             hvcc: HvcCBox {
                 configuration_version: 1,
+                general_profile_idc: 1,
+                general_profile_compatibility_flags: 0x60000000,
+                general_constraint_indicator_flag: 0x900000000000,
+                general_level_idc: 93,
+                min_spatial_segmentation_idc: 0,
+                chroma_format_idc: 1,
+                num_temporal_layers: 1,
+                temporal_id_nested: true,
+                length_size_minus_one: 3,
+                arrays: vec![
+                    HvcCArray::new(HEVC_NAL_VPS, &[0x40, 0x01, 0x0C]),
+                    HvcCArray::new(HEVC_NAL_SPS, &[0x42, 0x01, 0x01]),
+                    HvcCArray {
+                        completeness: false,
+                        nal_unit_type: HEVC_NAL_PPS,
+                        nalus: vec![NalUnit::from(&[0x44, 0x01][..]), NalUnit::from(&[0x44][..])],
+                    },
+                ],
+                ..Default::default()
             },
+            btrt: None,
+            unknown_boxes: Vec::new(),
+            child_order: Vec::new(),
         };
         let mut buf = Vec::new();
         src_box.write_box(&mut buf).unwrap();
@@ -241,4 +580,50 @@ mod tests {
         let dst_box = Hev1Box::read_box(&mut reader, header.size).unwrap();
         assert_eq!(src_box, dst_box);
     }
+
+    #[test]
+    fn test_hvcc_new() {
+        // 1080p Main profile, level 4 SPS with an emulation prevention byte
+        // in the constraint flags.
+        let sps = [
+            0x42, 0x01, 0x01, 0x01, 0x60, 0x00, 0x00, 0x03, 0x00, 0x90, 0x00, 0x00, 0x03, 0x00,
+            0x00, 0x03, 0x00, 0x78, 0xA0, 0x03, 0xC0, 0x80,
+        ];
+        let hvcc = HvcCBox::new(&[0x40, 0x01], &sps, &[0x44, 0x01]);
+        assert_eq!(hvcc.general_profile_space, 0);
+        assert!(!hvcc.general_tier_flag);
+        assert_eq!(hvcc.general_profile_idc, 1);
+        assert_eq!(hvcc.general_profile_compatibility_flags, 0x60000000);
+        assert_eq!(hvcc.general_constraint_indicator_flag, 0x900000000000);
+        assert_eq!(hvcc.general_level_idc, 120);
+        assert_eq!(hvcc.num_temporal_layers, 1);
+        assert!(hvcc.temporal_id_nested);
+        assert_eq!(hvcc.nal_unit(HEVC_NAL_SPS).unwrap().bytes, sps);
+        assert_eq!(hvcc.nal_unit(HEVC_NAL_PPS).unwrap().bytes, [0x44, 0x01]);
+        // Cut short before the bit depths, so the defaults remain.
+        assert_eq!(hvcc.chroma_format_idc, 1);
+        assert_eq!(hvcc.bit_depth_luma_minus8, 0);
+    }
+
+    #[test]
+    fn test_hvcc_new_chroma_format() {
+        // 720p 4:2:2 10-bit with a sub-layer level and a conformance window.
+        let sps = [
+            0x42, 0x01, 0x03, 0x04, 0x08, 0x00, 0x00, 0x03, 0x00, 0x00, 0x03, 0x00, 0x00, 0x03,
+            0x00, 0x00, 0x03, 0x00, 0x5D, 0x40, 0x00, 0x5A, 0xB0, 0x02, 0x80, 0x80, 0x2D, 0x1F,
+            0x2B, 0x70,
+        ];
+        let hvcc = HvcCBox::new(&[], &sps, &[0x44, 0x01]);
+        assert_eq!(hvcc.general_profile_idc, 4);
+        assert_eq!(hvcc.general_level_idc, 93);
+        assert_eq!(hvcc.num_temporal_layers, 2);
+        assert_eq!(hvcc.chroma_format_idc, 2);
+        assert_eq!(hvcc.bit_depth_luma_minus8, 2);
+        assert_eq!(hvcc.bit_depth_chroma_minus8, 2);
+
+        // No VPS array for the empty VPS.
+        assert_eq!(hvcc.arrays.len(), 2);
+        assert!(hvcc.nal_unit(HEVC_NAL_VPS).is_none());
+        assert!(HvcCBox::new(&[], &[], &[]).arrays.is_empty());
+    }
 }
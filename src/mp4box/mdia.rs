@@ -2,13 +2,16 @@ use std::io::{Read, Seek, SeekFrom, Write};
 use serde::{Serialize};
 
 use crate::mp4box::*;
-use crate::mp4box::{hdlr::HdlrBox, mdhd::MdhdBox, minf::MinfBox};
+use crate::mp4box::{hdlr::HdlrBox, mdhd::MdhdBox, minf::MinfBox, raw::RawBox};
 
 #[derive(Debug, Clone, PartialEq, Default, Serialize)]
 pub struct MdiaBox {
     pub mdhd: MdhdBox,
     pub hdlr: HdlrBox,
     pub minf: MinfBox,
+
+    #[serde(skip)]
+    pub unknown_boxes: Vec<RawBox>,
 }
 
 impl MdiaBox {
@@ -17,7 +20,11 @@ impl MdiaBox {
     }
 
     pub fn get_size(&self) -> u64 {
-        HEADER_SIZE + self.mdhd.box_size() + self.hdlr.box_size() + self.minf.box_size()
+        let mut size = HEADER_SIZE + self.mdhd.box_size() + self.hdlr.box_size() + self.minf.box_size();
+        for unknown in self.unknown_boxes.iter() {
+            size += unknown.box_size();
+        }
+        size
     }
 }
 
@@ -47,13 +54,15 @@ impl<R: Read + Seek> ReadBox<&mut R> for MdiaBox {
         let mut mdhd = None;
         let mut hdlr = None;
         let mut minf = None;
+        let mut unknown_boxes = Vec::new();
 
         let mut current = reader.seek(SeekFrom::Current(0))?;
         let end = start + size;
         while current < end {
             // Get box header.
-            let header = BoxHeader::read(reader)?;
-            let BoxHeader { name, size: s } = header;
+            let header = BoxHeader::read_in(reader, end)?;
+            let BoxHeader { name, .. } = header;
+            let s = header.read_size();
 
             match name {
                 BoxType::MdhdBox => {
@@ -66,8 +75,7 @@ impl<R: Read + Seek> ReadBox<&mut R> for MdiaBox {
                     minf = Some(MinfBox::read_box(reader, s)?);
                 }
                 _ => {
-                    // XXX warn!()
-                    skip_box(reader, s)?;
+                    unknown_boxes.push(RawBox::read(reader, &header)?);
                 }
             }
 
@@ -90,6 +98,7 @@ impl<R: Read + Seek> ReadBox<&mut R> for MdiaBox {
             mdhd: mdhd.unwrap(),
             hdlr: hdlr.unwrap(),
             minf: minf.unwrap(),
+            unknown_boxes,
         })
     }
 }
@@ -103,6 +112,10 @@ impl<W: Write> WriteBox<&mut W> for MdiaBox {
         self.hdlr.write_box(writer)?;
         self.minf.write_box(writer)?;
 
+        for unknown in self.unknown_boxes.iter() {
+            unknown.write_box(writer)?;
+        }
+
         Ok(size)
     }
 }
@@ -2,7 +2,7 @@ use std::io::{Read, Seek, SeekFrom, Write};
 use serde::{Serialize};
 
 use crate::mp4box::*;
-use crate::mp4box::{dinf::DinfBox, smhd::SmhdBox, stbl::StblBox, vmhd::VmhdBox};
+use crate::mp4box::{dinf::DinfBox, smhd::SmhdBox, stbl::StblBox, vmhd::VmhdBox, raw::RawBox};
 
 #[derive(Debug, Clone, PartialEq, Default, Serialize)]
 pub struct MinfBox {
@@ -14,6 +14,9 @@ pub struct MinfBox {
 
     pub dinf: DinfBox,
     pub stbl: StblBox,
+
+    #[serde(skip)]
+    pub unknown_boxes: Vec<RawBox>,
 }
 
 impl MinfBox {
@@ -31,6 +34,9 @@ impl MinfBox {
         }
         size += self.dinf.box_size();
         size += self.stbl.box_size();
+        for unknown in self.unknown_boxes.iter() {
+            size += unknown.box_size();
+        }
         size
     }
 }
@@ -62,13 +68,15 @@ impl<R: Read + Seek> ReadBox<&mut R> for MinfBox {
         let mut smhd = None;
         let mut dinf = None;
         let mut stbl = None;
+        let mut unknown_boxes = Vec::new();
 
         let mut current = reader.seek(SeekFrom::Current(0))?;
         let end = start + size;
         while current < end {
             // Get box header.
-            let header = BoxHeader::read(reader)?;
-            let BoxHeader { name, size: s } = header;
+            let header = BoxHeader::read_in(reader, end)?;
+            let BoxHeader { name, .. } = header;
+            let s = header.read_size();
 
             match name {
                 BoxType::VmhdBox => {
@@ -84,8 +92,7 @@ impl<R: Read + Seek> ReadBox<&mut R> for MinfBox {
                     stbl = Some(StblBox::read_box(reader, s)?);
                 }
                 _ => {
-                    // XXX warn!()
-                    skip_box(reader, s)?;
+                    unknown_boxes.push(RawBox::read(reader, &header)?);
                 }
             }
 
@@ -106,6 +113,7 @@ impl<R: Read + Seek> ReadBox<&mut R> for MinfBox {
             smhd,
             dinf: dinf.unwrap(),
             stbl: stbl.unwrap(),
+            unknown_boxes,
         })
     }
 }
@@ -124,6 +132,10 @@ impl<W: Write> WriteBox<&mut W> for MinfBox {
         self.dinf.write_box(writer)?;
         self.stbl.write_box(writer)?;
 
+        for unknown in self.unknown_boxes.iter() {
+            unknown.write_box(writer)?;
+        }
+
         Ok(size)
     }
 }
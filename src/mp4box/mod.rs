@@ -14,6 +14,7 @@
 //!     mvhd
 //!     trak
 //!         tkhd
+//!         tref
 //!         mdia
 //!             mdhd
 //!             hdlr
@@ -21,9 +22,16 @@
 //!                 stbl
 //!                     stsd
 //!                         avc1
+//!                         avc2
+//!                         avc3
+//!                         avc4
 //!                         hev1
+//!                         hvc1
+//!                         av01
 //!                         mp4a
+//!                         Opus
 //!                         tx3g
+//!                         btrt
 //!                     stts
 //!                     stsc
 //!                     stsz
@@ -44,9 +52,11 @@
 //!     mfhd
 //!     traf
 //!         tfhd
+//!         tfdt
 //!         trun
 //! mdat
 //! free
+//! uuid
 //! 
 
 use byteorder::{BigEndian, ReadBytesExt, WriteBytesExt};
@@ -55,7 +65,9 @@ use std::io::{Read, Seek, SeekFrom, Write};
 
 use crate::*;
 
+pub(crate) mod av01;
 pub(crate) mod avc1;
+pub(crate) mod btrt;
 pub(crate) mod co64;
 pub(crate) mod ctts;
 pub(crate) mod dinf;
@@ -75,6 +87,8 @@ pub(crate) mod moof;
 pub(crate) mod mp4a;
 pub(crate) mod mvhd;
 pub(crate) mod mfhd;
+pub(crate) mod opus;
+pub(crate) mod raw;
 pub(crate) mod smhd;
 pub(crate) mod stbl;
 pub(crate) mod stco;
@@ -84,9 +98,11 @@ pub(crate) mod stss;
 pub(crate) mod stsz;
 pub(crate) mod stts;
 pub(crate) mod tkhd;
+pub(crate) mod tfdt;
 pub(crate) mod tfhd;
 pub(crate) mod trak;
 pub(crate) mod traf;
+pub(crate) mod tref;
 pub(crate) mod trun;
 pub(crate) mod tx3g;
 pub(crate) mod vmhd;
@@ -98,8 +114,9 @@ pub use moov::MoovBox;
 pub use moof::MoofBox;
 
 pub const HEADER_SIZE: u64 = 8;
-// const HEADER_LARGE_SIZE: u64 = 16;
+pub const HEADER_LARGE_SIZE: u64 = 16;
 pub const HEADER_EXT_SIZE: u64 = 4;
+pub const USERTYPE_SIZE: u64 = 16;
 
 macro_rules! boxtype {
     ($( $name:ident => $value:expr ),*) => {
@@ -142,6 +159,7 @@ boxtype! {
     MoofBox => 0x6d6f6f66,
     TkhdBox => 0x746b6864,
     TfhdBox => 0x74666864,
+    TfdtBox => 0x74666474,
     EdtsBox => 0x65647473,
     MdiaBox => 0x6d646961,
     ElstBox => 0x656c7374,
@@ -159,6 +177,7 @@ boxtype! {
     StcoBox => 0x7374636F,
     Co64Box => 0x636F3634,
     TrakBox => 0x7472616b,
+    TrefBox => 0x74726566,
     TrafBox => 0x74726166,
     TrunBox => 0x7472756E,
     UdtaBox => 0x75647461,
@@ -167,14 +186,24 @@ boxtype! {
     UrlBox  => 0x75726C20,
     SmhdBox => 0x736d6864,
     Avc1Box => 0x61766331,
+    Avc2Box => 0x61766332,
+    Avc3Box => 0x61766333,
+    Avc4Box => 0x61766334,
+    Av01Box => 0x61763031,
+    Av1CBox => 0x61763143,
     AvcCBox => 0x61766343,
     Hev1Box => 0x68657631,
     HvcCBox => 0x68766343,
+    Hvc1Box => 0x68766331,
     Mp4aBox => 0x6d703461,
     EsdsBox => 0x65736473,
+    OpusBox => 0x4F707573,
+    DopsBox => 0x644F7073,
     Tx3gBox => 0x74783367,
     VpccBox => 0x76706343,
-    Vp09Box => 0x76703039
+    Vp09Box => 0x76703039,
+    BtrtBox => 0x62747274,
+    UuidBox => 0x75756964
 }
 
 pub trait Mp4Box: Sized {
@@ -195,19 +224,49 @@ pub trait WriteBox<T>: Sized {
 #[derive(Debug, Clone, Copy)]
 pub struct BoxHeader {
     pub name: BoxType,
+    /// Size of the whole box, header included. A size of 0 means the box
+    /// extends to the end of its container, see [BoxHeader::read_in].
     pub size: u64,
+    /// Length of the header itself: 8, or 16 with a largesize, plus 16 for
+    /// the extended type of a uuid box.
+    pub header_size: u64,
+    /// Extended type of a uuid box.
+    pub usertype: Option<[u8; 16]>,
 }
 
 impl BoxHeader {
+    /// Creates an 8 byte header for a box of `size` bytes, header included.
+    /// [BoxHeader::write] fails if `size` doesn't fit in 32 bits, see
+    /// [BoxHeader::for_data] for boxes that may grow that large.
     pub fn new(name: BoxType, size: u64) -> Self {
-        Self { name, size }
+        Self {
+            name,
+            size,
+            header_size: HEADER_SIZE,
+            usertype: None,
+        }
+    }
+
+    /// Creates a header for a box with `data_size` bytes after the header,
+    /// using a largesize if the box doesn't fit in a 32-bit size.
+    pub fn for_data(name: BoxType, data_size: u64) -> Self {
+        let header_size = if data_size + HEADER_SIZE > u32::MAX as u64 {
+            HEADER_LARGE_SIZE
+        } else {
+            HEADER_SIZE
+        };
+        Self {
+            name,
+            size: data_size + header_size,
+            header_size,
+            usertype: None,
+        }
     }
 
-    // TODO: if size is 0, then this box is the last one in the file
     pub fn read<R: Read>(reader: &mut R) -> Result<Self> {
         // Create and read to buf.
         let mut buf = [0u8; 8]; // 8 bytes for box header.
-        reader.read(&mut buf)?;
+        reader.read_exact(&mut buf)?;
 
         // Get size.
         let s = buf[0..4].try_into().unwrap();
@@ -216,36 +275,74 @@ impl BoxHeader {
         // Get box type string.
         let t = buf[4..8].try_into().unwrap();
         let typ = u32::from_be_bytes(t);
+        let name = BoxType::from(typ);
 
         // Get largesize if size is 1
-        if size == 1 {
-            reader.read(&mut buf)?;
-            let s = buf.try_into().unwrap();
-            let largesize = u64::from_be_bytes(s);
-
-            Ok(BoxHeader {
-                name: BoxType::from(typ),
-                size: largesize - HEADER_SIZE,
-            })
+        let (size, mut header_size) = if size == 1 {
+            reader.read_exact(&mut buf)?;
+            (u64::from_be_bytes(buf), HEADER_LARGE_SIZE)
+        } else {
+            (size as u64, HEADER_SIZE)
+        };
+
+        let usertype = if name == BoxType::UuidBox {
+            let mut usertype = [0u8; 16];
+            reader.read_exact(&mut usertype)?;
+            header_size += USERTYPE_SIZE;
+            Some(usertype)
         } else {
-            Ok(BoxHeader {
-                name: BoxType::from(typ),
-                size: size as u64,
-            })
+            None
+        };
+
+        if size != 0 && size < header_size {
+            return Err(Error::InvalidData("box size smaller than its header"));
         }
+
+        Ok(BoxHeader {
+            name,
+            size,
+            header_size,
+            usertype,
+        })
+    }
+
+    /// Reads a header inside a container ending at `end`, resolving a size of
+    /// 0 to the rest of the container.
+    pub fn read_in<R: Read + Seek>(reader: &mut R, end: u64) -> Result<Self> {
+        let start = reader.stream_position()?;
+        let mut header = Self::read(reader)?;
+        if header.size == 0 {
+            header.size = end.saturating_sub(start);
+        }
+        Ok(header)
+    }
+
+    /// Size to pass to [ReadBox::read_box], which measures a box from
+    /// [HEADER_SIZE] bytes before the end of its header.
+    pub fn read_size(&self) -> u64 {
+        self.size.saturating_sub(self.header_size) + HEADER_SIZE
     }
 
     pub fn write<W: Write>(&self, writer: &mut W) -> Result<u64> {
-        if self.size > u32::MAX as u64 {
+        let extended = if self.usertype.is_some() {
+            USERTYPE_SIZE
+        } else {
+            0
+        };
+        if self.header_size - extended == HEADER_LARGE_SIZE {
             writer.write_u32::<BigEndian>(1)?;
             writer.write_u32::<BigEndian>(self.name.into())?;
             writer.write_u64::<BigEndian>(self.size)?;
-            Ok(16)
+        } else if self.size > u32::MAX as u64 {
+            return Err(Error::InvalidData("box size too large for a 32-bit header"));
         } else {
             writer.write_u32::<BigEndian>(self.size as u32)?;
             writer.write_u32::<BigEndian>(self.name.into())?;
-            Ok(8)
         }
+        if let Some(usertype) = self.usertype {
+            writer.write_all(&usertype)?;
+        }
+        Ok(self.header_size)
     }
 }
 
@@ -337,6 +434,7 @@ mod value_u8 {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use std::io::Cursor;
 
     #[test]
     fn test_fourcc() {
@@ -346,4 +444,88 @@ mod tests {
         let ftyp_fcc2: u32 = ftyp_value.into();
         assert_eq!(ftyp_fcc, ftyp_fcc2);
     }
+
+    #[test]
+    fn test_box_header() {
+        let header = BoxHeader::new(BoxType::FreeBox, 24);
+        let mut buf = Vec::new();
+        assert_eq!(header.write(&mut buf).unwrap(), HEADER_SIZE);
+        assert_eq!(buf.len() as u64, HEADER_SIZE);
+
+        let header = BoxHeader::read(&mut Cursor::new(&buf)).unwrap();
+        assert_eq!(header.name, BoxType::FreeBox);
+        assert_eq!(header.size, 24);
+        assert_eq!(header.header_size, HEADER_SIZE);
+        assert_eq!(header.read_size(), 24);
+    }
+
+    #[test]
+    fn test_box_header_largesize() {
+        let mut buf = Vec::new();
+        let header = BoxHeader::new(BoxType::MdatBox, 0x1_0000_0000);
+        assert!(header.write(&mut buf).is_err());
+
+        let header = BoxHeader::for_data(BoxType::MdatBox, 0x1_0000_0000);
+        assert_eq!(header.size, 0x1_0000_0010);
+        let mut buf = Vec::new();
+        assert_eq!(header.write(&mut buf).unwrap(), HEADER_LARGE_SIZE);
+        assert_eq!(buf.len() as u64, HEADER_LARGE_SIZE);
+
+        let header = BoxHeader::read(&mut Cursor::new(&buf)).unwrap();
+        assert_eq!(header.name, BoxType::MdatBox);
+        assert_eq!(header.size, 0x1_0000_0010);
+        assert_eq!(header.header_size, HEADER_LARGE_SIZE);
+        assert_eq!(header.read_size(), 0x1_0000_0008);
+
+        // Small boxes may use a largesize too.
+        let mut buf = Vec::new();
+        buf.extend_from_slice(&1u32.to_be_bytes());
+        buf.extend_from_slice(b"free");
+        buf.extend_from_slice(&20u64.to_be_bytes());
+        let header = BoxHeader::read(&mut Cursor::new(&buf)).unwrap();
+        assert_eq!(header.size, 20);
+        assert_eq!(header.header_size, HEADER_LARGE_SIZE);
+    }
+
+    #[test]
+    fn test_box_header_uuid() {
+        let usertype = *b"\xbe\x7a\xcf\xcb\x97\xa9\x42\xe8\x9c\x71\x99\x94\x91\xe3\xaf\xac";
+        let header = BoxHeader {
+            name: BoxType::UuidBox,
+            size: 40,
+            header_size: HEADER_SIZE + USERTYPE_SIZE,
+            usertype: Some(usertype),
+        };
+        let mut buf = Vec::new();
+        assert_eq!(header.write(&mut buf).unwrap(), 24);
+        assert_eq!(buf.len(), 24);
+
+        let header = BoxHeader::read(&mut Cursor::new(&buf)).unwrap();
+        assert_eq!(header.name, BoxType::UuidBox);
+        assert_eq!(header.size, 40);
+        assert_eq!(header.header_size, 24);
+        assert_eq!(header.usertype, Some(usertype));
+    }
+
+    #[test]
+    fn test_box_header_size_zero() {
+        let mut buf = Vec::new();
+        BoxHeader::new(BoxType::FreeBox, 8).write(&mut buf).unwrap();
+        buf.extend_from_slice(&0u32.to_be_bytes());
+        buf.extend_from_slice(b"mdat");
+        buf.extend_from_slice(&[0; 20]);
+
+        let mut reader = Cursor::new(&buf);
+        reader.seek(SeekFrom::Start(8)).unwrap();
+        let header = BoxHeader::read_in(&mut reader, buf.len() as u64).unwrap();
+        assert_eq!(header.name, BoxType::MdatBox);
+        assert_eq!(header.size, 28);
+
+        let mut reader = Cursor::new(&buf);
+        reader.seek(SeekFrom::Start(8)).unwrap();
+        assert_eq!(BoxHeader::read(&mut reader).unwrap().size, 0);
+
+        let mut reader = Cursor::new(&[0, 0, 0, 4, b'f', b'r', b'e', b'e']);
+        assert!(BoxHeader::read(&mut reader).is_err());
+    }
 }
@@ -2,7 +2,7 @@ use std::io::{Read, Seek, SeekFrom, Write};
 use serde::{Serialize};
 
 use crate::mp4box::*;
-use crate::mp4box::{mfhd::MfhdBox, traf::TrafBox};
+use crate::mp4box::{mfhd::MfhdBox, raw::RawBox, traf::TrafBox};
 
 #[derive(Debug, Clone, PartialEq, Default, Serialize)]
 pub struct MoofBox {
@@ -10,6 +10,9 @@ pub struct MoofBox {
 
     #[serde(rename = "traf")]
     pub trafs: Vec<TrafBox>,
+
+    #[serde(skip)]
+    pub unknown_boxes: Vec<RawBox>,
 }
 
 impl MoofBox {
@@ -22,6 +25,9 @@ impl MoofBox {
         for traf in self.trafs.iter() {
             size += traf.box_size();
         }
+        for unknown in self.unknown_boxes.iter() {
+            size += unknown.box_size();
+        }
         size
     }
 }
@@ -51,13 +57,15 @@ impl<R: Read + Seek> ReadBox<&mut R> for MoofBox {
 
         let mut mfhd = None;
         let mut trafs = Vec::new();
+        let mut unknown_boxes = Vec::new();
 
         let mut current = reader.seek(SeekFrom::Current(0))?;
         let end = start + size;
         while current < end {
             // Get box header.
-            let header = BoxHeader::read(reader)?;
-            let BoxHeader { name, size: s } = header;
+            let header = BoxHeader::read_in(reader, end)?;
+            let BoxHeader { name, .. } = header;
+            let s = header.read_size();
 
             match name {
                 BoxType::MfhdBox => {
@@ -68,8 +76,7 @@ impl<R: Read + Seek> ReadBox<&mut R> for MoofBox {
                     trafs.push(traf);
                 }
                 _ => {
-                    // XXX warn!()
-                    skip_box(reader, s)?;
+                    unknown_boxes.push(RawBox::read(reader, &header)?);
                 }
             }
             current = reader.seek(SeekFrom::Current(0))?;
@@ -84,6 +91,7 @@ impl<R: Read + Seek> ReadBox<&mut R> for MoofBox {
         Ok(MoofBox {
             mfhd: mfhd.unwrap(),
             trafs,
+            unknown_boxes,
         })
     }
 }
@@ -97,6 +105,56 @@ impl<W: Write> WriteBox<&mut W> for MoofBox {
         for traf in self.trafs.iter() {
             traf.write_box(writer)?;
         }
-        Ok(0)
+        for unknown in self.unknown_boxes.iter() {
+            unknown.write_box(writer)?;
+        }
+        Ok(size)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mp4box::{tfhd::TfhdBox, BoxHeader};
+    use std::io::Cursor;
+
+    fn raw_box(name: &[u8; 4], data: &[u8]) -> RawBox {
+        RawBox {
+            name: BoxType::from(u32::from_be_bytes(*name)),
+            usertype: None,
+            data: data.to_vec(),
+        }
+    }
+
+    #[test]
+    fn test_moof_unknown_boxes() {
+        let traf = TrafBox {
+            tfhd: TfhdBox {
+                track_id: 1,
+                ..TfhdBox::default()
+            },
+            unknown_boxes: vec![
+                raw_box(b"sgpd", &[1, 2, 3]),
+                raw_box(b"sbgp", &[4, 5]),
+                raw_box(b"saiz", &[6]),
+            ],
+            ..TrafBox::default()
+        };
+        let src_box = MoofBox {
+            trafs: vec![traf],
+            unknown_boxes: vec![raw_box(b"pssh", &[0, 0, 0, 0])],
+            ..MoofBox::default()
+        };
+        let mut buf = Vec::new();
+        src_box.write_box(&mut buf).unwrap();
+        assert_eq!(buf.len(), src_box.box_size() as usize);
+
+        let mut reader = Cursor::new(&buf);
+        let header = BoxHeader::read(&mut reader).unwrap();
+        assert_eq!(header.name, BoxType::MoofBox);
+        assert_eq!(src_box.box_size(), header.size);
+
+        let dst_box = MoofBox::read_box(&mut reader, header.size).unwrap();
+        assert_eq!(src_box, dst_box);
     }
 }
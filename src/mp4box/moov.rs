@@ -2,7 +2,7 @@ use std::io::{Read, Seek, SeekFrom, Write};
 use serde::{Serialize};
 
 use crate::mp4box::*;
-use crate::mp4box::{mvhd::MvhdBox, mvex::MvexBox, trak::TrakBox};
+use crate::mp4box::{mvhd::MvhdBox, mvex::MvexBox, trak::TrakBox, raw::RawBox};
 
 #[derive(Debug, Clone, PartialEq, Default, Serialize)]
 pub struct MoovBox {
@@ -13,6 +13,9 @@ pub struct MoovBox {
 
     #[serde(rename = "trak")]
     pub traks: Vec<TrakBox>,
+
+    #[serde(skip)]
+    pub unknown_boxes: Vec<RawBox>,
 }
 
 impl MoovBox {
@@ -22,9 +25,15 @@ impl MoovBox {
 
     pub fn get_size(&self) -> u64 {
         let mut size = HEADER_SIZE + self.mvhd.box_size();
+        if let Some(ref mvex) = self.mvex {
+            size += mvex.box_size();
+        }
         for trak in self.traks.iter() {
             size += trak.box_size();
         }
+        for unknown in self.unknown_boxes.iter() {
+            size += unknown.box_size();
+        }
         size
     }
 }
@@ -55,13 +64,15 @@ impl<R: Read + Seek> ReadBox<&mut R> for MoovBox {
         let mut mvhd = None;
         let mut mvex = None;
         let mut traks = Vec::new();
+        let mut unknown_boxes = Vec::new();
 
         let mut current = reader.seek(SeekFrom::Current(0))?;
         let end = start + size;
         while current < end {
             // Get box header.
-            let header = BoxHeader::read(reader)?;
-            let BoxHeader { name, size: s } = header;
+            let header = BoxHeader::read_in(reader, end)?;
+            let BoxHeader { name, .. } = header;
+            let s = header.read_size();
 
             match name {
                 BoxType::MvhdBox => {
@@ -74,13 +85,8 @@ impl<R: Read + Seek> ReadBox<&mut R> for MoovBox {
                     let trak = TrakBox::read_box(reader, s)?;
                     traks.push(trak);
                 }
-                BoxType::UdtaBox => {
-                    // XXX warn!()
-                    skip_box(reader, s)?;
-                }
                 _ => {
-                    // XXX warn!()
-                    skip_box(reader, s)?;
+                    unknown_boxes.push(RawBox::read(reader, &header)?);
                 }
             }
 
@@ -97,6 +103,7 @@ impl<R: Read + Seek> ReadBox<&mut R> for MoovBox {
             mvhd: mvhd.unwrap(),
             mvex,
             traks,
+            unknown_boxes,
         })
     }
 }
@@ -110,6 +117,64 @@ impl<W: Write> WriteBox<&mut W> for MoovBox {
         for trak in self.traks.iter() {
             trak.write_box(writer)?;
         }
-        Ok(0)
+        if let Some(ref mvex) = self.mvex {
+            mvex.write_box(writer)?;
+        }
+        for unknown in self.unknown_boxes.iter() {
+            unknown.write_box(writer)?;
+        }
+
+        Ok(size)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mp4box::{trex::TrexBox, BoxHeader};
+    use crate::track::Mp4TrackWriter;
+    use crate::{TrackConfig, TtxtConfig};
+    use std::io::Cursor;
+
+    fn raw_box(name: &[u8; 4], data: &[u8]) -> RawBox {
+        RawBox {
+            name: BoxType::from(u32::from_be_bytes(*name)),
+            usertype: None,
+            data: data.to_vec(),
+        }
+    }
+
+    #[test]
+    fn test_moov_unknown_boxes() {
+        let mut track = Mp4TrackWriter::new(1, &TrackConfig::from(TtxtConfig {})).unwrap();
+        let mut trak = track.write_end(&mut Cursor::new(Vec::new()), 1000).unwrap();
+        trak.unknown_boxes.push(raw_box(b"meta", &[0, 0, 0, 0]));
+        trak.mdia.minf.stbl.unknown_boxes.push(raw_box(b"sgpd", &[1, 2, 3]));
+        trak.mdia.minf.dinf.unknown_boxes.push(raw_box(b"test", &[4]));
+        let src_box = MoovBox {
+            mvex: Some(MvexBox {
+                trexs: vec![TrexBox::default()],
+                unknown_boxes: vec![raw_box(b"leva", &[0, 0, 0, 0, 0])],
+                ..MvexBox::default()
+            }),
+            traks: vec![trak],
+            unknown_boxes: vec![raw_box(b"udta", &[0, 0, 0, 8, b'f', b'r', b'e', b'e'])],
+            ..MoovBox::default()
+        };
+        let mut buf = Vec::new();
+        src_box.write_box(&mut buf).unwrap();
+        assert_eq!(buf.len(), src_box.box_size() as usize);
+
+        let mut reader = Cursor::new(&buf);
+        let header = BoxHeader::read(&mut reader).unwrap();
+        assert_eq!(header.name, BoxType::MoovBox);
+        assert_eq!(src_box.box_size(), header.size);
+
+        let dst_box = MoovBox::read_box(&mut reader, header.size).unwrap();
+        assert_eq!(src_box, dst_box);
+
+        let mut dst_buf = Vec::new();
+        dst_box.write_box(&mut dst_buf).unwrap();
+        assert_eq!(buf, dst_buf);
     }
 }
@@ -1,8 +1,10 @@
 use byteorder::{BigEndian, ReadBytesExt, WriteBytesExt};
-use std::io::{Read, Seek, Write};
+use std::io::{Read, Seek, SeekFrom, Write};
 use serde::{Serialize};
 
 use crate::mp4box::*;
+use crate::mp4box::btrt::BtrtBox;
+use crate::mp4box::raw::{child_order, Child, RawBox};
 
 #[derive(Debug, Clone, PartialEq, Serialize)]
 pub struct Mp4aBox {
@@ -13,6 +15,17 @@ pub struct Mp4aBox {
     #[serde(with = "value_u32")]
     pub samplerate: FixedPointU16,
     pub esds: Option<EsdsBox>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub btrt: Option<BtrtBox>,
+
+    #[serde(skip)]
+    pub unknown_boxes: Vec<RawBox>,
+
+    /// Types of all children in the order they were read, so unknown boxes
+    /// are written back where they were. Empty when there are none.
+    #[serde(skip)]
+    pub child_order: Vec<BoxType>,
 }
 
 impl Default for Mp4aBox {
@@ -23,6 +36,9 @@ impl Default for Mp4aBox {
             samplesize: 16,
             samplerate: FixedPointU16::new(48000),
             esds: Some(EsdsBox::default()),
+            btrt: None,
+            unknown_boxes: Vec::new(),
+            child_order: Vec::new(),
         }
     }
 }
@@ -35,6 +51,9 @@ impl Mp4aBox {
             samplesize: 16,
             samplerate: FixedPointU16::new(config.freq_index.freq() as u16),
             esds: Some(EsdsBox::new(config)),
+            btrt: None,
+            unknown_boxes: Vec::new(),
+            child_order: Vec::new(),
         }
     }
 
@@ -47,6 +66,12 @@ impl Mp4aBox {
         if let Some(ref esds) = self.esds {
             size += esds.box_size();
         }
+        if let Some(ref btrt) = self.btrt {
+            size += btrt.box_size();
+        }
+        for unknown in self.unknown_boxes.iter() {
+            size += unknown.box_size();
+        }
         size
     }
 }
@@ -85,13 +110,37 @@ impl<R: Read + Seek> ReadBox<&mut R> for Mp4aBox {
         reader.read_u32::<BigEndian>()?; // pre-defined, reserved
         let samplerate = FixedPointU16::new_raw(reader.read_u32::<BigEndian>()?);
 
-        let header = BoxHeader::read(reader)?;
-        let BoxHeader { name, size: s } = header;
-
         let mut esds = None;
-        if name == BoxType::EsdsBox {
-            esds = Some(EsdsBox::read_box(reader, s)?);
+        let mut btrt = None;
+        let mut unknown_boxes = Vec::new();
+        let mut child_order = Vec::new();
+
+        let mut current = reader.stream_position()?;
+        let end = start + size;
+        while current + HEADER_SIZE <= end {
+            let header = BoxHeader::read_in(reader, end)?;
+            let BoxHeader { name, .. } = header;
+            let s = header.read_size();
+            child_order.push(name);
+
+            match name {
+                BoxType::EsdsBox => {
+                    esds = Some(EsdsBox::read_box(reader, s)?);
+                }
+                BoxType::BtrtBox => {
+                    btrt = Some(BtrtBox::read_box(reader, s)?);
+                }
+                _ => {
+                    unknown_boxes.push(RawBox::read(reader, &header)?);
+                }
+            }
+
+            current = reader.stream_position()?;
+        }
+        if unknown_boxes.is_empty() {
+            child_order.clear();
         }
+
         skip_bytes_to(reader, start + size)?;
 
         Ok(Mp4aBox {
@@ -100,6 +149,9 @@ impl<R: Read + Seek> ReadBox<&mut R> for Mp4aBox {
             samplesize,
             samplerate,
             esds,
+            btrt,
+            unknown_boxes,
+            child_order,
         })
     }
 }
@@ -119,8 +171,24 @@ impl<W: Write> WriteBox<&mut W> for Mp4aBox {
         writer.write_u32::<BigEndian>(0)?; // reserved
         writer.write_u32::<BigEndian>(self.samplerate.raw_value())?;
 
-        if let Some(ref esds) = self.esds {
-            esds.write_box(writer)?;
+        let known = [BoxType::EsdsBox, BoxType::BtrtBox];
+        for child in child_order(&self.child_order, &known, self.unknown_boxes.len()) {
+            match child {
+                Child::Known(BoxType::EsdsBox) => {
+                    if let Some(ref esds) = self.esds {
+                        esds.write_box(writer)?;
+                    }
+                }
+                Child::Known(BoxType::BtrtBox) => {
+                    if let Some(ref btrt) = self.btrt {
+                        btrt.write_box(writer)?;
+                    }
+                }
+                Child::Known(_) => {}
+                Child::Unknown(i) => {
+                    self.unknown_boxes[i].write_box(writer)?;
+                }
+            }
         }
 
         Ok(size)
@@ -570,6 +638,9 @@ mod tests {
                     sl_config: SLConfigDescriptor::default(),
                 },
             }),
+            btrt: None,
+            unknown_boxes: Vec::new(),
+            child_order: Vec::new(),
         };
         let mut buf = Vec::new();
         src_box.write_box(&mut buf).unwrap();
@@ -592,6 +663,9 @@ mod tests {
             samplesize: 16,
             samplerate: FixedPointU16::new(48000),
             esds: None,
+            btrt: None,
+            unknown_boxes: Vec::new(),
+            child_order: Vec::new(),
         };
         let mut buf = Vec::new();
         src_box.write_box(&mut buf).unwrap();
@@ -605,4 +679,40 @@ mod tests {
         let dst_box = Mp4aBox::read_box(&mut reader, header.size).unwrap();
         assert_eq!(src_box, dst_box);
     }
+
+    #[test]
+    fn test_mp4a_child_order() {
+        let chnl = BoxType::UnknownBox(u32::from_be_bytes(*b"chnl"));
+        let src_box = Mp4aBox {
+            btrt: Some(BtrtBox {
+                buffer_size_db: 0,
+                max_bitrate: 128000,
+                avg_bitrate: 128000,
+            }),
+            unknown_boxes: vec![RawBox {
+                name: chnl,
+                usertype: None,
+                data: vec![0, 0, 0, 0, 1, 2],
+            }],
+            child_order: vec![BoxType::EsdsBox, chnl, BoxType::BtrtBox],
+            ..Mp4aBox::default()
+        };
+        let mut buf = Vec::new();
+        src_box.write_box(&mut buf).unwrap();
+        assert_eq!(buf.len(), src_box.box_size() as usize);
+
+        let mut reader = Cursor::new(&buf);
+        let header = BoxHeader::read(&mut reader).unwrap();
+        let dst_box = Mp4aBox::read_box(&mut reader, header.size).unwrap();
+        assert_eq!(src_box, dst_box);
+
+        let mut children = Vec::new();
+        reader.set_position(HEADER_SIZE + 28);
+        while reader.position() < buf.len() as u64 {
+            let header = BoxHeader::read(&mut reader).unwrap();
+            children.push(header.name);
+            reader.set_position(reader.position() + header.size - header.header_size);
+        }
+        assert_eq!(children, src_box.child_order);
+    }
 }
@@ -2,21 +2,33 @@ use std::io::{Read, Seek, SeekFrom, Write};
 use serde::{Serialize};
 
 use crate::mp4box::*;
-use crate::mp4box::{mehd::MehdBox, trex::TrexBox};
+use crate::mp4box::{mehd::MehdBox, raw::RawBox, trex::TrexBox};
 
 #[derive(Debug, Clone, PartialEq, Default, Serialize)]
 pub struct MvexBox {
     pub mehd: Option<MehdBox>,
-    pub trex: TrexBox,
+
+    #[serde(rename = "trex")]
+    pub trexs: Vec<TrexBox>,
+
+    #[serde(skip)]
+    pub unknown_boxes: Vec<RawBox>,
 }
 
 impl MvexBox {
     pub fn get_type(&self) -> BoxType {
-        BoxType::MdiaBox
+        BoxType::MvexBox
     }
 
     pub fn get_size(&self) -> u64 {
-        HEADER_SIZE + self.mehd.as_ref().map(|x| x.box_size()).unwrap_or(0) + self.trex.box_size()
+        let mut size = HEADER_SIZE + self.mehd.as_ref().map(|x| x.box_size()).unwrap_or(0);
+        for trex in self.trexs.iter() {
+            size += trex.box_size();
+        }
+        for unknown in self.unknown_boxes.iter() {
+            size += unknown.box_size();
+        }
+        size
     }
 }
 
@@ -44,32 +56,34 @@ impl<R: Read + Seek> ReadBox<&mut R> for MvexBox {
         let start = box_start(reader)?;
 
         let mut mehd = None;
-        let mut trex = None;
+        let mut trexs = Vec::new();
+        let mut unknown_boxes = Vec::new();
 
         let mut current = reader.seek(SeekFrom::Current(0))?;
         let end = start + size;
         while current < end {
             // Get box header.
-            let header = BoxHeader::read(reader)?;
-            let BoxHeader { name, size: s } = header;
+            let header = BoxHeader::read_in(reader, end)?;
+            let BoxHeader { name, .. } = header;
+            let s = header.read_size();
 
             match name {
                 BoxType::MehdBox => {
                     mehd = Some(MehdBox::read_box(reader, s)?);
                 }
                 BoxType::TrexBox => {
-                    trex = Some(TrexBox::read_box(reader, s)?);
+                    let trex = TrexBox::read_box(reader, s)?;
+                    trexs.push(trex);
                 }
                 _ => {
-                    // XXX warn!()
-                    skip_box(reader, s)?;
+                    unknown_boxes.push(RawBox::read(reader, &header)?);
                 }
             }
 
             current = reader.seek(SeekFrom::Current(0))?;
         }
 
-        if trex.is_none() {
+        if trexs.is_empty() {
             return Err(Error::BoxNotFound(BoxType::TrexBox));
         }
 
@@ -77,7 +91,8 @@ impl<R: Read + Seek> ReadBox<&mut R> for MvexBox {
 
         Ok(MvexBox {
             mehd,
-            trex: trex.unwrap(),
+            trexs,
+            unknown_boxes,
         })
     }
 }
@@ -90,7 +105,12 @@ impl<W: Write> WriteBox<&mut W> for MvexBox {
         if let Some(mehd) = &self.mehd{
             mehd.write_box(writer)?;
         }
-        self.trex.write_box(writer)?;
+        for trex in self.trexs.iter() {
+            trex.write_box(writer)?;
+        }
+        for unknown in self.unknown_boxes.iter() {
+            unknown.write_box(writer)?;
+        }
 
         Ok(size)
     }
@@ -15,6 +15,11 @@ pub struct MvhdBox {
 
     #[serde(with = "value_u32")]
     pub rate: FixedPointU16,
+
+    #[serde(with = "value_u8")]
+    pub volume: FixedPointU8,
+    pub matrix: Matrix,
+    pub next_track_id: u32,
 }
 
 impl MvhdBox {
@@ -44,6 +49,9 @@ impl Default for MvhdBox {
             timescale: 1000,
             duration: 0,
             rate: FixedPointU16::new(1),
+            volume: FixedPointU8::new(1),
+            matrix: Matrix::default(),
+            next_track_id: 1,
         }
     }
 }
@@ -92,6 +100,24 @@ impl<R: Read + Seek> ReadBox<&mut R> for MvhdBox {
             return Err(Error::InvalidData("version must be 0 or 1"));
         };
         let rate = FixedPointU16::new_raw(reader.read_u32::<BigEndian>()?);
+        let volume = FixedPointU8::new_raw(reader.read_u16::<BigEndian>()?);
+
+        reader.read_u16::<BigEndian>()?; // reserved
+        reader.read_u64::<BigEndian>()?; // reserved
+        let matrix = Matrix {
+            a: reader.read_i32::<BigEndian>()?,
+            b: reader.read_i32::<BigEndian>()?,
+            u: reader.read_i32::<BigEndian>()?,
+            c: reader.read_i32::<BigEndian>()?,
+            d: reader.read_i32::<BigEndian>()?,
+            v: reader.read_i32::<BigEndian>()?,
+            x: reader.read_i32::<BigEndian>()?,
+            y: reader.read_i32::<BigEndian>()?,
+            w: reader.read_i32::<BigEndian>()?,
+        };
+
+        skip_bytes(reader, 24)?; // pre_defined
+        let next_track_id = reader.read_u32::<BigEndian>()?;
 
         skip_bytes_to(reader, start + size)?;
 
@@ -103,6 +129,9 @@ impl<R: Read + Seek> ReadBox<&mut R> for MvhdBox {
             timescale,
             duration,
             rate,
+            volume,
+            matrix,
+            next_track_id,
         })
     }
 }
@@ -128,9 +157,23 @@ impl<W: Write> WriteBox<&mut W> for MvhdBox {
             return Err(Error::InvalidData("version must be 0 or 1"));
         }
         writer.write_u32::<BigEndian>(self.rate.raw_value())?;
+        writer.write_u16::<BigEndian>(self.volume.raw_value())?;
+
+        writer.write_u16::<BigEndian>(0)?; // reserved
+        writer.write_u64::<BigEndian>(0)?; // reserved
+
+        writer.write_i32::<BigEndian>(self.matrix.a)?;
+        writer.write_i32::<BigEndian>(self.matrix.b)?;
+        writer.write_i32::<BigEndian>(self.matrix.u)?;
+        writer.write_i32::<BigEndian>(self.matrix.c)?;
+        writer.write_i32::<BigEndian>(self.matrix.d)?;
+        writer.write_i32::<BigEndian>(self.matrix.v)?;
+        writer.write_i32::<BigEndian>(self.matrix.x)?;
+        writer.write_i32::<BigEndian>(self.matrix.y)?;
+        writer.write_i32::<BigEndian>(self.matrix.w)?;
 
-        // XXX volume, ...
-        write_zeros(writer, 76)?;
+        write_zeros(writer, 24)?; // pre_defined
+        writer.write_u32::<BigEndian>(self.next_track_id)?;
 
         Ok(size)
     }
@@ -152,6 +195,9 @@ mod tests {
             timescale: 1000,
             duration: 634634,
             rate: FixedPointU16::new(1),
+            volume: FixedPointU8::new(1),
+            matrix: Matrix::default(),
+            next_track_id: 2,
         };
         let mut buf = Vec::new();
         src_box.write_box(&mut buf).unwrap();
@@ -176,6 +222,9 @@ mod tests {
             timescale: 1000,
             duration: 634634,
             rate: FixedPointU16::new(1),
+            volume: FixedPointU8::new(1),
+            matrix: Matrix::default(),
+            next_track_id: 2,
         };
         let mut buf = Vec::new();
         src_box.write_box(&mut buf).unwrap();
@@ -0,0 +1,368 @@
+use byteorder::{BigEndian, ReadBytesExt, WriteBytesExt};
+use std::io::{Read, Seek, Write};
+use serde::{Serialize};
+
+use crate::mp4box::*;
+use crate::mp4box::btrt::BtrtBox;
+use crate::mp4box::raw::{child_order, Child, RawBox};
+
+/// Opus sample entry, see the Opus in ISOBMFF encapsulation spec.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct OpusBox {
+    pub data_reference_index: u16,
+    pub channelcount: u16,
+    pub samplesize: u16,
+
+    #[serde(with = "value_u32")]
+    pub samplerate: FixedPointU16,
+    pub dops: DopsBox,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub btrt: Option<BtrtBox>,
+
+    #[serde(skip)]
+    pub unknown_boxes: Vec<RawBox>,
+
+    /// Types of all children in the order they were read, so unknown boxes
+    /// are written back where they were. Empty when there are none.
+    #[serde(skip)]
+    pub child_order: Vec<BoxType>,
+}
+
+impl Default for OpusBox {
+    fn default() -> Self {
+        Self {
+            data_reference_index: 0,
+            channelcount: 2,
+            samplesize: 16,
+            samplerate: FixedPointU16::new(48000),
+            dops: DopsBox::default(),
+            btrt: None,
+            unknown_boxes: Vec::new(),
+            child_order: Vec::new(),
+        }
+    }
+}
+
+impl OpusBox {
+    pub fn new(config: &OpusConfig) -> Self {
+        Self {
+            data_reference_index: 1,
+            channelcount: config.channel_count as u16,
+            samplesize: 16,
+            // Opus is always decoded at 48kHz.
+            samplerate: FixedPointU16::new(48000),
+            dops: DopsBox::new(config),
+            btrt: None,
+            unknown_boxes: Vec::new(),
+            child_order: Vec::new(),
+        }
+    }
+
+    pub fn get_type(&self) -> BoxType {
+        BoxType::OpusBox
+    }
+
+    pub fn get_size(&self) -> u64 {
+        let mut size = HEADER_SIZE + 8 + 20 + self.dops.box_size();
+        if let Some(ref btrt) = self.btrt {
+            size += btrt.box_size();
+        }
+        for unknown in self.unknown_boxes.iter() {
+            size += unknown.box_size();
+        }
+        size
+    }
+}
+
+impl Mp4Box for OpusBox {
+    fn box_type(&self) -> BoxType {
+        self.get_type()
+    }
+
+    fn box_size(&self) -> u64 {
+        self.get_size()
+    }
+
+    fn to_json(&self) -> Result<String> {
+        Ok(serde_json::to_string(&self).unwrap())
+    }
+
+    fn summary(&self) -> Result<String> {
+        let s = format!("channel_count={} sample_size={} sample_rate={}",
+            self.channelcount, self.samplesize, self.samplerate.value());
+        Ok(s)
+    }
+}
+
+impl<R: Read + Seek> ReadBox<&mut R> for OpusBox {
+    fn read_box(reader: &mut R, size: u64) -> Result<Self> {
+        let start = box_start(reader)?;
+
+        reader.read_u32::<BigEndian>()?; // reserved
+        reader.read_u16::<BigEndian>()?; // reserved
+        let data_reference_index = reader.read_u16::<BigEndian>()?;
+
+        reader.read_u64::<BigEndian>()?; // reserved
+        let channelcount = reader.read_u16::<BigEndian>()?;
+        let samplesize = reader.read_u16::<BigEndian>()?;
+        reader.read_u32::<BigEndian>()?; // pre-defined, reserved
+        let samplerate = FixedPointU16::new_raw(reader.read_u32::<BigEndian>()?);
+
+        let mut dops = None;
+        let mut btrt = None;
+        let mut unknown_boxes = Vec::new();
+        let mut child_order = Vec::new();
+
+        let mut current = reader.stream_position()?;
+        let end = start + size;
+        while current + HEADER_SIZE <= end {
+            let header = BoxHeader::read_in(reader, end)?;
+            let BoxHeader { name, .. } = header;
+            let s = header.read_size();
+            child_order.push(name);
+
+            match name {
+                BoxType::DopsBox => {
+                    dops = Some(DopsBox::read_box(reader, s)?);
+                }
+                BoxType::BtrtBox => {
+                    btrt = Some(BtrtBox::read_box(reader, s)?);
+                }
+                _ => {
+                    unknown_boxes.push(RawBox::read(reader, &header)?);
+                }
+            }
+
+            current = reader.stream_position()?;
+        }
+        if unknown_boxes.is_empty() {
+            child_order.clear();
+        }
+
+        let dops = dops.ok_or(Error::InvalidData("dops not found"))?;
+
+        skip_bytes_to(reader, start + size)?;
+
+        Ok(OpusBox {
+            data_reference_index,
+            channelcount,
+            samplesize,
+            samplerate,
+            dops,
+            btrt,
+            unknown_boxes,
+            child_order,
+        })
+    }
+}
+
+impl<W: Write> WriteBox<&mut W> for OpusBox {
+    fn write_box(&self, writer: &mut W) -> Result<u64> {
+        let size = self.box_size();
+        BoxHeader::new(self.box_type(), size).write(writer)?;
+
+        writer.write_u32::<BigEndian>(0)?; // reserved
+        writer.write_u16::<BigEndian>(0)?; // reserved
+        writer.write_u16::<BigEndian>(self.data_reference_index)?;
+
+        writer.write_u64::<BigEndian>(0)?; // reserved
+        writer.write_u16::<BigEndian>(self.channelcount)?;
+        writer.write_u16::<BigEndian>(self.samplesize)?;
+        writer.write_u32::<BigEndian>(0)?; // reserved
+        writer.write_u32::<BigEndian>(self.samplerate.raw_value())?;
+
+        let known = [BoxType::DopsBox, BoxType::BtrtBox];
+        for child in child_order(&self.child_order, &known, self.unknown_boxes.len()) {
+            match child {
+                Child::Known(BoxType::DopsBox) => {
+                    self.dops.write_box(writer)?;
+                }
+                Child::Known(BoxType::BtrtBox) => {
+                    if let Some(ref btrt) = self.btrt {
+                        btrt.write_box(writer)?;
+                    }
+                }
+                Child::Known(_) => {}
+                Child::Unknown(i) => {
+                    self.unknown_boxes[i].write_box(writer)?;
+                }
+            }
+        }
+
+        Ok(size)
+    }
+}
+
+/// OpusSpecificBox. Like the Ogg Opus ID header, but big endian and without
+/// the magic signature.
+#[derive(Debug, Clone, PartialEq, Default, Serialize)]
+pub struct DopsBox {
+    pub version: u8,
+    pub output_channel_count: u8,
+    pub pre_skip: u16,
+    pub input_sample_rate: u32,
+    pub output_gain: i16,
+    pub channel_mapping_family: u8,
+
+    /// Only present if `channel_mapping_family` isn't 0.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub channel_mapping_table: Option<OpusChannelMappingTable>,
+}
+
+impl DopsBox {
+    pub fn new(config: &OpusConfig) -> Self {
+        Self {
+            version: 0,
+            output_channel_count: config.channel_count,
+            pre_skip: config.pre_skip,
+            input_sample_rate: config.input_sample_rate,
+            output_gain: config.output_gain,
+            channel_mapping_family: config.channel_mapping_family,
+            channel_mapping_table: config.channel_mapping_table.clone(),
+        }
+    }
+}
+
+impl Mp4Box for DopsBox {
+    fn box_type(&self) -> BoxType {
+        BoxType::DopsBox
+    }
+
+    fn box_size(&self) -> u64 {
+        let mut size = HEADER_SIZE + 11;
+        if let Some(ref table) = self.channel_mapping_table {
+            size += 2 + table.channel_mapping.len() as u64;
+        }
+        size
+    }
+
+    fn to_json(&self) -> Result<String> {
+        Ok(serde_json::to_string(&self).unwrap())
+    }
+
+    fn summary(&self) -> Result<String> {
+        let s = format!("output_channel_count={} pre_skip={} input_sample_rate={} channel_mapping_family={}",
+            self.output_channel_count, self.pre_skip, self.input_sample_rate,
+            self.channel_mapping_family);
+        Ok(s)
+    }
+}
+
+impl<R: Read + Seek> ReadBox<&mut R> for DopsBox {
+    fn read_box(reader: &mut R, size: u64) -> Result<Self> {
+        let start = box_start(reader)?;
+
+        let version = reader.read_u8()?;
+        let output_channel_count = reader.read_u8()?;
+        let pre_skip = reader.read_u16::<BigEndian>()?;
+        let input_sample_rate = reader.read_u32::<BigEndian>()?;
+        let output_gain = reader.read_i16::<BigEndian>()?;
+        let channel_mapping_family = reader.read_u8()?;
+
+        let channel_mapping_table = if channel_mapping_family != 0 {
+            let stream_count = reader.read_u8()?;
+            let coupled_count = reader.read_u8()?;
+            let mut channel_mapping = vec![0u8; output_channel_count as usize];
+            reader.read_exact(&mut channel_mapping)?;
+            Some(OpusChannelMappingTable {
+                stream_count,
+                coupled_count,
+                channel_mapping,
+            })
+        } else {
+            None
+        };
+
+        skip_bytes_to(reader, start + size)?;
+
+        Ok(DopsBox {
+            version,
+            output_channel_count,
+            pre_skip,
+            input_sample_rate,
+            output_gain,
+            channel_mapping_family,
+            channel_mapping_table,
+        })
+    }
+}
+
+impl<W: Write> WriteBox<&mut W> for DopsBox {
+    fn write_box(&self, writer: &mut W) -> Result<u64> {
+        let size = self.box_size();
+        BoxHeader::new(self.box_type(), size).write(writer)?;
+
+        writer.write_u8(self.version)?;
+        writer.write_u8(self.output_channel_count)?;
+        writer.write_u16::<BigEndian>(self.pre_skip)?;
+        writer.write_u32::<BigEndian>(self.input_sample_rate)?;
+        writer.write_i16::<BigEndian>(self.output_gain)?;
+        writer.write_u8(self.channel_mapping_family)?;
+        if let Some(ref table) = self.channel_mapping_table {
+            writer.write_u8(table.stream_count)?;
+            writer.write_u8(table.coupled_count)?;
+            writer.write_all(&table.channel_mapping)?;
+        }
+
+        Ok(size)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mp4box::BoxHeader;
+    use std::io::Cursor;
+
+    #[test]
+    fn test_opus() {
+        let src_box = OpusBox::new(&OpusConfig {
+            channel_count: 2,
+            pre_skip: 312,
+            input_sample_rate: 48000,
+            output_gain: 0,
+            ..OpusConfig::default()
+        });
+        let mut buf = Vec::new();
+        src_box.write_box(&mut buf).unwrap();
+        assert_eq!(buf.len(), src_box.box_size() as usize);
+
+        let mut reader = Cursor::new(&buf);
+        let header = BoxHeader::read(&mut reader).unwrap();
+        assert_eq!(header.name, BoxType::OpusBox);
+        assert_eq!(src_box.box_size(), header.size);
+
+        let dst_box = OpusBox::read_box(&mut reader, header.size).unwrap();
+        assert_eq!(src_box, dst_box);
+    }
+
+    #[test]
+    fn test_dops_channel_mapping() {
+        // 5.1 surround.
+        let src_box = DopsBox {
+            version: 0,
+            output_channel_count: 6,
+            pre_skip: 312,
+            input_sample_rate: 44100,
+            output_gain: -256,
+            channel_mapping_family: 1,
+            channel_mapping_table: Some(OpusChannelMappingTable {
+                stream_count: 4,
+                coupled_count: 2,
+                channel_mapping: vec![0, 4, 1, 2, 3, 5],
+            }),
+        };
+        let mut buf = Vec::new();
+        src_box.write_box(&mut buf).unwrap();
+        assert_eq!(buf.len(), src_box.box_size() as usize);
+
+        let mut reader = Cursor::new(&buf);
+        let header = BoxHeader::read(&mut reader).unwrap();
+        assert_eq!(header.name, BoxType::DopsBox);
+        assert_eq!(src_box.box_size(), header.size);
+
+        let dst_box = DopsBox::read_box(&mut reader, header.size).unwrap();
+        assert_eq!(src_box, dst_box);
+    }
+}
@@ -0,0 +1,167 @@
+use std::io::{Read, Write};
+use serde::{Serialize};
+
+use crate::mp4box::*;
+
+/// A box this crate doesn't parse, kept as its type and payload so it can be
+/// written back unchanged.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct RawBox {
+    #[serde(skip)]
+    pub name: BoxType,
+    pub usertype: Option<[u8; 16]>,
+
+    #[serde(skip)]
+    pub data: Vec<u8>,
+}
+
+impl RawBox {
+    /// Reads the payload of the box whose header was just read.
+    pub fn read<R: Read>(reader: &mut R, header: &BoxHeader) -> Result<Self> {
+        let len = header.size.saturating_sub(header.header_size);
+        let mut data = Vec::new();
+        reader.by_ref().take(len).read_to_end(&mut data)?;
+        if data.len() as u64 != len {
+            return Err(Error::InvalidData("box extends past its container"));
+        }
+
+        Ok(RawBox {
+            name: header.name,
+            usertype: header.usertype,
+            data,
+        })
+    }
+
+    pub fn get_type(&self) -> BoxType {
+        self.name
+    }
+
+    pub fn get_size(&self) -> u64 {
+        self.header().size
+    }
+
+    fn header(&self) -> BoxHeader {
+        let mut header_size = HEADER_SIZE;
+        if self.usertype.is_some() {
+            header_size += USERTYPE_SIZE;
+        }
+        let mut size = header_size + self.data.len() as u64;
+        if size > u32::MAX as u64 {
+            header_size += HEADER_LARGE_SIZE - HEADER_SIZE;
+            size += HEADER_LARGE_SIZE - HEADER_SIZE;
+        }
+        BoxHeader {
+            name: self.name,
+            size,
+            header_size,
+            usertype: self.usertype,
+        }
+    }
+}
+
+impl Mp4Box for RawBox {
+    fn box_type(&self) -> BoxType {
+        self.get_type()
+    }
+
+    fn box_size(&self) -> u64 {
+        self.get_size()
+    }
+
+    fn to_json(&self) -> Result<String> {
+        Ok(serde_json::to_string(&self).unwrap())
+    }
+
+    fn summary(&self) -> Result<String> {
+        let s = format!("type={} data_size={}", self.name, self.data.len());
+        Ok(s)
+    }
+}
+
+impl<W: Write> WriteBox<&mut W> for RawBox {
+    fn write_box(&self, writer: &mut W) -> Result<u64> {
+        let header = self.header();
+        header.write(writer)?;
+
+        writer.write_all(&self.data)?;
+
+        Ok(header.size)
+    }
+}
+
+/// A child of a sample entry, in the order returned by [child_order].
+pub(crate) enum Child {
+    Known(BoxType),
+    Unknown(usize),
+}
+
+/// Order to write a sample entry's children in: as recorded in `order` when
+/// the entry was read, then any of the `known` types it doesn't mention, then
+/// the unknown children it doesn't account for.
+pub(crate) fn child_order(order: &[BoxType], known: &[BoxType], unknown_count: usize) -> Vec<Child> {
+    let mut children = Vec::new();
+    let mut unknown = 0;
+    for (i, name) in order.iter().enumerate() {
+        if known.contains(name) {
+            // Only the last of repeated known children is kept when reading.
+            if !order[..i].contains(name) {
+                children.push(Child::Known(*name));
+            }
+        } else if unknown < unknown_count {
+            children.push(Child::Unknown(unknown));
+            unknown += 1;
+        }
+    }
+    for name in known.iter() {
+        if !order.contains(name) {
+            children.push(Child::Known(*name));
+        }
+    }
+    children.extend((unknown..unknown_count).map(Child::Unknown));
+    children
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mp4box::BoxHeader;
+    use std::io::Cursor;
+
+    #[test]
+    fn test_raw() {
+        let src_box = RawBox {
+            name: BoxType::UnknownBox(u32::from_be_bytes(*b"pasp")),
+            usertype: None,
+            data: vec![0, 0, 0, 1, 0, 0, 0, 1],
+        };
+        let mut buf = Vec::new();
+        src_box.write_box(&mut buf).unwrap();
+        assert_eq!(buf.len(), src_box.box_size() as usize);
+
+        let mut reader = Cursor::new(&buf);
+        let header = BoxHeader::read(&mut reader).unwrap();
+        assert_eq!(src_box.box_size(), header.size);
+
+        let dst_box = RawBox::read(&mut reader, &header).unwrap();
+        assert_eq!(src_box, dst_box);
+    }
+
+    #[test]
+    fn test_raw_uuid() {
+        let src_box = RawBox {
+            name: BoxType::UuidBox,
+            usertype: Some([7; 16]),
+            data: vec![1, 2, 3],
+        };
+        let mut buf = Vec::new();
+        src_box.write_box(&mut buf).unwrap();
+        assert_eq!(buf.len(), src_box.box_size() as usize);
+
+        let mut reader = Cursor::new(&buf);
+        let header = BoxHeader::read(&mut reader).unwrap();
+        assert_eq!(header.usertype, Some([7; 16]));
+
+        let dst_box = RawBox::read(&mut reader, &header).unwrap();
+        assert_eq!(src_box, dst_box);
+    }
+}
@@ -5,6 +5,7 @@ use crate::mp4box::*;
 use crate::mp4box::{
     co64::Co64Box,
     ctts::CttsBox,
+    raw::RawBox,
     stco::StcoBox,
     stsc::StscBox,
     stsd::StsdBox,
@@ -31,6 +32,9 @@ pub struct StblBox {
 
     #[serde(skip_serializing_if = "Option::is_none")]
     pub co64: Option<Co64Box>,
+
+    #[serde(skip)]
+    pub unknown_boxes: Vec<RawBox>,
 }
 
 impl StblBox {
@@ -56,6 +60,9 @@ impl StblBox {
         if let Some(ref co64) = self.co64 {
             size += co64.box_size();
         }
+        for unknown in self.unknown_boxes.iter() {
+            size += unknown.box_size();
+        }
         size
     }
 }
@@ -91,13 +98,15 @@ impl<R: Read + Seek> ReadBox<&mut R> for StblBox {
         let mut stsz = None;
         let mut stco = None;
         let mut co64 = None;
+        let mut unknown_boxes = Vec::new();
 
         let mut current = reader.seek(SeekFrom::Current(0))?;
         let end = start + size;
         while current < end {
             // Get box header.
-            let header = BoxHeader::read(reader)?;
-            let BoxHeader { name, size: s } = header;
+            let header = BoxHeader::read_in(reader, end)?;
+            let BoxHeader { name, .. } = header;
+            let s = header.read_size();
 
             match name {
                 BoxType::StsdBox => {
@@ -125,8 +134,7 @@ impl<R: Read + Seek> ReadBox<&mut R> for StblBox {
                     co64 = Some(Co64Box::read_box(reader, s)?);
                 }
                 _ => {
-                    // XXX warn!()
-                    skip_box(reader, s)?;
+                    unknown_boxes.push(RawBox::read(reader, &header)?);
                 }
             }
             current = reader.seek(SeekFrom::Current(0))?;
@@ -159,6 +167,7 @@ impl<R: Read + Seek> ReadBox<&mut R> for StblBox {
             stsz: stsz.unwrap(),
             stco: stco,
             co64: co64,
+            unknown_boxes,
         })
     }
 }
@@ -185,6 +194,10 @@ impl<W: Write> WriteBox<&mut W> for StblBox {
             co64.write_box(writer)?;
         }
 
+        for unknown in self.unknown_boxes.iter() {
+            unknown.write_box(writer)?;
+        }
+
         Ok(size)
     }
 }
@@ -3,7 +3,9 @@ use std::io::{Read, Seek, Write};
 use serde::{Serialize};
 
 use crate::mp4box::*;
-use crate::mp4box::{avc1::Avc1Box, hev1::Hev1Box, mp4a::Mp4aBox, tx3g::Tx3gBox};
+use crate::mp4box::{avc1::Avc1Box, btrt::BtrtBox, hev1::Hev1Box, mp4a::Mp4aBox, raw::RawBox, tx3g::Tx3gBox};
+use crate::mp4box::av01::Av01Box;
+use crate::mp4box::opus::OpusBox;
 use crate::mp4box::vp09::Vp09Box;
 
 #[derive(Debug, Clone, PartialEq, Default, Serialize)]
@@ -11,20 +13,29 @@ pub struct StsdBox {
     pub version: u8,
     pub flags: u32,
 
-    #[serde(skip_serializing_if = "Option::is_none")]
-    pub avc1: Option<Avc1Box>,
-
-    #[serde(skip_serializing_if = "Option::is_none")]
-    pub hev1: Option<Hev1Box>,
-    
-    #[serde(skip_serializing_if = "Option::is_none")]
-    pub vp09: Option<Vp09Box>,
-
-    #[serde(skip_serializing_if = "Option::is_none")]
-    pub mp4a: Option<Mp4aBox>,
+    /// Sample entries in order; a sample description index of 1 refers to
+    /// the first entry.
+    pub entries: Vec<SampleEntry>,
+}
 
-    #[serde(skip_serializing_if = "Option::is_none")]
-    pub tx3g: Option<Tx3gBox>,
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SampleEntry {
+    Avc1(Avc1Box),
+    /// avc2, avc3 and avc4 share the avc1 layout. avc3 and avc4 may carry
+    /// parameter sets in the samples.
+    Avc2(Avc1Box),
+    Avc3(Avc1Box),
+    Avc4(Avc1Box),
+    Hev1(Hev1Box),
+    /// Same layout as hev1, but parameter sets are only in the hvcC box.
+    Hvc1(Hev1Box),
+    Vp09(Vp09Box),
+    Av01(Av01Box),
+    Mp4a(Mp4aBox),
+    Opus(OpusBox),
+    Tx3g(Tx3gBox),
+    Unknown(RawBox),
 }
 
 impl StsdBox {
@@ -34,19 +45,92 @@ impl StsdBox {
 
     pub fn get_size(&self) -> u64 {
         let mut size = HEADER_SIZE + HEADER_EXT_SIZE + 4;
-        if let Some(ref avc1) = self.avc1 {
-            size += avc1.box_size();
-        } else if let Some(ref hev1) = self.hev1 {
-            size += hev1.box_size();
-        } else if let Some(ref vp09) = self.vp09 {
-            size += vp09.box_size();
-        } else if let Some(ref mp4a) = self.mp4a {
-            size += mp4a.box_size();
-        } else if let Some(ref tx3g) = self.tx3g {
-            size += tx3g.box_size();
+        for entry in self.entries.iter() {
+            size += entry.box_size();
         }
         size
     }
+
+    /// Returns the entry for a 1-based sample description index.
+    pub fn entry(&self, sample_description_index: u32) -> Option<&SampleEntry> {
+        let idx = sample_description_index.checked_sub(1)?;
+        self.entries.get(idx as usize)
+    }
+
+    pub fn avc1(&self) -> Option<&Avc1Box> {
+        self.entries.iter().find_map(|entry| match entry {
+            SampleEntry::Avc1(avc1) => Some(avc1),
+            _ => None,
+        })
+    }
+
+    /// Returns the first avc1, avc2, avc3 or avc4 entry.
+    pub fn avc(&self) -> Option<&Avc1Box> {
+        self.entries.iter().find_map(|entry| match entry {
+            SampleEntry::Avc1(avc)
+            | SampleEntry::Avc2(avc)
+            | SampleEntry::Avc3(avc)
+            | SampleEntry::Avc4(avc) => Some(avc),
+            _ => None,
+        })
+    }
+
+    pub fn hev1(&self) -> Option<&Hev1Box> {
+        self.entries.iter().find_map(|entry| match entry {
+            SampleEntry::Hev1(hev1) => Some(hev1),
+            _ => None,
+        })
+    }
+
+    pub fn hvc1(&self) -> Option<&Hev1Box> {
+        self.entries.iter().find_map(|entry| match entry {
+            SampleEntry::Hvc1(hvc1) => Some(hvc1),
+            _ => None,
+        })
+    }
+
+    /// Returns the first hev1 or hvc1 entry.
+    pub fn hevc(&self) -> Option<&Hev1Box> {
+        self.entries.iter().find_map(|entry| match entry {
+            SampleEntry::Hev1(hevc) | SampleEntry::Hvc1(hevc) => Some(hevc),
+            _ => None,
+        })
+    }
+
+    pub fn vp09(&self) -> Option<&Vp09Box> {
+        self.entries.iter().find_map(|entry| match entry {
+            SampleEntry::Vp09(vp09) => Some(vp09),
+            _ => None,
+        })
+    }
+
+    pub fn av01(&self) -> Option<&Av01Box> {
+        self.entries.iter().find_map(|entry| match entry {
+            SampleEntry::Av01(av01) => Some(av01),
+            _ => None,
+        })
+    }
+
+    pub fn mp4a(&self) -> Option<&Mp4aBox> {
+        self.entries.iter().find_map(|entry| match entry {
+            SampleEntry::Mp4a(mp4a) => Some(mp4a),
+            _ => None,
+        })
+    }
+
+    pub fn opus(&self) -> Option<&OpusBox> {
+        self.entries.iter().find_map(|entry| match entry {
+            SampleEntry::Opus(opus) => Some(opus),
+            _ => None,
+        })
+    }
+
+    pub fn tx3g(&self) -> Option<&Tx3gBox> {
+        self.entries.iter().find_map(|entry| match entry {
+            SampleEntry::Tx3g(tx3g) => Some(tx3g),
+            _ => None,
+        })
+    }
 }
 
 impl Mp4Box for StsdBox {
@@ -63,7 +147,7 @@ impl Mp4Box for StsdBox {
     }
 
     fn summary(&self) -> Result<String> {
-        let s = format!("");
+        let s = format!("entry_count={}", self.entries.len());
         Ok(s)
     }
 }
@@ -71,50 +155,46 @@ impl Mp4Box for StsdBox {
 impl<R: Read + Seek> ReadBox<&mut R> for StsdBox {
     fn read_box(reader: &mut R, size: u64) -> Result<Self> {
         let start = box_start(reader)?;
+        let end = start + size;
 
         let (version, flags) = read_box_header_ext(reader)?;
 
-        reader.read_u32::<BigEndian>()?; // XXX entry_count
-
-        let mut avc1 = None;
-        let mut hev1 = None;
-        let mut vp09 = None;
-        let mut mp4a = None;
-        let mut tx3g = None;
-
-        // Get box header.
-        let header = BoxHeader::read(reader)?;
-        let BoxHeader { name, size: s } = header;
-
-        match name {
-            BoxType::Avc1Box => {
-                avc1 = Some(Avc1Box::read_box(reader, s)?);
-            }
-            BoxType::Hev1Box => {
-                hev1 = Some(Hev1Box::read_box(reader, s)?);
-            }
-            BoxType::Vp09Box => {
-                vp09 = Some(Vp09Box::read_box(reader, s)?);
-            }
-            BoxType::Mp4aBox => {
-                mp4a = Some(Mp4aBox::read_box(reader, s)?);
-            }
-            BoxType::Tx3gBox => {
-                tx3g = Some(Tx3gBox::read_box(reader, s)?);
-            }
-            _ => {}
+        let entry_count = reader.read_u32::<BigEndian>()?;
+
+        let mut entries = Vec::new();
+        let mut current = reader.stream_position()?;
+        while entries.len() < entry_count as usize && current + HEADER_SIZE <= end {
+            // Get box header.
+            let header = BoxHeader::read_in(reader, end)?;
+            let BoxHeader { name, .. } = header;
+            let s = header.read_size();
+
+            let entry = match name {
+                BoxType::Avc1Box => SampleEntry::Avc1(Avc1Box::read_box(reader, s)?),
+                BoxType::Avc2Box => SampleEntry::Avc2(Avc1Box::read_box(reader, s)?),
+                BoxType::Avc3Box => SampleEntry::Avc3(Avc1Box::read_box(reader, s)?),
+                BoxType::Avc4Box => SampleEntry::Avc4(Avc1Box::read_box(reader, s)?),
+                BoxType::Hev1Box => SampleEntry::Hev1(Hev1Box::read_box(reader, s)?),
+                BoxType::Hvc1Box => SampleEntry::Hvc1(Hev1Box::read_box(reader, s)?),
+                BoxType::Vp09Box => SampleEntry::Vp09(Vp09Box::read_box(reader, s)?),
+                BoxType::Av01Box => SampleEntry::Av01(Av01Box::read_box(reader, s)?),
+                BoxType::Mp4aBox => SampleEntry::Mp4a(Mp4aBox::read_box(reader, s)?),
+                BoxType::OpusBox => SampleEntry::Opus(OpusBox::read_box(reader, s)?),
+                BoxType::Tx3gBox => SampleEntry::Tx3g(Tx3gBox::read_box(reader, s)?),
+                _ => SampleEntry::Unknown(RawBox::read(reader, &header)?),
+            };
+            entries.push(entry);
+
+            current += header.size;
+            skip_bytes_to(reader, current)?;
         }
 
-        skip_bytes_to(reader, start + size)?;
+        skip_bytes_to(reader, end)?;
 
         Ok(StsdBox {
             version,
             flags,
-            avc1,
-            hev1,
-            vp09,
-            mp4a,
-            tx3g,
+            entries,
         })
     }
 }
@@ -126,20 +206,173 @@ impl<W: Write> WriteBox<&mut W> for StsdBox {
 
         write_box_header_ext(writer, self.version, self.flags)?;
 
-        writer.write_u32::<BigEndian>(1)?; // entry_count
-
-        if let Some(ref avc1) = self.avc1 {
-            avc1.write_box(writer)?;
-        } else if let Some(ref hev1) = self.hev1 {
-            hev1.write_box(writer)?;
-        } else if let Some(ref vp09) = self.vp09 {
-            vp09.write_box(writer)?;
-        } else if let Some(ref mp4a) = self.mp4a {
-            mp4a.write_box(writer)?;
-        } else if let Some(ref tx3g) = self.tx3g {
-            tx3g.write_box(writer)?;
+        writer.write_u32::<BigEndian>(self.entries.len() as u32)?;
+        for entry in self.entries.iter() {
+            entry.write_box(writer)?;
         }
 
         Ok(size)
     }
 }
+
+impl SampleEntry {
+    pub fn btrt(&self) -> Option<&BtrtBox> {
+        match self {
+            SampleEntry::Avc1(avc)
+            | SampleEntry::Avc2(avc)
+            | SampleEntry::Avc3(avc)
+            | SampleEntry::Avc4(avc) => avc.btrt.as_ref(),
+            SampleEntry::Hev1(hevc) | SampleEntry::Hvc1(hevc) => hevc.btrt.as_ref(),
+            SampleEntry::Vp09(vp09) => vp09.btrt.as_ref(),
+            SampleEntry::Av01(av01) => av01.btrt.as_ref(),
+            SampleEntry::Mp4a(mp4a) => mp4a.btrt.as_ref(),
+            SampleEntry::Opus(opus) => opus.btrt.as_ref(),
+            SampleEntry::Tx3g(_) | SampleEntry::Unknown(_) => None,
+        }
+    }
+}
+
+impl Mp4Box for SampleEntry {
+    fn box_type(&self) -> BoxType {
+        match self {
+            SampleEntry::Avc1(avc1) => avc1.box_type(),
+            SampleEntry::Avc2(_) => BoxType::Avc2Box,
+            SampleEntry::Avc3(_) => BoxType::Avc3Box,
+            SampleEntry::Avc4(_) => BoxType::Avc4Box,
+            SampleEntry::Hev1(hev1) => hev1.box_type(),
+            SampleEntry::Hvc1(_) => BoxType::Hvc1Box,
+            SampleEntry::Vp09(vp09) => vp09.box_type(),
+            SampleEntry::Av01(av01) => av01.box_type(),
+            SampleEntry::Mp4a(mp4a) => mp4a.box_type(),
+            SampleEntry::Opus(opus) => opus.box_type(),
+            SampleEntry::Tx3g(tx3g) => tx3g.box_type(),
+            SampleEntry::Unknown(raw) => raw.box_type(),
+        }
+    }
+
+    fn box_size(&self) -> u64 {
+        match self {
+            SampleEntry::Avc1(avc)
+            | SampleEntry::Avc2(avc)
+            | SampleEntry::Avc3(avc)
+            | SampleEntry::Avc4(avc) => avc.box_size(),
+            SampleEntry::Hev1(hevc) | SampleEntry::Hvc1(hevc) => hevc.box_size(),
+            SampleEntry::Vp09(vp09) => vp09.box_size(),
+            SampleEntry::Av01(av01) => av01.box_size(),
+            SampleEntry::Mp4a(mp4a) => mp4a.box_size(),
+            SampleEntry::Opus(opus) => opus.box_size(),
+            SampleEntry::Tx3g(tx3g) => tx3g.box_size(),
+            SampleEntry::Unknown(raw) => raw.box_size(),
+        }
+    }
+
+    fn to_json(&self) -> Result<String> {
+        Ok(serde_json::to_string(&self).unwrap())
+    }
+
+    fn summary(&self) -> Result<String> {
+        match self {
+            SampleEntry::Avc1(avc)
+            | SampleEntry::Avc2(avc)
+            | SampleEntry::Avc3(avc)
+            | SampleEntry::Avc4(avc) => avc.summary(),
+            SampleEntry::Hev1(hevc) | SampleEntry::Hvc1(hevc) => hevc.summary(),
+            SampleEntry::Vp09(vp09) => vp09.summary(),
+            SampleEntry::Av01(av01) => av01.summary(),
+            SampleEntry::Mp4a(mp4a) => mp4a.summary(),
+            SampleEntry::Opus(opus) => opus.summary(),
+            SampleEntry::Tx3g(tx3g) => tx3g.summary(),
+            SampleEntry::Unknown(raw) => raw.summary(),
+        }
+    }
+}
+
+impl<W: Write> WriteBox<&mut W> for SampleEntry {
+    fn write_box(&self, writer: &mut W) -> Result<u64> {
+        match self {
+            SampleEntry::Avc1(avc1) => avc1.write_box(writer),
+            SampleEntry::Avc2(avc2) => avc2.write_entry(writer, BoxType::Avc2Box),
+            SampleEntry::Avc3(avc3) => avc3.write_entry(writer, BoxType::Avc3Box),
+            SampleEntry::Avc4(avc4) => avc4.write_entry(writer, BoxType::Avc4Box),
+            SampleEntry::Hev1(hev1) => hev1.write_box(writer),
+            SampleEntry::Hvc1(hvc1) => hvc1.write_entry(writer, BoxType::Hvc1Box),
+            SampleEntry::Vp09(vp09) => vp09.write_box(writer),
+            SampleEntry::Av01(av01) => av01.write_box(writer),
+            SampleEntry::Mp4a(mp4a) => mp4a.write_box(writer),
+            SampleEntry::Opus(opus) => opus.write_box(writer),
+            SampleEntry::Tx3g(tx3g) => tx3g.write_box(writer),
+            SampleEntry::Unknown(raw) => raw.write_box(writer),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mp4box::BoxHeader;
+    use crate::mp4box::hev1::HvcCBox;
+    use std::io::Cursor;
+
+    #[test]
+    fn test_stsd_entries() {
+        let src_box = StsdBox {
+            version: 0,
+            flags: 0,
+            entries: vec![
+                SampleEntry::Tx3g(Tx3gBox::default()),
+                SampleEntry::Unknown(RawBox {
+                    name: BoxType::UnknownBox(0x77766474), // wvtt
+                    usertype: None,
+                    data: vec![0, 0, 0, 0, 0, 0, 0, 1],
+                }),
+                SampleEntry::Tx3g(Tx3gBox {
+                    data_reference_index: 2,
+                    ..Tx3gBox::default()
+                }),
+            ],
+        };
+        let mut buf = Vec::new();
+        src_box.write_box(&mut buf).unwrap();
+        assert_eq!(buf.len(), src_box.box_size() as usize);
+
+        let mut reader = Cursor::new(&buf);
+        let header = BoxHeader::read(&mut reader).unwrap();
+        assert_eq!(header.name, BoxType::StsdBox);
+        assert_eq!(src_box.box_size(), header.size);
+
+        let dst_box = StsdBox::read_box(&mut reader, header.size).unwrap();
+        assert_eq!(src_box, dst_box);
+        assert_eq!(dst_box.entry(3), src_box.entries.get(2));
+        assert!(dst_box.entry(0).is_none());
+        assert_eq!(dst_box.tx3g(), Some(&Tx3gBox::default()));
+    }
+
+    #[test]
+    fn test_stsd_hvc1() {
+        let hevc = Hev1Box {
+            data_reference_index: 1,
+            width: 320,
+            height: 240,
+            hvcc: HvcCBox::new(&[0x40, 0x01], &[0x42, 0x01], &[0x44, 0x01]),
+            ..Hev1Box::default()
+        };
+        let src_box = StsdBox {
+            version: 0,
+            flags: 0,
+            entries: vec![SampleEntry::Hvc1(hevc.clone())],
+        };
+        let mut buf = Vec::new();
+        src_box.write_box(&mut buf).unwrap();
+        assert_eq!(buf.len(), src_box.box_size() as usize);
+        assert_eq!(&buf[20..24], b"hvc1");
+
+        let mut reader = Cursor::new(&buf);
+        let header = BoxHeader::read(&mut reader).unwrap();
+        let dst_box = StsdBox::read_box(&mut reader, header.size).unwrap();
+        assert_eq!(src_box, dst_box);
+        assert_eq!(dst_box.entries[0].box_type(), BoxType::Hvc1Box);
+        assert!(dst_box.hev1().is_none());
+        assert_eq!(dst_box.hvc1(), Some(&hevc));
+        assert_eq!(dst_box.hevc(), Some(&hevc));
+    }
+}
@@ -53,8 +53,13 @@ impl<R: Read + Seek> ReadBox<&mut R> for StszBox {
 
         let sample_size = reader.read_u32::<BigEndian>()?;
         let sample_count = reader.read_u32::<BigEndian>()?;
-        let mut sample_sizes = Vec::with_capacity(sample_count as usize);
+        let mut sample_sizes = Vec::new();
         if sample_size == 0 {
+            let remaining = size.saturating_sub(reader.stream_position()? - start);
+            if sample_count as u64 * 4 > remaining {
+                return Err(Error::InvalidData("stsz sample count exceeds box size"));
+            }
+            sample_sizes.reserve(sample_count as usize);
             for _ in 0..sample_count {
                 let sample_number = reader.read_u32::<BigEndian>()?;
                 sample_sizes.push(sample_number);
@@ -2,16 +2,22 @@ use std::io::{Read, Seek, SeekFrom, Write};
 use serde::{Serialize};
 
 use crate::mp4box::*;
-use crate::mp4box::{edts::EdtsBox, mdia::MdiaBox, tkhd::TkhdBox};
+use crate::mp4box::{edts::EdtsBox, mdia::MdiaBox, tkhd::TkhdBox, tref::TrefBox, raw::RawBox};
 
 #[derive(Debug, Clone, PartialEq, Default, Serialize)]
 pub struct TrakBox {
     pub tkhd: TkhdBox,
 
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tref: Option<TrefBox>,
+
     #[serde(skip_serializing_if = "Option::is_none")]
     pub edts: Option<EdtsBox>,
 
     pub mdia: MdiaBox,
+
+    #[serde(skip)]
+    pub unknown_boxes: Vec<RawBox>,
 }
 
 impl TrakBox {
@@ -22,10 +28,16 @@ impl TrakBox {
     pub fn get_size(&self) -> u64 {
         let mut size = HEADER_SIZE;
         size += self.tkhd.box_size();
+        if let Some(ref tref) = self.tref {
+            size += tref.box_size();
+        }
         if let Some(ref edts) = self.edts {
             size += edts.box_size();
         }
         size += self.mdia.box_size();
+        for unknown in self.unknown_boxes.iter() {
+            size += unknown.box_size();
+        }
         size
     }
 }
@@ -54,20 +66,26 @@ impl<R: Read + Seek> ReadBox<&mut R> for TrakBox {
         let start = box_start(reader)?;
 
         let mut tkhd = None;
+        let mut tref = None;
         let mut edts = None;
         let mut mdia = None;
+        let mut unknown_boxes = Vec::new();
 
         let mut current = reader.seek(SeekFrom::Current(0))?;
         let end = start + size;
         while current < end {
             // Get box header.
-            let header = BoxHeader::read(reader)?;
-            let BoxHeader { name, size: s } = header;
+            let header = BoxHeader::read_in(reader, end)?;
+            let BoxHeader { name, .. } = header;
+            let s = header.read_size();
 
             match name {
                 BoxType::TkhdBox => {
                     tkhd = Some(TkhdBox::read_box(reader, s)?);
                 }
+                BoxType::TrefBox => {
+                    tref = Some(TrefBox::read_box(reader, s)?);
+                }
                 BoxType::EdtsBox => {
                     edts = Some(EdtsBox::read_box(reader, s)?);
                 }
@@ -75,8 +93,7 @@ impl<R: Read + Seek> ReadBox<&mut R> for TrakBox {
                     mdia = Some(MdiaBox::read_box(reader, s)?);
                 }
                 _ => {
-                    // XXX warn!()
-                    skip_box(reader, s)?;
+                    unknown_boxes.push(RawBox::read(reader, &header)?);
                 }
             }
 
@@ -94,8 +111,10 @@ impl<R: Read + Seek> ReadBox<&mut R> for TrakBox {
 
         Ok(TrakBox {
             tkhd: tkhd.unwrap(),
+            tref,
             edts,
             mdia: mdia.unwrap(),
+            unknown_boxes,
         })
     }
 }
@@ -106,11 +125,18 @@ impl<W: Write> WriteBox<&mut W> for TrakBox {
         BoxHeader::new(self.box_type(), size).write(writer)?;
 
         self.tkhd.write_box(writer)?;
+        if let Some(ref tref) = self.tref {
+            tref.write_box(writer)?;
+        }
         if let Some(ref edts) = self.edts {
             edts.write_box(writer)?;
         }
         self.mdia.write_box(writer)?;
 
+        for unknown in self.unknown_boxes.iter() {
+            unknown.write_box(writer)?;
+        }
+
         Ok(size)
     }
 }
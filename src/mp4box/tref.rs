@@ -0,0 +1,152 @@
+use byteorder::{BigEndian, ReadBytesExt, WriteBytesExt};
+use std::io::{Read, Seek, Write};
+use serde::{Serialize};
+
+use crate::mp4box::*;
+
+#[derive(Debug, Clone, PartialEq, Default, Serialize)]
+pub struct TrefBox {
+    pub references: Vec<TrackReference>,
+}
+
+/// A typed reference from a track to others, such as `chap` for a chapter
+/// track, `tmcd` for a timecode track or `cdsc` for the track a metadata
+/// track describes.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct TrackReference {
+    pub reference_type: FourCC,
+    pub track_ids: Vec<u32>,
+}
+
+impl TrackReference {
+    fn box_size(&self) -> u64 {
+        HEADER_SIZE + 4 * self.track_ids.len() as u64
+    }
+}
+
+impl TrefBox {
+    pub fn get_type(&self) -> BoxType {
+        BoxType::TrefBox
+    }
+
+    pub fn get_size(&self) -> u64 {
+        let mut size = HEADER_SIZE;
+        for reference in self.references.iter() {
+            size += reference.box_size();
+        }
+        size
+    }
+
+    /// Adds `track_id` to the references of `reference_type`.
+    pub fn add(&mut self, reference_type: FourCC, track_id: u32) {
+        match self
+            .references
+            .iter_mut()
+            .find(|reference| reference.reference_type == reference_type)
+        {
+            Some(reference) => reference.track_ids.push(track_id),
+            None => self.references.push(TrackReference {
+                reference_type,
+                track_ids: vec![track_id],
+            }),
+        }
+    }
+}
+
+impl Mp4Box for TrefBox {
+    fn box_type(&self) -> BoxType {
+        self.get_type()
+    }
+
+    fn box_size(&self) -> u64 {
+        self.get_size()
+    }
+
+    fn to_json(&self) -> Result<String> {
+        Ok(serde_json::to_string(&self).unwrap())
+    }
+
+    fn summary(&self) -> Result<String> {
+        let mut s = Vec::new();
+        for reference in self.references.iter() {
+            s.push(format!("{}={:?}", reference.reference_type, reference.track_ids));
+        }
+        Ok(s.join(" "))
+    }
+}
+
+impl<R: Read + Seek> ReadBox<&mut R> for TrefBox {
+    fn read_box(reader: &mut R, size: u64) -> Result<Self> {
+        let start = box_start(reader)?;
+
+        let mut references = Vec::new();
+
+        let mut current = reader.stream_position()?;
+        let end = start + size;
+        while current + HEADER_SIZE <= end {
+            let header = BoxHeader::read_in(reader, end)?;
+            let count = (header.size - header.header_size) / 4;
+            let mut track_ids = Vec::with_capacity(count as usize);
+            for _ in 0..count {
+                track_ids.push(reader.read_u32::<BigEndian>()?);
+            }
+            references.push(TrackReference {
+                reference_type: header.name.into(),
+                track_ids,
+            });
+
+            current += header.size;
+            skip_bytes_to(reader, current)?;
+        }
+
+        skip_bytes_to(reader, end)?;
+
+        Ok(TrefBox { references })
+    }
+}
+
+impl<W: Write> WriteBox<&mut W> for TrefBox {
+    fn write_box(&self, writer: &mut W) -> Result<u64> {
+        let size = self.box_size();
+        BoxHeader::new(self.box_type(), size).write(writer)?;
+
+        for reference in self.references.iter() {
+            let name = BoxType::from(u32::from(reference.reference_type));
+            BoxHeader::new(name, reference.box_size()).write(writer)?;
+            for track_id in reference.track_ids.iter() {
+                writer.write_u32::<BigEndian>(*track_id)?;
+            }
+        }
+
+        Ok(size)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mp4box::BoxHeader;
+    use std::io::Cursor;
+
+    #[test]
+    fn test_tref() {
+        let mut src_box = TrefBox::default();
+        src_box.add(str::parse("chap").unwrap(), 3);
+        src_box.add(str::parse("tmcd").unwrap(), 4);
+        src_box.add(str::parse("chap").unwrap(), 5);
+        assert_eq!(src_box.references.len(), 2);
+
+        let mut buf = Vec::new();
+        src_box.write_box(&mut buf).unwrap();
+        assert_eq!(buf.len(), src_box.box_size() as usize);
+
+        let mut reader = Cursor::new(&buf);
+        let header = BoxHeader::read(&mut reader).unwrap();
+        assert_eq!(header.name, BoxType::TrefBox);
+        assert_eq!(src_box.box_size(), header.size);
+
+        let dst_box = TrefBox::read_box(&mut reader, header.size).unwrap();
+        assert_eq!(src_box, dst_box);
+        assert_eq!(dst_box.references[0].track_ids, vec![3, 5]);
+    }
+}
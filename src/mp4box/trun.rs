@@ -98,10 +98,25 @@ impl<R: Read + Seek> ReadBox<&mut R> for TrunBox {
             None
         };
 
-        let mut sample_durations = Vec::with_capacity(sample_count as usize);
-        let mut sample_sizes = Vec::with_capacity(sample_count as usize);
-        let mut sample_flags = Vec::with_capacity(sample_count as usize);
-        let mut sample_cts = Vec::with_capacity(sample_count as usize);
+        let per_sample = [
+            TrunBox::FLAG_SAMPLE_DURATION,
+            TrunBox::FLAG_SAMPLE_SIZE,
+            TrunBox::FLAG_SAMPLE_FLAGS,
+            TrunBox::FLAG_SAMPLE_CTS,
+        ]
+        .iter()
+        .filter(|flag| **flag & flags > 0)
+        .count() as u64;
+        let remaining = size.saturating_sub(reader.stream_position()? - start);
+        if sample_count as u64 * per_sample * 4 > remaining {
+            return Err(Error::InvalidData("trun sample count exceeds box size"));
+        }
+
+        let capacity = if per_sample > 0 { sample_count as usize } else { 0 };
+        let mut sample_durations = Vec::with_capacity(capacity);
+        let mut sample_sizes = Vec::with_capacity(capacity);
+        let mut sample_flags = Vec::with_capacity(capacity);
+        let mut sample_cts = Vec::with_capacity(capacity);
         for _ in 0..sample_count {
             if TrunBox::FLAG_SAMPLE_DURATION & flags > 0 {
                 let duration = reader.read_u32::<BigEndian>()?;
@@ -154,7 +169,9 @@ impl<W: Write> WriteBox<&mut W> for TrunBox {
         if let Some(v) = self.first_sample_flags {
             writer.write_u32::<BigEndian>(v)?;
         }
-        if self.sample_count != self.sample_sizes.len() as u32 {
+        if TrunBox::FLAG_SAMPLE_SIZE & self.flags > 0
+            && self.sample_count != self.sample_sizes.len() as u32
+        {
             return Err(Error::InvalidData("sample count out of sync"));
         }
         for i in 0..self.sample_count as usize {
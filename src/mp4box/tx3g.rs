@@ -3,6 +3,7 @@ use std::io::{Read, Seek, Write};
 use serde::{Serialize};
 
 use crate::mp4box::*;
+use crate::mp4box::raw::RawBox;
 
 #[derive(Debug, Clone, PartialEq, Serialize)]
 pub struct Tx3gBox {
@@ -13,6 +14,9 @@ pub struct Tx3gBox {
     pub bg_color_rgba: RgbaColor,
     pub box_record: [i16; 4],
     pub style_record: [u8; 12],
+
+    #[serde(skip)]
+    pub unknown_boxes: Vec<RawBox>,
 }
 
 #[derive(Debug, Clone, PartialEq, Default, Serialize)]
@@ -38,6 +42,7 @@ impl Default for Tx3gBox {
             },
             box_record: [0, 0, 0, 0],
             style_record: [0, 0, 0, 0, 0, 1, 0, 16, 255, 255, 255, 255],
+            unknown_boxes: Vec::new(),
         }
     }
 }
@@ -48,7 +53,11 @@ impl Tx3gBox {
     }
 
     pub fn get_size(&self) -> u64 {
-        HEADER_SIZE + 6 + 32 
+        let mut size = HEADER_SIZE + 6 + 32;
+        for unknown in self.unknown_boxes.iter() {
+            size += unknown.box_size();
+        }
+        size
     }
 }
 
@@ -112,6 +121,18 @@ impl<R: Read + Seek> ReadBox<&mut R> for Tx3gBox {
             reader.read_u8()?,
         ];
 
+        let mut unknown_boxes = Vec::new();
+
+        let mut current = reader.stream_position()?;
+        let end = start + size;
+        while current + HEADER_SIZE <= end {
+            // ftab and other children aren't parsed yet.
+            let header = BoxHeader::read_in(reader, end)?;
+            unknown_boxes.push(RawBox::read(reader, &header)?);
+
+            current = reader.stream_position()?;
+        }
+
         skip_bytes_to(reader, start + size)?;
 
         Ok(Tx3gBox {
@@ -122,6 +143,7 @@ impl<R: Read + Seek> ReadBox<&mut R> for Tx3gBox {
             bg_color_rgba,
             box_record,
             style_record,
+            unknown_boxes,
         })
     }
 }
@@ -147,6 +169,9 @@ impl<W: Write> WriteBox<&mut W> for Tx3gBox {
         for n in 0..12 {
             writer.write_u8(self.style_record[n])?;
         }
+        for unknown in self.unknown_boxes.iter() {
+            unknown.write_box(writer)?;
+        }
 
         Ok(size)
     }
@@ -173,6 +198,11 @@ mod tests {
             },
             box_record: [0, 0, 0, 0],
             style_record: [0, 0, 0, 0, 0, 1, 0, 16, 255, 255, 255, 255],
+            unknown_boxes: vec![RawBox {
+                name: BoxType::UnknownBox(u32::from_be_bytes(*b"ftab")),
+                usertype: None,
+                data: vec![0, 1, 0, 1, 5, b'S', b'e', b'r', b'i', b'f'],
+            }],
         };
         let mut buf = Vec::new();
         src_box.write_box(&mut buf).unwrap();
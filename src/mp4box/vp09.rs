@@ -1,6 +1,8 @@
 use crate::Mp4Box;
 use crate::mp4box::*;
 use serde::{Serialize};
+use crate::mp4box::btrt::BtrtBox;
+use crate::mp4box::raw::{child_order, Child, RawBox};
 use crate::mp4box::vpcc::VpccBox;
 
 #[derive(Debug, Clone, PartialEq, Default, Serialize)]
@@ -20,6 +22,17 @@ pub struct Vp09Box {
     pub depth: u16,
     pub end_code: u16,
     pub vpcc: VpccBox,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub btrt: Option<BtrtBox>,
+
+    #[serde(skip)]
+    pub unknown_boxes: Vec<RawBox>,
+
+    /// Types of all children in the order they were read, so unknown boxes
+    /// are written back where they were. Empty when there are none.
+    #[serde(skip)]
+    pub child_order: Vec<BoxType>,
 }
 
 impl Vp09Box {
@@ -61,6 +74,9 @@ impl Vp09Box {
                 matrix_coefficients: 0,
                 codec_initialization_data_size: 0,
             },
+            btrt: None,
+            unknown_boxes: Vec::new(),
+            child_order: Vec::new(),
         }
     }
 }
@@ -71,7 +87,14 @@ impl Mp4Box for Vp09Box {
     }
 
     fn box_size(&self) -> u64 {
-        0x6A
+        let mut size = 0x6A;
+        if let Some(ref btrt) = self.btrt {
+            size += btrt.box_size();
+        }
+        for unknown in self.unknown_boxes.iter() {
+            size += unknown.box_size();
+        }
+        size
     }
 
     fn to_json(&self) -> Result<String> {
@@ -113,10 +136,38 @@ impl<R: Read + Seek> ReadBox<&mut R> for Vp09Box {
         let depth: u16 = reader.read_u16::<BigEndian>()?;
         let end_code: u16 = reader.read_u16::<BigEndian>()?;
 
-        let vpcc = {
-            let header = BoxHeader::read(reader)?;
-            VpccBox::read_box(reader, header.size)?
-        };
+        let mut vpcc = None;
+        let mut btrt = None;
+        let mut unknown_boxes = Vec::new();
+        let mut child_order = Vec::new();
+
+        let mut current = reader.stream_position()?;
+        let end = start + size;
+        while current + HEADER_SIZE <= end {
+            let header = BoxHeader::read_in(reader, end)?;
+            let BoxHeader { name, .. } = header;
+            let s = header.read_size();
+            child_order.push(name);
+
+            match name {
+                BoxType::VpccBox => {
+                    vpcc = Some(VpccBox::read_box(reader, s)?);
+                }
+                BoxType::BtrtBox => {
+                    btrt = Some(BtrtBox::read_box(reader, s)?);
+                }
+                _ => {
+                    unknown_boxes.push(RawBox::read(reader, &header)?);
+                }
+            }
+
+            current = reader.stream_position()?;
+        }
+        if unknown_boxes.is_empty() {
+            child_order.clear();
+        }
+
+        let vpcc = vpcc.ok_or(Error::InvalidData("vpcc not found"))?;
 
         skip_bytes_to(reader, start + size)?;
 
@@ -136,6 +187,9 @@ impl<R: Read + Seek> ReadBox<&mut R> for Vp09Box {
             depth,
             end_code,
             vpcc,
+            btrt,
+            unknown_boxes,
+            child_order,
         })
     }
 }
@@ -161,7 +215,23 @@ impl<W: Write> WriteBox<&mut W> for Vp09Box {
         writer.write_all(&self.compressorname)?;
         writer.write_u16::<BigEndian>(self.depth)?;
         writer.write_u16::<BigEndian>(self.end_code)?;
-        VpccBox::write_box(&self.vpcc, writer)?;
+        let known = [BoxType::VpccBox, BoxType::BtrtBox];
+        for child in child_order(&self.child_order, &known, self.unknown_boxes.len()) {
+            match child {
+                Child::Known(BoxType::VpccBox) => {
+                    VpccBox::write_box(&self.vpcc, writer)?;
+                }
+                Child::Known(BoxType::BtrtBox) => {
+                    if let Some(ref btrt) = self.btrt {
+                        btrt.write_box(writer)?;
+                    }
+                }
+                Child::Known(_) => {}
+                Child::Unknown(i) => {
+                    self.unknown_boxes[i].write_box(writer)?;
+                }
+            }
+        }
 
         Ok(size)
     }
@@ -1,9 +1,45 @@
+use std::fmt;
 use std::io::{Read, Seek, SeekFrom};
 use std::time::Duration;
 
 use crate::mp4box::*;
 use crate::*;
 
+/// Options for [Mp4Reader::read_header_with_options].
+#[derive(Debug, Clone, Default)]
+pub struct ReadOptions {
+    /// Tolerate recoverable problems, such as a missing ftyp, duplicate track
+    /// IDs or a truncated box at the end of the file, and report them as
+    /// warnings instead of failing.
+    pub lenient: bool,
+}
+
+/// A problem found while reading the header that didn't stop parsing.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ReadWarning {
+    /// Path of the box the problem was found in, e.g. `moof/traf`.
+    pub path: String,
+    /// Offset of that box in the file.
+    pub offset: u64,
+    pub message: String,
+}
+
+impl fmt::Display for ReadWarning {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} at {:#x}: {}", self.path, self.offset, self.message)
+    }
+}
+
+impl ReadWarning {
+    fn new(path: &str, offset: u64, message: impl ToString) -> Self {
+        Self {
+            path: path.to_string(),
+            offset,
+            message: message.to_string(),
+        }
+    }
+}
+
 #[derive(Debug)]
 pub struct Mp4Reader<R> {
     reader: R,
@@ -13,97 +49,192 @@ pub struct Mp4Reader<R> {
 
     tracks: Vec<Mp4Track>,
     size: u64,
+    warnings: Vec<ReadWarning>,
 }
 
 impl<R: Read + Seek> Mp4Reader<R> {
-    pub fn read_header(mut reader: R, size: u64) -> Result<Self> {
+    pub fn read_header(reader: R, size: u64) -> Result<Self> {
+        Self::read_header_with_options(reader, size, &ReadOptions::default())
+    }
+
+    pub fn read_header_with_options(
+        mut reader: R,
+        size: u64,
+        options: &ReadOptions,
+    ) -> Result<Self> {
         let start = reader.seek(SeekFrom::Current(0))?;
 
         let mut ftyp = None;
         let mut moov = None;
+        let mut moov_offset = 0;
         let mut moofs = Vec::new();
+        let mut moof_offsets = Vec::new();
+        let mut warnings = Vec::new();
 
         let mut current = start;
         while current < size {
-            // Get box header.
-            let header = BoxHeader::read(&mut reader)?;
-            let BoxHeader { name, size: s } = header;
-
-            // Match and parse the atom boxes.
-            match name {
-                BoxType::FtypBox => {
-                    ftyp = Some(FtypBox::read_box(&mut reader, s)?);
-                }
-                BoxType::FreeBox => {
-                    skip_box(&mut reader, s)?;
+            if current + HEADER_SIZE > size {
+                if !options.lenient {
+                    return Err(Error::InvalidData("truncated box header"));
                 }
-                BoxType::MdatBox => {
-                    skip_box(&mut reader, s)?;
+                warnings.push(ReadWarning::new("", current, "truncated box header"));
+                break;
+            }
+
+            // Get box header.
+            let header = match BoxHeader::read_in(&mut reader, size) {
+                Ok(header) => header,
+                Err(err) if options.lenient => {
+                    warnings.push(ReadWarning::new("", current, err));
+                    break;
                 }
-                BoxType::MoovBox => {
-                    moov = Some(MoovBox::read_box(&mut reader, s)?);
+                Err(err) => return Err(err),
+            };
+            let name = header.name;
+            let s = header.read_size();
+            let path = name.to_string();
+            if current + header.size > size {
+                if !options.lenient {
+                    return Err(Error::InvalidData("box extends past the end of the file"));
                 }
-                BoxType::MoofBox => {
-                    let moof = MoofBox::read_box(&mut reader, s)?;
-                    moofs.push(moof);
+                warnings.push(ReadWarning::new(
+                    &path,
+                    current,
+                    "box extends past the end of the file",
+                ));
+            }
+
+            // Match and parse the atom boxes.
+            let end = current + header.size;
+            let parsed = match name {
+                BoxType::FtypBox => FtypBox::read_box(&mut reader, s).map(|box_| {
+                    ftyp = Some(box_);
+                }),
+                BoxType::FreeBox => skip_bytes_to(&mut reader, end),
+                BoxType::MdatBox => skip_bytes_to(&mut reader, end),
+                BoxType::MoovBox => MoovBox::read_box(&mut reader, s).map(|box_| {
+                    moov = Some(box_);
+                    moov_offset = current;
+                }),
+                BoxType::MoofBox => MoofBox::read_box(&mut reader, s).map(|box_| {
+                    moofs.push(box_);
+                    moof_offsets.push(current);
+                }),
+                BoxType::UuidBox => {
+                    let usertype: String = header
+                        .usertype
+                        .unwrap_or_default()
+                        .iter()
+                        .map(|b| format!("{:02x}", b))
+                        .collect();
+                    warnings.push(ReadWarning::new(
+                        &path,
+                        current,
+                        format!("uuid box {} skipped", usertype),
+                    ));
+                    skip_bytes_to(&mut reader, end)
                 }
                 _ => {
-                    // XXX warn!()
-                    skip_box(&mut reader, s)?;
+                    warnings.push(ReadWarning::new(&path, current, "unknown box skipped"));
+                    skip_bytes_to(&mut reader, end)
                 }
+            };
+            if let Err(err) = parsed {
+                if !options.lenient {
+                    return Err(err);
+                }
+                warnings.push(ReadWarning::new(&path, current, err));
+                break;
             }
             current = reader.seek(SeekFrom::Current(0))?;
         }
 
-        if ftyp.is_none() {
-            return Err(Error::BoxNotFound(BoxType::FtypBox));
-        }
-        if moov.is_none() {
-            return Err(Error::BoxNotFound(BoxType::MoovBox));
-        }
+        let ftyp = match ftyp {
+            Some(ftyp) => ftyp,
+            None if options.lenient => {
+                warnings.push(ReadWarning::new("ftyp", start, "ftyp box not found"));
+                FtypBox::default()
+            }
+            None => return Err(Error::BoxNotFound(BoxType::FtypBox)),
+        };
+        let moov = match moov {
+            Some(moov) => moov,
+            None => return Err(Error::BoxNotFound(BoxType::MoovBox)),
+        };
 
         let size = current - start;
-        let mut tracks = if let Some(ref moov) = moov {
-            let mut tracks = Vec::with_capacity(moov.traks.len());
-            for (i, trak) in moov.traks.iter().enumerate() {
-                if trak.tkhd.track_id != i as u32 + 1 {
-                    return Err(Error::InvalidData("tracks out of order"));
+        let mut tracks = Vec::with_capacity(moov.traks.len());
+        for trak in moov.traks.iter() {
+            let track_id = trak.tkhd.track_id;
+            if tracks
+                .iter()
+                .any(|track: &Mp4Track| track.track_id() == track_id)
+            {
+                if !options.lenient {
+                    return Err(Error::InvalidData("duplicate track id"));
                 }
-                tracks.push(Mp4Track::from(trak));
+                warnings.push(ReadWarning::new(
+                    "moov/trak",
+                    moov_offset,
+                    format!("duplicate track {} skipped", track_id),
+                ));
+                continue;
             }
-            tracks
-        } else {
-            Vec::new()
-        };
+            tracks.push(Mp4Track::from(trak));
+        }
 
         // Update tracks if any fragmented (moof) boxes are found.
-        if moofs.len() > 0 {
-            let mut default_sample_duration = 0;
-            if let Some(ref moov) = moov {
-                if let Some(ref mvex) = &moov.mvex {
-                    default_sample_duration = mvex.trex.default_sample_duration
+        if !moofs.is_empty() {
+            if let Some(ref mvex) = moov.mvex {
+                for track in tracks.iter_mut() {
+                    track.trex = mvex
+                        .trexs
+                        .iter()
+                        .find(|trex| trex.track_id == track.track_id())
+                        .cloned();
                 }
             }
 
-            for moof in moofs.iter() {
+            for (moof, moof_offset) in moofs.iter().zip(moof_offsets) {
+                // Without an explicit base, the first traf starts at the moof and
+                // each following traf continues where the previous one's data ended.
+                let mut data_offset = moof_offset;
                 for traf in moof.trafs.iter() {
-                    let track_id = traf.tfhd.track_id as usize - 1;
-                    tracks[track_id].default_sample_duration = default_sample_duration;
-                    tracks[track_id].trafs.push(traf.clone());
+                    let track_id = traf.tfhd.track_id;
+                    match tracks.iter_mut().find(|track| track.track_id() == track_id) {
+                        Some(track) => {
+                            data_offset = track.add_traf(traf, moof_offset, data_offset);
+                        }
+                        None if options.lenient => {
+                            warnings.push(ReadWarning::new(
+                                "moof/traf",
+                                moof_offset,
+                                format!("traf for unknown track {} skipped", track_id),
+                            ));
+                        }
+                        None => return Err(Error::TrakNotFound(track_id)),
+                    }
                 }
             }
         }
 
         Ok(Mp4Reader {
             reader,
-            ftyp: ftyp.unwrap(),
-            moov: moov.unwrap(),
+            ftyp,
+            moov,
             moofs,
             size,
             tracks,
+            warnings,
         })
     }
 
+    /// Problems found while reading the header. Only recoverable problems are
+    /// reported here unless [ReadOptions::lenient] is set.
+    pub fn warnings(&self) -> &[ReadWarning] {
+        &self.warnings
+    }
+
     pub fn size(&self) -> u64 {
         self.size
     }
@@ -136,27 +267,541 @@ impl<R: Read + Seek> Mp4Reader<R> {
         &self.tracks
     }
 
+    pub fn track(&self, track_id: u32) -> Result<&Mp4Track> {
+        self.tracks
+            .iter()
+            .find(|track| track.track_id() == track_id)
+            .ok_or(Error::TrakNotFound(track_id))
+    }
+
     pub fn sample_count(&self, track_id: u32) -> Result<u32> {
-        if track_id == 0 {
-            return Err(Error::TrakNotFound(track_id));
+        Ok(self.track(track_id)?.sample_count())
+    }
+
+    pub fn read_sample(&mut self, track_id: u32, sample_id: u32) -> Result<Option<Mp4Sample>> {
+        let track = self
+            .tracks
+            .iter()
+            .find(|track| track.track_id() == track_id)
+            .ok_or(Error::TrakNotFound(track_id))?;
+        track.read_sample(&mut self.reader, sample_id)
+    }
+
+    /// Iterates the samples of a track in decoding order, reading each payload.
+    pub fn samples(&mut self, track_id: u32) -> Result<SampleIter<'_, R>> {
+        let sample_count = self.sample_count(track_id)?;
+        Ok(SampleIter {
+            reader: self,
+            track_id,
+            sample_id: 1,
+            sample_count,
+        })
+    }
+
+    /// Iterates the location and timing of the samples of a track in decoding
+    /// order, without touching the sample data.
+    pub fn sample_infos(&self, track_id: u32) -> Result<impl Iterator<Item = SampleInfo> + '_> {
+        Ok(self.track(track_id)?.sample_infos())
+    }
+
+    /// Iterates the samples of all tracks as `(track_id, sample)` pairs in the
+    /// given order. Ties are broken by track order.
+    pub fn interleaved_samples(&mut self, order: SampleOrder) -> InterleavedSampleIter<'_, R> {
+        let movie_timescale = self.moov.mvhd.timescale as u128;
+        let mut samples = Vec::new();
+        for track in self.tracks.iter() {
+            let track_timescale = track.timescale() as u128;
+            for (sample_idx, info) in track.sample_infos().enumerate() {
+                let key = match order {
+                    SampleOrder::FileOffset => info.offset as u128,
+                    SampleOrder::DecodeTime if track_timescale > 0 => {
+                        info.start_time as u128 * movie_timescale / track_timescale
+                    }
+                    SampleOrder::DecodeTime => info.start_time as u128,
+                };
+                samples.push((key, track.track_id(), sample_idx as u32 + 1));
+            }
         }
+        samples.sort_by_key(|(key, _, _)| *key);
 
-        if let Some(track) = self.tracks.get(track_id as usize - 1) {
-            Ok(track.sample_count())
-        } else {
-            Err(Error::TrakNotFound(track_id))
+        InterleavedSampleIter {
+            reader: self,
+            samples: samples.into_iter(),
         }
     }
 
-    pub fn read_sample(&mut self, track_id: u32, sample_id: u32) -> Result<Option<Mp4Sample>> {
-        if track_id == 0 {
-            return Err(Error::TrakNotFound(track_id));
+    /// Returns the id of the sample to start decoding from to present `time`
+    /// on the movie timeline.
+    pub fn seek(&self, track_id: u32, time: Duration, mode: SeekMode) -> Result<u32> {
+        self.track(track_id)?
+            .seek(time, mode, self.moov.mvhd.timescale)
+    }
+
+    /// Returns the time a sample is presented on the movie timeline.
+    pub fn presentation_time(&self, track_id: u32, sample_id: u32) -> Result<Duration> {
+        self.track(track_id)?
+            .presentation_time(sample_id, self.moov.mvhd.timescale)
+    }
+}
+
+/// Iterator over the samples of a track, created by [Mp4Reader::samples].
+#[derive(Debug)]
+pub struct SampleIter<'a, R> {
+    reader: &'a mut Mp4Reader<R>,
+    track_id: u32,
+    sample_id: u32,
+    sample_count: u32,
+}
+
+impl<'a, R: Read + Seek> Iterator for SampleIter<'a, R> {
+    type Item = Result<Mp4Sample>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.sample_id > self.sample_count {
+            return None;
         }
 
-        if let Some(ref track) = self.tracks.get(track_id as usize - 1) {
-            track.read_sample(&mut self.reader, sample_id)
-        } else {
-            Err(Error::TrakNotFound(track_id))
+        let sample = self.reader.read_sample(self.track_id, self.sample_id);
+        self.sample_id += 1;
+        sample.transpose()
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let remaining = (self.sample_count + 1 - self.sample_id) as usize;
+        (remaining, Some(remaining))
+    }
+}
+
+/// Iterator over the samples of all tracks, created by
+/// [Mp4Reader::interleaved_samples].
+#[derive(Debug)]
+pub struct InterleavedSampleIter<'a, R> {
+    reader: &'a mut Mp4Reader<R>,
+    samples: std::vec::IntoIter<(u128, u32, u32)>,
+}
+
+impl<'a, R: Read + Seek> Iterator for InterleavedSampleIter<'a, R> {
+    type Item = Result<(u32, Mp4Sample)>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let (_, track_id, sample_id) = self.samples.next()?;
+        match self.reader.read_sample(track_id, sample_id) {
+            Ok(Some(sample)) => Some(Ok((track_id, sample))),
+            Ok(None) => None,
+            Err(err) => Some(Err(err)),
         }
     }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.samples.size_hint()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mp4box::{
+        edts::EdtsBox, elst::ElstBox, elst::ElstEntry, tfhd::TfhdBox, traf::TrafBox, trun::TrunBox,
+    };
+    use crate::test_util::{fragmented_mp4, test_config, write_fragment};
+    use std::io::Cursor;
+
+    #[test]
+    fn test_read_fragmented_samples() {
+        let buf = fragmented_mp4();
+        let size = buf.len() as u64;
+        let mut mp4 = Mp4Reader::read_header(Cursor::new(buf), size).unwrap();
+
+        assert!(mp4.is_fragmented());
+        assert_eq!(mp4.sample_count(1).unwrap(), 5);
+
+        let sample = mp4.read_sample(1, 1).unwrap().unwrap();
+        assert_eq!(sample.start_time, 5000);
+        assert_eq!(sample.duration, 1000);
+        assert_eq!(sample.rendering_offset, 0);
+        assert!(sample.is_sync);
+        assert_eq!(sample.bytes.as_ref(), &[1; 3]);
+
+        let sample = mp4.read_sample(1, 2).unwrap().unwrap();
+        assert_eq!(sample.start_time, 6000);
+        assert_eq!(sample.rendering_offset, 2000);
+        assert!(!sample.is_sync);
+        assert_eq!(sample.bytes.as_ref(), &[2; 5]);
+
+        let sample = mp4.read_sample(1, 3).unwrap().unwrap();
+        assert_eq!(sample.start_time, 7000);
+        assert_eq!(sample.rendering_offset, 1000);
+        assert_eq!(sample.bytes.as_ref(), &[3; 7]);
+
+        let sample = mp4.read_sample(1, 4).unwrap().unwrap();
+        assert_eq!(sample.start_time, 8000);
+        assert_eq!(sample.duration, 500);
+        assert!(!sample.is_sync);
+        assert_eq!(sample.bytes.as_ref(), &[4; 4]);
+
+        let sample = mp4.read_sample(1, 5).unwrap().unwrap();
+        assert_eq!(sample.start_time, 8500);
+        assert_eq!(sample.bytes.as_ref(), &[5; 4]);
+
+        assert!(mp4.read_sample(1, 6).unwrap().is_none());
+    }
+
+    #[test]
+    fn test_seek_fragmented() {
+        let buf = fragmented_mp4();
+        let size = buf.len() as u64;
+        let mp4 = Mp4Reader::read_header(Cursor::new(buf), size).unwrap();
+
+        assert_eq!(
+            mp4.presentation_time(1, 1).unwrap(),
+            Duration::from_millis(5000)
+        );
+        assert_eq!(
+            mp4.presentation_time(1, 2).unwrap(),
+            Duration::from_millis(8000)
+        );
+        assert_eq!(
+            mp4.presentation_time(1, 5).unwrap(),
+            Duration::from_millis(8500)
+        );
+        assert!(mp4.presentation_time(1, 6).is_err());
+
+        let time = Duration::from_millis(8600);
+        assert_eq!(mp4.seek(1, time, SeekMode::Exact).unwrap(), 5);
+        assert_eq!(mp4.seek(1, time, SeekMode::PreviousSync).unwrap(), 1);
+        assert_eq!(mp4.seek(1, Duration::ZERO, SeekMode::Exact).unwrap(), 1);
+        assert!(mp4.seek(2, time, SeekMode::Exact).is_err());
+    }
+
+    #[test]
+    fn test_seek_edit_list() {
+        let config = test_config();
+        let mut writer = Mp4Writer::write_start(Cursor::new(Vec::new()), &config).unwrap();
+        writer.add_track(&TrackConfig::from(TtxtConfig {})).unwrap();
+        for i in 0..6 {
+            let sample = Mp4Sample {
+                start_time: i * 1000,
+                duration: 1000,
+                rendering_offset: 0,
+                is_sync: i % 3 == 0,
+                sample_description_index: 1,
+                bytes: Bytes::from(vec![i as u8; 4]),
+            };
+            writer.write_sample(1, &sample).unwrap();
+        }
+        writer.write_end().unwrap();
+        let mut buf = writer.into_writer().into_inner();
+
+        // Swap in a moov whose edit list starts with a 2s gap and then plays
+        // the media from 1s on.
+        let size = buf.len() as u64;
+        let mut moov = Mp4Reader::read_header(Cursor::new(&buf), size)
+            .unwrap()
+            .moov;
+        buf.truncate(buf.len() - moov.box_size() as usize);
+        moov.traks[0].edts = Some(EdtsBox {
+            elst: Some(ElstBox {
+                version: 0,
+                flags: 0,
+                entries: vec![
+                    ElstEntry {
+                        segment_duration: 2000,
+                        media_time: -1,
+                        media_rate: 1,
+                        media_rate_fraction: 0,
+                    },
+                    ElstEntry {
+                        segment_duration: 5000,
+                        media_time: 1000,
+                        media_rate: 1,
+                        media_rate_fraction: 0,
+                    },
+                ],
+            }),
+        });
+        moov.write_box(&mut buf).unwrap();
+
+        let size = buf.len() as u64;
+        let mp4 = Mp4Reader::read_header(Cursor::new(buf), size).unwrap();
+
+        assert_eq!(
+            mp4.presentation_time(1, 2).unwrap(),
+            Duration::from_millis(2000)
+        );
+        assert_eq!(
+            mp4.presentation_time(1, 4).unwrap(),
+            Duration::from_millis(4000)
+        );
+
+        let time = Duration::from_millis(500);
+        assert_eq!(mp4.seek(1, time, SeekMode::Exact).unwrap(), 2);
+        assert_eq!(mp4.seek(1, time, SeekMode::PreviousSync).unwrap(), 1);
+
+        let time = Duration::from_millis(5500);
+        assert_eq!(mp4.seek(1, time, SeekMode::Exact).unwrap(), 5);
+        assert_eq!(mp4.seek(1, time, SeekMode::PreviousSync).unwrap(), 4);
+    }
+
+    #[test]
+    fn test_sample_iterators() {
+        let buf = fragmented_mp4();
+        let size = buf.len() as u64;
+        let mut mp4 = Mp4Reader::read_header(Cursor::new(buf), size).unwrap();
+
+        let infos: Vec<SampleInfo> = mp4.sample_infos(1).unwrap().collect();
+        assert_eq!(infos.len(), 5);
+        assert_eq!(infos[1].size, 5);
+        assert_eq!(infos[1].offset, infos[0].offset + 3);
+        assert_eq!(infos[3].start_time, 8000);
+        assert!(infos[0].is_sync);
+        assert!(!infos[4].is_sync);
+        assert!(infos.iter().all(|info| info.sample_description_index == 1));
+
+        let samples = mp4.samples(1).unwrap();
+        assert_eq!(samples.size_hint(), (5, Some(5)));
+        let samples: Vec<Mp4Sample> = samples.map(|sample| sample.unwrap()).collect();
+        assert_eq!(samples.len(), 5);
+        for (sample, info) in samples.iter().zip(infos.iter()) {
+            assert_eq!(sample.start_time, info.start_time);
+            assert_eq!(sample.bytes.len(), info.size as usize);
+        }
+
+        assert!(mp4.samples(2).is_err());
+        assert!(mp4.sample_infos(0).is_err());
+    }
+
+    #[test]
+    fn test_interleaved_samples() {
+        let config = test_config();
+        let mut writer = Mp4Writer::write_start(Cursor::new(Vec::new()), &config).unwrap();
+        writer.add_track(&TrackConfig::from(TtxtConfig {})).unwrap();
+        writer
+            .add_track(&TrackConfig {
+                timescale: 48000,
+                ..TrackConfig::from(TtxtConfig {})
+            })
+            .unwrap();
+        // Track 1 is written in full before track 2, so file order and decode
+        // order differ.
+        for i in 0..3 {
+            let sample = Mp4Sample {
+                start_time: i * 1000,
+                duration: 1000,
+                rendering_offset: 0,
+                is_sync: true,
+                sample_description_index: 1,
+                bytes: Bytes::from(vec![1; 4]),
+            };
+            writer.write_sample(1, &sample).unwrap();
+        }
+        for i in 0..6 {
+            let sample = Mp4Sample {
+                start_time: i * 24000,
+                duration: 24000,
+                rendering_offset: 0,
+                is_sync: true,
+                sample_description_index: 1,
+                bytes: Bytes::from(vec![2; 4]),
+            };
+            writer.write_sample(2, &sample).unwrap();
+        }
+        writer.write_end().unwrap();
+        let buf = writer.into_writer().into_inner();
+
+        let size = buf.len() as u64;
+        let mut mp4 = Mp4Reader::read_header(Cursor::new(buf), size).unwrap();
+
+        let samples: Vec<(u32, u64)> = mp4
+            .interleaved_samples(SampleOrder::DecodeTime)
+            .map(|sample| sample.map(|(track_id, sample)| (track_id, sample.start_time)))
+            .collect::<Result<_>>()
+            .unwrap();
+        assert_eq!(
+            samples,
+            vec![
+                (1, 0),
+                (2, 0),
+                (2, 24000),
+                (1, 1000),
+                (2, 48000),
+                (2, 72000),
+                (1, 2000),
+                (2, 96000),
+                (2, 120000),
+            ]
+        );
+
+        let track_ids: Vec<u32> = mp4
+            .interleaved_samples(SampleOrder::FileOffset)
+            .map(|sample| sample.unwrap().0)
+            .collect();
+        assert_eq!(track_ids, vec![1, 1, 1, 2, 2, 2, 2, 2, 2]);
+    }
+
+    #[test]
+    fn test_read_lenient() {
+        let mut buf = fragmented_mp4();
+        let ftyp = BoxHeader::read(&mut Cursor::new(&buf)).unwrap();
+        buf.drain(..ftyp.size as usize);
+
+        let size = buf.len() as u64;
+        let err = Mp4Reader::read_header(Cursor::new(&buf), size).unwrap_err();
+        assert!(matches!(err, Error::BoxNotFound(BoxType::FtypBox)));
+
+        // An unknown box, then a trailing box cut short.
+        BoxHeader::new(BoxType::UnknownBox(0x75647461), 12)
+            .write(&mut buf)
+            .unwrap();
+        buf.extend_from_slice(&[0; 4]);
+        let truncated_offset = buf.len() as u64;
+        BoxHeader::new(BoxType::FreeBox, 100)
+            .write(&mut buf)
+            .unwrap();
+        buf.extend_from_slice(&[0; 10]);
+
+        let size = buf.len() as u64;
+        let options = ReadOptions { lenient: true };
+        let mut mp4 =
+            Mp4Reader::read_header_with_options(Cursor::new(&buf), size, &options).unwrap();
+        assert_eq!(mp4.sample_count(1).unwrap(), 5);
+        assert!(mp4.read_sample(1, 5).unwrap().is_some());
+
+        let warnings = mp4.warnings();
+        assert_eq!(warnings.len(), 3);
+        assert_eq!(warnings[0].path, "udta");
+        assert_eq!(warnings[1].path, "free");
+        assert_eq!(warnings[1].offset, truncated_offset);
+        assert_eq!(warnings[2].path, "ftyp");
+    }
+
+    #[test]
+    fn test_read_truncated_strict() {
+        let mut buf = fragmented_mp4();
+        let size = buf.len() as u64;
+
+        // A box that claims more bytes than the file has.
+        let mut past_end = buf.clone();
+        BoxHeader::new(BoxType::FreeBox, 100)
+            .write(&mut past_end)
+            .unwrap();
+        past_end.extend_from_slice(&[0; 10]);
+        let err = Mp4Reader::read_header(Cursor::new(&past_end), past_end.len() as u64)
+            .unwrap_err();
+        assert!(matches!(err, Error::InvalidData(_)));
+
+        // Fewer bytes left than a box header needs.
+        buf.extend_from_slice(&[0; 4]);
+        let err = Mp4Reader::read_header(Cursor::new(&buf), size + 4).unwrap_err();
+        assert!(matches!(err, Error::InvalidData(_)));
+
+        let options = ReadOptions { lenient: true };
+        let mp4 =
+            Mp4Reader::read_header_with_options(Cursor::new(&buf), size + 4, &options).unwrap();
+        assert_eq!(mp4.warnings().len(), 1);
+        assert_eq!(mp4.warnings()[0].offset, size);
+    }
+
+    #[test]
+    fn test_read_huge_sample_counts() {
+        fn count_pos(buf: &[u8], name: &[u8; 4], skip: usize) -> usize {
+            buf.windows(4).position(|w| w == name).unwrap() + 8 + skip
+        }
+
+        // A fixed-size stsz claiming far more samples than stts describes.
+        let mut writer = Mp4Writer::write_start(Cursor::new(Vec::new()), &test_config()).unwrap();
+        writer.add_track(&TrackConfig::from(TtxtConfig {})).unwrap();
+        for i in 0..3 {
+            let sample = Mp4Sample {
+                start_time: i * 1000,
+                duration: 1000,
+                rendering_offset: 0,
+                is_sync: true,
+                sample_description_index: 1,
+                bytes: Bytes::from(vec![i as u8; 4]),
+            };
+            writer.write_sample(1, &sample).unwrap();
+        }
+        writer.write_end().unwrap();
+        let mut buf = writer.into_writer().into_inner();
+        let pos = count_pos(&buf, b"stsz", 4);
+        buf[pos..pos + 4].copy_from_slice(&u32::MAX.to_be_bytes());
+        let mp4 = Mp4Reader::read_header(Cursor::new(&buf), buf.len() as u64).unwrap();
+        assert_eq!(mp4.sample_count(1).unwrap(), 3);
+
+        // A trun with per-sample sizes claiming more samples than it holds.
+        let mut buf = fragmented_mp4();
+        let pos = count_pos(&buf, b"trun", 0);
+        buf[pos..pos + 4].copy_from_slice(&u32::MAX.to_be_bytes());
+        let err = Mp4Reader::read_header(Cursor::new(&buf), buf.len() as u64).unwrap_err();
+        assert!(matches!(err, Error::InvalidData(_)));
+    }
+
+    #[test]
+    fn test_read_lenient_unknown_traf() {
+        let mut buf = fragmented_mp4();
+        let traf = TrafBox {
+            tfhd: TfhdBox {
+                flags: TfhdBox::FLAG_DEFAULT_BASE_IS_MOOF,
+                track_id: 2,
+                ..TfhdBox::default()
+            },
+            tfdt: None,
+            trun: Some(TrunBox {
+                flags: TrunBox::FLAG_DATA_OFFSET | TrunBox::FLAG_SAMPLE_SIZE,
+                sample_count: 1,
+                data_offset: Some(0),
+                sample_sizes: vec![2],
+                ..TrunBox::default()
+            }),
+            ..TrafBox::default()
+        };
+        write_fragment(&mut buf, traf, &[vec![6; 2]]);
+
+        let size = buf.len() as u64;
+        let err = Mp4Reader::read_header(Cursor::new(&buf), size).unwrap_err();
+        assert!(matches!(err, Error::TrakNotFound(2)));
+
+        let options = ReadOptions { lenient: true };
+        let mp4 = Mp4Reader::read_header_with_options(Cursor::new(&buf), size, &options).unwrap();
+        assert_eq!(mp4.sample_count(1).unwrap(), 5);
+        assert_eq!(mp4.warnings().len(), 1);
+        assert_eq!(mp4.warnings()[0].path, "moof/traf");
+    }
+
+    #[test]
+    fn test_read_box_headers() {
+        let mut buf = fragmented_mp4();
+        let sample_count = Mp4Reader::read_header(Cursor::new(&buf), buf.len() as u64)
+            .unwrap()
+            .sample_count(1)
+            .unwrap();
+
+        // The last mdat extends to the end of the file.
+        let mdat_offset = buf.len() - 16;
+        assert_eq!(&buf[mdat_offset + 4..mdat_offset + 8], b"mdat");
+        buf[mdat_offset..mdat_offset + 4].copy_from_slice(&[0; 4]);
+
+        // A uuid box with a largesize header before it.
+        let usertype = [0xa5; 16];
+        let uuid = BoxHeader {
+            name: BoxType::UuidBox,
+            size: 40,
+            header_size: HEADER_LARGE_SIZE + USERTYPE_SIZE,
+            usertype: Some(usertype),
+        };
+        let mut uuid_buf = Vec::new();
+        uuid.write(&mut uuid_buf).unwrap();
+        uuid_buf.extend_from_slice(&[0; 8]);
+        let ftyp = BoxHeader::read(&mut Cursor::new(&buf)).unwrap();
+        buf.splice(ftyp.size as usize..ftyp.size as usize, uuid_buf);
+
+        let size = buf.len() as u64;
+        let mut mp4 = Mp4Reader::read_header(Cursor::new(&buf), size).unwrap();
+        assert_eq!(mp4.size(), size);
+        assert_eq!(mp4.warnings().len(), 1);
+        assert_eq!(mp4.warnings()[0].path, "uuid");
+        assert_eq!(mp4.sample_count(1).unwrap(), sample_count);
+        let sample = mp4.read_sample(1, sample_count).unwrap().unwrap();
+        assert_eq!(sample.bytes.as_ref(), &[5; 4]);
+    }
 }
@@ -0,0 +1,350 @@
+use bytes::{Buf, BytesMut};
+use std::collections::VecDeque;
+use std::io::{self, Cursor};
+
+use crate::mp4box::*;
+use crate::*;
+
+// Largest ftyp, moov or moof box buffered by Mp4StreamParser::new.
+const DEFAULT_MAX_BOX_SIZE: u64 = 64 * 1024 * 1024;
+
+#[derive(Debug)]
+pub enum StreamEvent {
+    Ftyp(FtypBox),
+    Moov(MoovBox),
+    Moof(MoofBox),
+    Sample { track_id: u32, sample: Mp4Sample },
+}
+
+#[derive(Debug, Clone, Copy)]
+enum State {
+    Header,
+    Mdat { end: u64 },
+    Skip { end: u64 },
+}
+
+/// Incremental parser for input that can't be seeked, such as fragmented MP4
+/// arriving over a pipe or socket.
+///
+/// Bytes are pushed in as they arrive. Only a single ftyp, moov or moof box
+/// or a single sample payload, each up to the maximum box size, is buffered at
+/// a time; everything else in mdat is dropped as soon as it's passed. Samples
+/// are emitted for every moof, and for a moov placed before its mdat.
+#[derive(Debug)]
+pub struct Mp4StreamParser {
+    buffer: BytesMut,
+    offset: u64,
+    state: State,
+    max_box_size: u64,
+
+    tracks: Vec<Mp4Track>,
+    pending: VecDeque<(u32, SampleInfo)>,
+}
+
+impl Default for Mp4StreamParser {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Mp4StreamParser {
+    /// Creates a parser that buffers ftyp, moov and moof boxes and samples of up
+    /// to 64 MiB.
+    pub fn new() -> Self {
+        Self::with_max_box_size(DEFAULT_MAX_BOX_SIZE)
+    }
+
+    pub fn with_max_box_size(max_box_size: u64) -> Self {
+        Self {
+            buffer: BytesMut::new(),
+            offset: 0,
+            state: State::Header,
+            max_box_size,
+            tracks: Vec::new(),
+            pending: VecDeque::new(),
+        }
+    }
+
+    /// Number of bytes consumed from the stream so far.
+    pub fn offset(&self) -> u64 {
+        self.offset
+    }
+
+    /// Feeds the next chunk of the stream and returns the events it completes.
+    pub fn push(&mut self, data: &[u8]) -> Result<Vec<StreamEvent>> {
+        self.buffer.extend_from_slice(data);
+
+        let mut events = Vec::new();
+        loop {
+            let done = match self.state {
+                State::Header => self.parse_box(&mut events)?,
+                State::Mdat { end } => self.parse_mdat(end, &mut events)?,
+                State::Skip { end } => {
+                    self.consume_to(end);
+                    self.offset < end
+                }
+            };
+            if done {
+                break;
+            }
+            if let State::Skip { end } | State::Mdat { end } = self.state {
+                if self.offset >= end {
+                    self.state = State::Header;
+                }
+            }
+        }
+        Ok(events)
+    }
+
+    /// Parses the box at the head of the buffer. Returns true when more data
+    /// is needed.
+    fn parse_box(&mut self, events: &mut Vec<StreamEvent>) -> Result<bool> {
+        let header = match BoxHeader::read(&mut Cursor::new(&self.buffer[..])) {
+            Ok(header) => header,
+            Err(Error::IoError(ref err)) if err.kind() == io::ErrorKind::UnexpectedEof => {
+                return Ok(true);
+            }
+            Err(err) => return Err(err),
+        };
+        let name = header.name;
+        // A size of 0 extends the box to the end of the stream.
+        let size = Some(header.size).filter(|size| *size != 0);
+        let end = size.map_or(u64::MAX, |size| self.offset + size);
+
+        match name {
+            BoxType::FtypBox | BoxType::MoovBox | BoxType::MoofBox => {
+                let size = size.ok_or(Error::InvalidData(
+                    "box size of 0 is only supported for mdat",
+                ))?;
+                if size > self.max_box_size {
+                    return Err(Error::InvalidData("box exceeds the maximum buffered size"));
+                }
+                if (self.buffer.len() as u64) < size {
+                    return Ok(true);
+                }
+
+                let box_offset = self.offset;
+                let data = self.buffer.split_to(size as usize);
+                self.offset += size;
+
+                let mut reader = Cursor::new(&data[..]);
+                reader.set_position(header.header_size);
+                let s = header.read_size();
+                match name {
+                    BoxType::FtypBox => {
+                        let ftyp = FtypBox::read_box(&mut reader, s)?;
+                        events.push(StreamEvent::Ftyp(ftyp));
+                    }
+                    BoxType::MoovBox => {
+                        let moov = MoovBox::read_box(&mut reader, s)?;
+                        self.add_moov(&moov);
+                        events.push(StreamEvent::Moov(moov));
+                    }
+                    _ => {
+                        let moof = MoofBox::read_box(&mut reader, s)?;
+                        self.add_moof(&moof, box_offset)?;
+                        events.push(StreamEvent::Moof(moof));
+                    }
+                }
+            }
+            BoxType::MdatBox => {
+                self.consume_to(self.offset + header.header_size);
+                self.state = State::Mdat { end };
+            }
+            _ => {
+                self.state = State::Skip { end };
+            }
+        }
+        Ok(false)
+    }
+
+    /// Emits the pending samples found in the mdat ending at `end`. Returns
+    /// true when more data is needed.
+    fn parse_mdat(&mut self, end: u64, events: &mut Vec<StreamEvent>) -> Result<bool> {
+        while self.offset < end {
+            let (track_id, info) = match self.pending.front() {
+                Some(pending) => *pending,
+                None => {
+                    self.consume_to(end);
+                    return Ok(self.offset < end);
+                }
+            };
+
+            if info.offset >= end {
+                // Belongs to a later mdat.
+                self.consume_to(end);
+                return Ok(self.offset < end);
+            }
+            if info.offset < self.offset || info.offset + info.size as u64 > end {
+                // Already passed, or not contained in this mdat.
+                self.pending.pop_front();
+                continue;
+            }
+            if info.size as u64 > self.max_box_size {
+                return Err(Error::InvalidData("sample exceeds the maximum buffered size"));
+            }
+
+            self.consume_to(info.offset);
+            if self.offset < info.offset || self.buffer.len() < info.size as usize {
+                return Ok(true);
+            }
+
+            let bytes = self.buffer.split_to(info.size as usize).freeze();
+            self.offset += info.size as u64;
+            self.pending.pop_front();
+            events.push(StreamEvent::Sample {
+                track_id,
+                sample: Mp4Sample {
+                    start_time: info.start_time,
+                    duration: info.duration,
+                    rendering_offset: info.rendering_offset,
+                    is_sync: info.is_sync,
+                    sample_description_index: info.sample_description_index,
+                    bytes,
+                },
+            });
+        }
+        Ok(false)
+    }
+
+    fn consume_to(&mut self, offset: u64) {
+        let len = offset
+            .saturating_sub(self.offset)
+            .min(self.buffer.len() as u64);
+        self.buffer.advance(len as usize);
+        self.offset += len;
+    }
+
+    fn add_moov(&mut self, moov: &MoovBox) {
+        self.tracks = moov.traks.iter().map(Mp4Track::from).collect();
+        if let Some(ref mvex) = moov.mvex {
+            for track in self.tracks.iter_mut() {
+                track.trex = mvex
+                    .trexs
+                    .iter()
+                    .find(|trex| trex.track_id == track.track_id())
+                    .cloned();
+            }
+        }
+        self.queue_samples();
+    }
+
+    fn add_moof(&mut self, moof: &MoofBox, moof_offset: u64) -> Result<()> {
+        let mut data_offset = moof_offset;
+        for traf in moof.trafs.iter() {
+            let track_id = traf.tfhd.track_id;
+            let track = self
+                .tracks
+                .iter_mut()
+                .find(|track| track.track_id() == track_id)
+                .ok_or(Error::TrakNotFound(track_id))?;
+            data_offset = track.add_traf(traf, moof_offset, data_offset);
+        }
+        self.queue_samples();
+        Ok(())
+    }
+
+    fn queue_samples(&mut self) {
+        for track in self.tracks.iter_mut() {
+            let track_id = track.track_id();
+            for info in track.take_samples() {
+                self.pending.push_back((track_id, info));
+            }
+        }
+        self.pending
+            .make_contiguous()
+            .sort_by_key(|(_, info)| info.offset);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mp4box::{tfhd::TfhdBox, traf::TrafBox, trun::TrunBox};
+    use crate::test_util::{fragmented_mp4, write_fragment};
+
+    #[test]
+    fn test_stream_fragmented() {
+        let buf = fragmented_mp4();
+        let size = buf.len() as u64;
+        let mut mp4 = Mp4Reader::read_header(Cursor::new(buf.clone()), size).unwrap();
+
+        let mut parser = Mp4StreamParser::new();
+        let mut events = Vec::new();
+        for chunk in buf.chunks(7) {
+            events.extend(parser.push(chunk).unwrap());
+        }
+        assert_eq!(parser.offset(), size);
+
+        assert!(matches!(events[0], StreamEvent::Ftyp(_)));
+        assert!(matches!(events[1], StreamEvent::Moov(_)));
+        assert!(matches!(events[2], StreamEvent::Moof(_)));
+        assert!(matches!(events[6], StreamEvent::Moof(_)));
+
+        let samples: Vec<(u32, Mp4Sample)> = events
+            .into_iter()
+            .filter_map(|event| match event {
+                StreamEvent::Sample { track_id, sample } => Some((track_id, sample)),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(samples.len(), 5);
+        for (i, (track_id, sample)) in samples.iter().enumerate() {
+            let expected = mp4.read_sample(1, i as u32 + 1).unwrap().unwrap();
+            assert_eq!(*track_id, 1);
+            assert_eq!(*sample, expected);
+            assert_eq!(sample.bytes, expected.bytes);
+        }
+    }
+
+    #[test]
+    fn test_stream_max_box_size() {
+        let buf = fragmented_mp4();
+        let mut parser = Mp4StreamParser::with_max_box_size(64);
+        assert!(parser.push(&buf).is_err());
+    }
+
+    #[test]
+    fn test_stream_max_sample_size() {
+        let mut buf = fragmented_mp4();
+        let mut parser = Mp4StreamParser::with_max_box_size(2048);
+        assert!(parser.push(&buf).is_ok());
+
+        let traf = TrafBox {
+            tfhd: TfhdBox {
+                flags: TfhdBox::FLAG_DEFAULT_BASE_IS_MOOF,
+                track_id: 1,
+                ..TfhdBox::default()
+            },
+            trun: Some(TrunBox {
+                flags: TrunBox::FLAG_DATA_OFFSET | TrunBox::FLAG_SAMPLE_SIZE,
+                sample_count: 1,
+                data_offset: Some(0),
+                sample_sizes: vec![4096],
+                ..TrunBox::default()
+            }),
+            ..TrafBox::default()
+        };
+        let offset = buf.len();
+        write_fragment(&mut buf, traf, &[vec![6; 4096]]);
+
+        // Rejected as soon as the sample is reached, not once it's buffered.
+        let err = parser.push(&buf[offset..offset + 512]).unwrap_err();
+        assert!(matches!(err, Error::InvalidData(msg) if msg.starts_with("sample")));
+    }
+
+    #[test]
+    fn test_stream_mdat_size_zero() {
+        let mut buf = fragmented_mp4();
+        let mdat_offset = buf.len() - 16;
+        buf[mdat_offset..mdat_offset + 4].copy_from_slice(&[0; 4]);
+
+        let mut parser = Mp4StreamParser::new();
+        let samples = buf
+            .chunks(5)
+            .flat_map(|chunk| parser.push(chunk).unwrap())
+            .filter(|event| matches!(event, StreamEvent::Sample { .. }))
+            .count();
+        assert_eq!(samples, 5);
+    }
+}
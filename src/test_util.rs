@@ -0,0 +1,159 @@
+//! Test fixtures used by the unit tests of more than one module.
+
+use bytes::Bytes;
+use std::io::{Cursor, Seek, Write};
+
+use crate::mp4box::*;
+use crate::mp4box::{
+    mfhd::MfhdBox, mvex::MvexBox, tfdt::TfdtBox, tfhd::TfhdBox, traf::TrafBox, trex::TrexBox,
+    trun::TrunBox,
+};
+use crate::track::Mp4TrackWriter;
+use crate::*;
+
+pub(crate) fn test_config() -> Mp4Config {
+    Mp4Config {
+        major_brand: str::parse("isom").unwrap(),
+        minor_version: 512,
+        compatible_brands: vec![str::parse("isom").unwrap()],
+        timescale: 1000,
+        creation_time: 0,
+        modification_time: 0,
+    }
+}
+
+pub(crate) fn top_level_boxes(buf: &[u8]) -> Vec<BoxType> {
+    let mut reader = Cursor::new(buf);
+    let mut names = Vec::new();
+    while reader.position() < buf.len() as u64 {
+        let header = BoxHeader::read(&mut reader).unwrap();
+        names.push(header.name);
+        reader.set_position(reader.position() + header.size - header.header_size);
+    }
+    names
+}
+
+pub(crate) fn read_all_samples(buf: &[u8]) -> Vec<(u32, u64, Bytes)> {
+    let size = buf.len() as u64;
+    let mut mp4 = Mp4Reader::read_header(Cursor::new(buf), size).unwrap();
+    mp4.interleaved_samples(SampleOrder::FileOffset)
+        .map(|sample| sample.map(|(track_id, sample)| (track_id, sample.start_time, sample.bytes)))
+        .collect::<Result<_>>()
+        .unwrap()
+}
+
+pub(crate) fn write_two_tracks<W: Write + Seek>(writer: &mut Mp4Writer<W>) {
+    writer.add_track(&TrackConfig::from(TtxtConfig {})).unwrap();
+    writer.add_track(&TrackConfig::from(TtxtConfig {})).unwrap();
+    for i in 0..10 {
+        let sample = Mp4Sample {
+            start_time: i * 500,
+            duration: 500,
+            rendering_offset: 0,
+            is_sync: true,
+            sample_description_index: 1,
+            bytes: Bytes::from(vec![i as u8; 10 + i as usize]),
+        };
+        writer.write_sample(1 + i as u32 % 2, &sample).unwrap();
+    }
+}
+
+pub(crate) fn write_fragment(buf: &mut Vec<u8>, traf: TrafBox, samples: &[Vec<u8>]) {
+    let mut moof = MoofBox {
+        mfhd: MfhdBox::default(),
+        trafs: vec![traf],
+        ..MoofBox::default()
+    };
+    let data_offset = moof.box_size() + HEADER_SIZE;
+    moof.trafs[0].trun.as_mut().unwrap().data_offset = Some(data_offset as i32);
+    moof.write_box(buf).unwrap();
+
+    let mdat_size = HEADER_SIZE + samples.iter().map(|s| s.len() as u64).sum::<u64>();
+    BoxHeader::new(BoxType::MdatBox, mdat_size)
+        .write(buf)
+        .unwrap();
+    for sample in samples.iter() {
+        buf.extend_from_slice(sample);
+    }
+}
+
+pub(crate) fn fragmented_mp4() -> Vec<u8> {
+    let mut buf = Vec::new();
+    let ftyp = FtypBox {
+        major_brand: str::parse("iso6").unwrap(),
+        minor_version: 0,
+        compatible_brands: vec![str::parse("iso6").unwrap()],
+    };
+    ftyp.write_box(&mut buf).unwrap();
+
+    let mut track = Mp4TrackWriter::new(1, &TrackConfig::from(TtxtConfig {})).unwrap();
+    let trak = track.write_end(&mut Cursor::new(Vec::new()), 1000).unwrap();
+    let moov = MoovBox {
+        mvex: Some(MvexBox {
+            mehd: None,
+            trexs: vec![TrexBox {
+                track_id: 1,
+                default_sample_description_index: 1,
+                default_sample_duration: 1000,
+                default_sample_flags: 0x10000, // non-sync
+                ..TrexBox::default()
+            }],
+            ..MvexBox::default()
+        }),
+        traks: vec![trak],
+        ..MoovBox::default()
+    };
+    moov.write_box(&mut buf).unwrap();
+
+    // Per-sample sizes and composition offsets, durations from trex.
+    let traf = TrafBox {
+        tfhd: TfhdBox {
+            flags: TfhdBox::FLAG_DEFAULT_BASE_IS_MOOF,
+            track_id: 1,
+            ..TfhdBox::default()
+        },
+        tfdt: Some(TfdtBox {
+            version: 1,
+            flags: 0,
+            base_media_decode_time: 5000,
+        }),
+        trun: Some(TrunBox {
+            flags: TrunBox::FLAG_DATA_OFFSET
+                | TrunBox::FLAG_FIRST_SAMPLE_FLAGS
+                | TrunBox::FLAG_SAMPLE_SIZE
+                | TrunBox::FLAG_SAMPLE_CTS,
+            sample_count: 3,
+            data_offset: Some(0),
+            first_sample_flags: Some(0),
+            sample_sizes: vec![3, 5, 7],
+            sample_cts: vec![0, 2000, 1000],
+            ..TrunBox::default()
+        }),
+        ..TrafBox::default()
+    };
+    write_fragment(&mut buf, traf, &[vec![1; 3], vec![2; 5], vec![3; 7]]);
+
+    // Durations and sizes from tfhd defaults, no tfdt.
+    let traf = TrafBox {
+        tfhd: TfhdBox {
+            flags: TfhdBox::FLAG_DEFAULT_BASE_IS_MOOF
+                | TfhdBox::FLAG_DEFAULT_SAMPLE_DURATION
+                | TfhdBox::FLAG_DEFAULT_SAMPLE_SIZE,
+            track_id: 1,
+            default_sample_duration: Some(500),
+            default_sample_size: Some(4),
+            ..TfhdBox::default()
+        },
+        tfdt: None,
+        trun: Some(TrunBox {
+            flags: TrunBox::FLAG_DATA_OFFSET,
+            sample_count: 2,
+            data_offset: Some(0),
+            ..TrunBox::default()
+        }),
+        ..TrafBox::default()
+    };
+    write_fragment(&mut buf, traf, &[vec![4; 4], vec![5; 4]]);
+
+    buf
+}
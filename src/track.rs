@@ -1,5 +1,6 @@
-use bytes::BytesMut;
+use bytes::{Bytes, BytesMut};
 use std::cmp;
+use std::collections::VecDeque;
 use std::convert::TryFrom;
 use std::io::{Read, Seek, SeekFrom, Write};
 use std::time::Duration;
@@ -9,16 +10,26 @@ use crate::mp4box::traf::TrafBox;
 use crate::mp4box::*;
 use crate::mp4box::{
     avc1::Avc1Box,
-    hev1::Hev1Box,
+    btrt::BtrtBox,
+    hev1::{Hev1Box, HEVC_NAL_PPS, HEVC_NAL_SPS, HEVC_NAL_VPS},
     vp09::Vp09Box,
+    av01::Av01Box,
+    co64::Co64Box,
     ctts::CttsBox,
     ctts::CttsEntry,
+    edts::EdtsBox,
+    elst::{ElstBox, ElstEntry},
     mp4a::Mp4aBox,
+    opus::OpusBox,
     smhd::SmhdBox,
     stco::StcoBox,
     stsc::StscEntry,
+    stsd::SampleEntry,
+    tkhd::TrackFlag,
+    tref::{TrackReference, TrefBox},
     stss::StssBox,
     stts::SttsEntry,
+    trex::TrexBox,
     tx3g::Tx3gBox,
     vmhd::VmhdBox,
 };
@@ -30,6 +41,14 @@ pub struct TrackConfig {
     pub timescale: u32,
     pub language: String,
     pub media_conf: MediaConfig,
+
+    /// Edit list to write for the track. Left empty, no edts box is written.
+    pub edit_list: Vec<EditListEntry>,
+
+    pub chunk_policy: ChunkPolicy,
+
+    /// Flags, alternate group, layer, volume and matrix of the track header.
+    pub header: TrackHeader,
 }
 
 impl From<MediaConfig> for TrackConfig {
@@ -38,8 +57,10 @@ impl From<MediaConfig> for TrackConfig {
             MediaConfig::AvcConfig(avc_conf) => Self::from(avc_conf),
             MediaConfig::HevcConfig(hevc_conf) => Self::from(hevc_conf),
             MediaConfig::AacConfig(aac_conf) => Self::from(aac_conf),
+            MediaConfig::OpusConfig(opus_conf) => Self::from(opus_conf),
             MediaConfig::TtxtConfig(ttxt_conf) => Self::from(ttxt_conf),
             MediaConfig::Vp9Config(vp9_config) => Self::from(vp9_config),
+            MediaConfig::Av1Config(av1_config) => Self::from(av1_config),
         }
     }
 }
@@ -51,6 +72,9 @@ impl From<AvcConfig> for TrackConfig {
             timescale: 1000,               // XXX
             language: String::from("und"), // XXX
             media_conf: MediaConfig::AvcConfig(avc_conf),
+            edit_list: Vec::new(),
+            chunk_policy: ChunkPolicy::default(),
+            header: TrackHeader::from(TrackType::Video),
         }
     }
 }
@@ -62,6 +86,9 @@ impl From<HevcConfig> for TrackConfig {
             timescale: 1000,               // XXX
             language: String::from("und"), // XXX
             media_conf: MediaConfig::HevcConfig(hevc_conf),
+            edit_list: Vec::new(),
+            chunk_policy: ChunkPolicy::default(),
+            header: TrackHeader::from(TrackType::Video),
         }
     }
 }
@@ -73,6 +100,23 @@ impl From<AacConfig> for TrackConfig {
             timescale: 1000,               // XXX
             language: String::from("und"), // XXX
             media_conf: MediaConfig::AacConfig(aac_conf),
+            edit_list: Vec::new(),
+            chunk_policy: ChunkPolicy::default(),
+            header: TrackHeader::from(TrackType::Audio),
+        }
+    }
+}
+
+impl From<OpusConfig> for TrackConfig {
+    fn from(opus_conf: OpusConfig) -> Self {
+        Self {
+            track_type: TrackType::Audio,
+            timescale: 48000,
+            language: String::from("und"), // XXX
+            media_conf: MediaConfig::OpusConfig(opus_conf),
+            edit_list: Vec::new(),
+            chunk_policy: ChunkPolicy::default(),
+            header: TrackHeader::from(TrackType::Audio),
         }
     }
 }
@@ -84,6 +128,9 @@ impl From<TtxtConfig> for TrackConfig {
             timescale: 1000,               // XXX
             language: String::from("und"), // XXX
             media_conf: MediaConfig::TtxtConfig(txtt_conf),
+            edit_list: Vec::new(),
+            chunk_policy: ChunkPolicy::default(),
+            header: TrackHeader::from(TrackType::Subtitle),
         }
     }
 }
@@ -95,23 +142,230 @@ impl From<Vp9Config> for TrackConfig {
             timescale: 1000,               // XXX
             language: String::from("und"), // XXX
             media_conf: MediaConfig::Vp9Config(vp9_conf),
+            edit_list: Vec::new(),
+            chunk_policy: ChunkPolicy::default(),
+            header: TrackHeader::from(TrackType::Video),
+        }
+    }
+}
+
+impl From<Av1Config> for TrackConfig {
+    fn from(av1_conf: Av1Config) -> Self {
+        Self {
+            track_type: TrackType::Video,
+            timescale: 1000,               // XXX
+            language: String::from("und"), // XXX
+            media_conf: MediaConfig::Av1Config(av1_conf),
+            edit_list: Vec::new(),
+            chunk_policy: ChunkPolicy::default(),
+            header: TrackHeader::from(TrackType::Video),
         }
     }
 }
 
+// sample_is_non_sync_sample bit of the trun/tfhd/trex sample flags.
+pub(crate) const SAMPLE_IS_NON_SYNC_SAMPLE: u32 = 0x10000;
+
 #[derive(Debug)]
 pub struct Mp4Track {
     pub trak: TrakBox,
     pub trafs: Vec<TrafBox>,
 
     // Fragmented Tracks Defaults.
-    pub default_sample_duration: u32,
+    pub trex: Option<TrexBox>,
+
+    // Every sample resolved once from the sample tables (or trun/tfhd/trex for
+    // fragments) so lookups are O(1).
+    samples: Vec<SampleInfo>,
+
+    // Decode time following the samples removed by take_samples.
+    next_decode_time: u64,
 }
 
 impl Mp4Track {
     pub(crate) fn from(trak: &TrakBox) -> Self {
         let trak = trak.clone();
-        Self { trak, trafs: Vec::new(), default_sample_duration: 0, }
+        let samples = Self::build_sample_index(&trak);
+        Self {
+            trak,
+            trafs: Vec::new(),
+            trex: None,
+            samples,
+            next_decode_time: 0,
+        }
+    }
+
+    /// Resolves every sample described by the stbl box. Indexing stops at the
+    /// first sample the tables can't fully describe.
+    fn build_sample_index(trak: &TrakBox) -> Vec<SampleInfo> {
+        let stbl = &trak.mdia.minf.stbl;
+        let sample_count = stbl.stsz.sample_count as usize;
+        let mut samples = Vec::new();
+
+        let chunk_count = if let Some(ref stco) = stbl.stco {
+            stco.entries.len() as u32
+        } else if let Some(ref co64) = stbl.co64 {
+            co64.entries.len() as u32
+        } else {
+            0
+        };
+        let chunk_offset = |chunk_id: u32| -> Option<u64> {
+            let idx = chunk_id.checked_sub(1)? as usize;
+            if let Some(ref stco) = stbl.stco {
+                stco.entries.get(idx).map(|offset| *offset as u64)
+            } else if let Some(ref co64) = stbl.co64 {
+                co64.entries.get(idx).copied()
+            } else {
+                None
+            }
+        };
+
+        let mut durations = stbl
+            .stts
+            .entries
+            .iter()
+            .flat_map(|entry| (0..entry.sample_count).map(move |_| entry.sample_delta));
+        let mut rendering_offsets = stbl
+            .ctts
+            .iter()
+            .flat_map(|ctts| ctts.entries.iter())
+            .flat_map(|entry| (0..entry.sample_count).map(move |_| entry.sample_offset));
+        let mut sync_samples = stbl.stss.as_ref().map(|stss| stss.entries.iter().peekable());
+
+        let mut start_time = 0;
+        'chunks: for (i, entry) in stbl.stsc.entries.iter().enumerate() {
+            let last_chunk = match stbl.stsc.entries.get(i + 1) {
+                Some(next) => next.first_chunk.saturating_sub(1),
+                None => chunk_count,
+            };
+            for chunk_id in entry.first_chunk..=last_chunk {
+                let mut offset = match chunk_offset(chunk_id) {
+                    Some(offset) => offset,
+                    None => break 'chunks,
+                };
+                for _ in 0..entry.samples_per_chunk {
+                    if samples.len() == sample_count {
+                        break 'chunks;
+                    }
+                    let size = if stbl.stsz.sample_size > 0 {
+                        stbl.stsz.sample_size
+                    } else {
+                        match stbl.stsz.sample_sizes.get(samples.len()) {
+                            Some(size) => *size,
+                            None => break 'chunks,
+                        }
+                    };
+                    let duration = match durations.next() {
+                        Some(duration) => duration,
+                        None => break 'chunks,
+                    };
+                    let sample_id = samples.len() as u32 + 1;
+                    let is_sync = match sync_samples {
+                        Some(ref mut entries) => {
+                            while entries.next_if(|id| **id < sample_id).is_some() {}
+                            entries.next_if_eq(&&sample_id).is_some()
+                        }
+                        None => true,
+                    };
+
+                    samples.push(SampleInfo {
+                        offset,
+                        size,
+                        start_time,
+                        duration,
+                        rendering_offset: rendering_offsets.next().unwrap_or(0),
+                        is_sync,
+                        sample_description_index: entry.sample_description_index,
+                    });
+                    offset += size as u64;
+                    start_time += duration as u64;
+                }
+            }
+        }
+
+        samples
+    }
+
+    /// Adds a track fragment found in the moof box starting at `moof_offset`.
+    ///
+    /// `default_data_offset` is used as the base data offset when tfhd neither
+    /// carries an explicit one nor sets default-base-is-moof. Returns the end
+    /// offset of the fragment's sample data, which is the implicit base of the
+    /// next traf in the same moof.
+    pub(crate) fn add_traf(
+        &mut self,
+        traf: &TrafBox,
+        moof_offset: u64,
+        default_data_offset: u64,
+    ) -> u64 {
+        let base_data_offset = if let Some(offset) = traf.tfhd.base_data_offset {
+            offset
+        } else if traf.tfhd.default_base_is_moof() {
+            moof_offset
+        } else {
+            default_data_offset
+        };
+
+        let mut offset = base_data_offset;
+        let mut sample_count = 0;
+        if let Some(ref trun) = traf.trun {
+            if let Some(data_offset) = trun.data_offset {
+                offset = (base_data_offset as i64 + data_offset as i64) as u64;
+            }
+            sample_count = trun.sample_count as usize;
+        }
+
+        let mut start_time = if let Some(ref tfdt) = traf.tfdt {
+            tfdt.base_media_decode_time
+        } else if let Some(last) = self.samples.last() {
+            last.start_time + last.duration as u64
+        } else {
+            self.next_decode_time
+        };
+
+        let sample_description_index = if let Some(idx) = traf.tfhd.sample_description_index {
+            idx
+        } else if let Some(ref trex) = self.trex {
+            trex.default_sample_description_index
+        } else {
+            1
+        };
+
+        for i in 0..sample_count {
+            let size = self.traf_sample_size(traf, i);
+            let duration = self.traf_sample_duration(traf, i);
+            let flags = self.traf_sample_flags(traf, i);
+            let rendering_offset = traf
+                .trun
+                .as_ref()
+                .and_then(|trun| trun.sample_cts.get(i))
+                .map_or(0, |cts| *cts as i32);
+
+            self.samples.push(SampleInfo {
+                offset,
+                size,
+                start_time,
+                duration,
+                rendering_offset,
+                is_sync: flags & SAMPLE_IS_NON_SYNC_SAMPLE == 0,
+                sample_description_index,
+            });
+            offset += size as u64;
+            start_time += duration as u64;
+        }
+        self.trafs.push(traf.clone());
+
+        offset
+    }
+
+    /// Removes and returns the samples indexed so far, for callers that consume
+    /// them as they arrive instead of keeping the whole index.
+    pub(crate) fn take_samples(&mut self) -> Vec<SampleInfo> {
+        if let Some(last) = self.samples.last() {
+            self.next_decode_time = last.start_time + last.duration as u64;
+        }
+        self.trafs.clear();
+        std::mem::take(&mut self.samples)
     }
 
     pub fn track_id(&self) -> u32 {
@@ -123,39 +377,32 @@ impl Mp4Track {
     }
 
     pub fn media_type(&self) -> Result<MediaType> {
-        if self.trak.mdia.minf.stbl.stsd.avc1.is_some() {
-            Ok(MediaType::H264)
-        } else if self.trak.mdia.minf.stbl.stsd.hev1.is_some() {
-            Ok(MediaType::H265)
-        } else if self.trak.mdia.minf.stbl.stsd.vp09.is_some() {
-            Ok(MediaType::VP9)
-        } else if self.trak.mdia.minf.stbl.stsd.mp4a.is_some() {
-            Ok(MediaType::AAC)
-        } else if self.trak.mdia.minf.stbl.stsd.tx3g.is_some() {
-            Ok(MediaType::TTXT)
-        } else {
-            Err(Error::InvalidData("unsupported media type"))
+        match self.trak.mdia.minf.stbl.stsd.entry(1) {
+            Some(SampleEntry::Avc1(_))
+            | Some(SampleEntry::Avc2(_))
+            | Some(SampleEntry::Avc3(_))
+            | Some(SampleEntry::Avc4(_)) => Ok(MediaType::H264),
+            Some(SampleEntry::Hev1(_)) | Some(SampleEntry::Hvc1(_)) => Ok(MediaType::H265),
+            Some(SampleEntry::Vp09(_)) => Ok(MediaType::VP9),
+            Some(SampleEntry::Av01(_)) => Ok(MediaType::AV1),
+            Some(SampleEntry::Mp4a(_)) => Ok(MediaType::AAC),
+            Some(SampleEntry::Opus(_)) => Ok(MediaType::OPUS),
+            Some(SampleEntry::Tx3g(_)) => Ok(MediaType::TTXT),
+            _ => Err(Error::InvalidData("unsupported media type")),
         }
     }
 
     pub fn box_type(&self) -> Result<FourCC> {
-        if self.trak.mdia.minf.stbl.stsd.avc1.is_some() {
-            Ok(FourCC::from(BoxType::Avc1Box))
-        } else if self.trak.mdia.minf.stbl.stsd.hev1.is_some() {
-            Ok(FourCC::from(BoxType::Hev1Box))
-        } else if self.trak.mdia.minf.stbl.stsd.vp09.is_some() {
-            Ok(FourCC::from(BoxType::Vp09Box))
-        } else if self.trak.mdia.minf.stbl.stsd.mp4a.is_some() {
-            Ok(FourCC::from(BoxType::Mp4aBox))
-        } else if self.trak.mdia.minf.stbl.stsd.tx3g.is_some() {
-            Ok(FourCC::from(BoxType::Tx3gBox))
-        } else {
-            Err(Error::InvalidData("unsupported sample entry box"))
+        match self.trak.mdia.minf.stbl.stsd.entry(1) {
+            Some(SampleEntry::Unknown(_)) | None => {
+                Err(Error::InvalidData("unsupported sample entry box"))
+            }
+            Some(entry) => Ok(FourCC::from(entry.box_type())),
         }
     }
 
     pub fn width(&self) -> u16 {
-        if let Some(ref avc1) = self.trak.mdia.minf.stbl.stsd.avc1 {
+        if let Some(avc1) = self.trak.mdia.minf.stbl.stsd.avc() {
             avc1.width
         } else {
             self.trak.tkhd.width.value()
@@ -163,7 +410,7 @@ impl Mp4Track {
     }
 
     pub fn height(&self) -> u16 {
-        if let Some(ref avc1) = self.trak.mdia.minf.stbl.stsd.avc1 {
+        if let Some(avc1) = self.trak.mdia.minf.stbl.stsd.avc() {
             avc1.height
         } else {
             self.trak.tkhd.height.value()
@@ -180,7 +427,7 @@ impl Mp4Track {
     }
 
     pub fn sample_freq_index(&self) -> Result<SampleFreqIndex> {
-        if let Some(ref mp4a) = self.trak.mdia.minf.stbl.stsd.mp4a {
+        if let Some(mp4a) = self.trak.mdia.minf.stbl.stsd.mp4a() {
             if let Some(ref esds) = mp4a.esds {
                 SampleFreqIndex::try_from(esds.es_desc.dec_config.dec_specific.freq_index)
             } else {
@@ -192,7 +439,7 @@ impl Mp4Track {
     }
 
     pub fn channel_config(&self) -> Result<ChannelConfig> {
-        if let Some(ref mp4a) = self.trak.mdia.minf.stbl.stsd.mp4a {
+        if let Some(mp4a) = self.trak.mdia.minf.stbl.stsd.mp4a() {
             if let Some(ref esds) = mp4a.esds {
                 ChannelConfig::try_from(esds.es_desc.dec_config.dec_specific.chan_conf)
             } else {
@@ -203,6 +450,14 @@ impl Mp4Track {
         }
     }
 
+    /// References from this track to others, from its tref box.
+    pub fn references(&self) -> &[TrackReference] {
+        match self.trak.tref {
+            Some(ref tref) => &tref.references,
+            None => &[],
+        }
+    }
+
     pub fn language(&self) -> &str {
         &self.trak.mdia.mdhd.language
     }
@@ -218,7 +473,10 @@ impl Mp4Track {
     }
 
     pub fn bitrate(&self) -> u32 {
-        if let Some(ref mp4a) = self.trak.mdia.minf.stbl.stsd.mp4a {
+        let btrt = self.trak.mdia.minf.stbl.stsd.entry(1).and_then(|entry| entry.btrt());
+        if let Some(btrt) = btrt.filter(|btrt| btrt.avg_bitrate > 0) {
+            btrt.avg_bitrate
+        } else if let Some(mp4a) = self.trak.mdia.minf.stbl.stsd.mp4a() {
             if let Some(ref esds) = mp4a.esds {
                 esds.es_desc.dec_config.avg_bitrate
             } else {
@@ -237,21 +495,11 @@ impl Mp4Track {
     }
 
     pub fn sample_count(&self) -> u32 {
-        if self.trafs.len() > 0 {
-            let mut sample_count = 0u32;
-            for traf in self.trafs.iter() {
-                if let Some(ref trun) = traf.trun {
-                    sample_count += trun.sample_count;
-                }
-            }
-            sample_count
-        } else {
-            self.trak.mdia.minf.stbl.stsz.sample_count
-        }
+        self.samples.len() as u32
     }
 
     pub fn video_profile(&self) -> Result<AvcProfile> {
-        if let Some(ref avc1) = self.trak.mdia.minf.stbl.stsd.avc1 {
+        if let Some(avc1) = self.trak.mdia.minf.stbl.stsd.avc() {
             AvcProfile::try_from((
                 avc1.avcc.avc_profile_indication,
                 avc1.avcc.profile_compatibility,
@@ -261,8 +509,73 @@ impl Mp4Track {
         }
     }
 
+    /// Returns whether an AVC track uses avc1, avc2, avc3 or avc4 sample entries.
+    pub fn avc_box_type(&self) -> Result<AvcBoxType> {
+        match self.trak.mdia.minf.stbl.stsd.entry(1) {
+            Some(SampleEntry::Avc1(_)) => Ok(AvcBoxType::Avc1),
+            Some(SampleEntry::Avc2(_)) => Ok(AvcBoxType::Avc2),
+            Some(SampleEntry::Avc3(_)) => Ok(AvcBoxType::Avc3),
+            Some(SampleEntry::Avc4(_)) => Ok(AvcBoxType::Avc4),
+            _ => Err(Error::BoxInStblNotFound(self.track_id(), BoxType::Avc1Box)),
+        }
+    }
+
+    /// Returns the codec configuration of an Opus track.
+    pub fn opus_config(&self) -> Result<OpusConfig> {
+        if let Some(opus) = self.trak.mdia.minf.stbl.stsd.opus() {
+            Ok(OpusConfig {
+                channel_count: opus.dops.output_channel_count,
+                pre_skip: opus.dops.pre_skip,
+                input_sample_rate: opus.dops.input_sample_rate,
+                output_gain: opus.dops.output_gain,
+                channel_mapping_family: opus.dops.channel_mapping_family,
+                channel_mapping_table: opus.dops.channel_mapping_table.clone(),
+            })
+        } else {
+            Err(Error::BoxInStblNotFound(self.track_id(), BoxType::OpusBox))
+        }
+    }
+
+    /// Returns the codec configuration of an AV1 track.
+    pub fn av1_config(&self) -> Result<Av1Config> {
+        if let Some(av01) = self.trak.mdia.minf.stbl.stsd.av01() {
+            Ok(Av1Config {
+                width: av01.width,
+                height: av01.height,
+                seq_profile: av01.av1c.seq_profile,
+                seq_level_idx: av01.av1c.seq_level_idx_0,
+                seq_tier: av01.av1c.seq_tier_0,
+                bit_depth: av01.av1c.bit_depth(),
+                monochrome: av01.av1c.monochrome,
+                chroma_subsampling_x: av01.av1c.chroma_subsampling_x,
+                chroma_subsampling_y: av01.av1c.chroma_subsampling_y,
+                chroma_sample_position: av01.av1c.chroma_sample_position,
+                config_obus: av01.av1c.config_obus.clone(),
+            })
+        } else {
+            Err(Error::BoxInStblNotFound(self.track_id(), BoxType::Av01Box))
+        }
+    }
+
+    /// Returns whether an HEVC track uses hev1 or hvc1 sample entries.
+    pub fn hevc_box_type(&self) -> Result<HevcBoxType> {
+        match self.trak.mdia.minf.stbl.stsd.entry(1) {
+            Some(SampleEntry::Hev1(_)) => Ok(HevcBoxType::Hev1),
+            Some(SampleEntry::Hvc1(_)) => Ok(HevcBoxType::Hvc1),
+            _ => Err(Error::BoxInStblNotFound(self.track_id(), BoxType::Hev1Box)),
+        }
+    }
+
+    pub fn video_parameter_set(&self) -> Result<&[u8]> {
+        if let Some(hev1) = self.trak.mdia.minf.stbl.stsd.hevc() {
+            self.hevc_parameter_set(hev1, HEVC_NAL_VPS)
+        } else {
+            Err(Error::BoxInStblNotFound(self.track_id(), BoxType::Hev1Box))
+        }
+    }
+
     pub fn sequence_parameter_set(&self) -> Result<&[u8]> {
-        if let Some(ref avc1) = self.trak.mdia.minf.stbl.stsd.avc1 {
+        if let Some(avc1) = self.trak.mdia.minf.stbl.stsd.avc() {
             match avc1.avcc.sequence_parameter_sets.get(0) {
                 Some(ref nal) => Ok(nal.bytes.as_ref()),
                 None => Err(Error::EntryInStblNotFound(
@@ -271,13 +584,15 @@ impl Mp4Track {
                     0,
                 )),
             }
+        } else if let Some(hev1) = self.trak.mdia.minf.stbl.stsd.hevc() {
+            self.hevc_parameter_set(hev1, HEVC_NAL_SPS)
         } else {
             Err(Error::BoxInStblNotFound(self.track_id(), BoxType::Avc1Box))
         }
     }
 
     pub fn picture_parameter_set(&self) -> Result<&[u8]> {
-        if let Some(ref avc1) = self.trak.mdia.minf.stbl.stsd.avc1 {
+        if let Some(avc1) = self.trak.mdia.minf.stbl.stsd.avc() {
             match avc1.avcc.picture_parameter_sets.get(0) {
                 Some(ref nal) => Ok(nal.bytes.as_ref()),
                 None => Err(Error::EntryInStblNotFound(
@@ -286,13 +601,35 @@ impl Mp4Track {
                     0,
                 )),
             }
+        } else if let Some(hev1) = self.trak.mdia.minf.stbl.stsd.hevc() {
+            self.hevc_parameter_set(hev1, HEVC_NAL_PPS)
         } else {
             Err(Error::BoxInStblNotFound(self.track_id(), BoxType::Avc1Box))
         }
     }
 
+    /// Returns the SPS extension of high profile AVC tracks, if any.
+    pub fn sequence_parameter_set_ext(&self) -> Option<&[u8]> {
+        let avc = self.trak.mdia.minf.stbl.stsd.avc()?;
+        let ext = avc.avcc.ext.as_ref()?;
+        ext.sequence_parameter_set_exts
+            .first()
+            .map(|nal| nal.bytes.as_ref())
+    }
+
+    fn hevc_parameter_set<'a>(&self, hev1: &'a Hev1Box, nal_unit_type: u8) -> Result<&'a [u8]> {
+        match hev1.hvcc.nal_unit(nal_unit_type) {
+            Some(nal) => Ok(nal.bytes.as_ref()),
+            None => Err(Error::EntryInStblNotFound(
+                self.track_id(),
+                BoxType::HvcCBox,
+                0,
+            )),
+        }
+    }
+
     pub fn audio_profile(&self) -> Result<AudioObjectType> {
-        if let Some(ref mp4a) = self.trak.mdia.minf.stbl.stsd.mp4a {
+        if let Some(mp4a) = self.trak.mdia.minf.stbl.stsd.mp4a() {
             if let Some(ref esds) = mp4a.esds {
                 AudioObjectType::try_from(esds.es_desc.dec_config.dec_specific.profile)
             } else {
@@ -303,219 +640,190 @@ impl Mp4Track {
         }
     }
 
-    fn stsc_index(&self, sample_id: u32) -> Result<usize> {
-        if self.trak.mdia.minf.stbl.stsc.entries.is_empty() {
-            return Err(Error::InvalidData("no stsc entries"));
-        }
-        for (i, entry) in self.trak.mdia.minf.stbl.stsc.entries.iter().enumerate() {
-            if sample_id < entry.first_sample {
-                return if i == 0 {
-                    Err(Error::InvalidData("sample not found"))
-                } else {
-                    Ok(i - 1)
-                };
-            }
-        }
-        Ok(self.trak.mdia.minf.stbl.stsc.entries.len() - 1)
+    /// Returns the location and timing of a sample without reading it.
+    pub fn sample_info(&self, sample_id: u32) -> Option<SampleInfo> {
+        sample_id
+            .checked_sub(1)
+            .and_then(|i| self.samples.get(i as usize))
+            .copied()
     }
 
-    fn chunk_offset(&self, chunk_id: u32) -> Result<u64> {
-        if self.trak.mdia.minf.stbl.stco.is_none() && self.trak.mdia.minf.stbl.co64.is_none() {
-            return Err(Error::InvalidData("must have either stco or co64 boxes"));
-        }
-        if let Some(ref stco) = self.trak.mdia.minf.stbl.stco {
-            if let Some(offset) = stco.entries.get(chunk_id as usize - 1) {
-                return Ok(*offset as u64);
-            } else {
-                return Err(Error::EntryInStblNotFound(
-                    self.track_id(),
-                    BoxType::StcoBox,
-                    chunk_id,
-                ));
-            }
-        } else if let Some(ref co64) = self.trak.mdia.minf.stbl.co64 {
-            if let Some(offset) = co64.entries.get(chunk_id as usize - 1) {
-                return Ok(*offset);
-            } else {
-                return Err(Error::EntryInStblNotFound(
-                    self.track_id(),
-                    BoxType::Co64Box,
-                    chunk_id,
-                ));
-            }
-        }
-        return Err(Error::Box2NotFound(BoxType::StcoBox, BoxType::Co64Box));
+    /// Iterates the location and timing of every sample in decoding order.
+    pub fn sample_infos(&self) -> impl Iterator<Item = SampleInfo> + '_ {
+        self.samples.iter().copied()
     }
 
-    fn ctts_index(&self, sample_id: u32) -> Result<(usize, u32)> {
-        let ctts = self.trak.mdia.minf.stbl.ctts.as_ref().unwrap();
-        let mut sample_count = 1;
-        for (i, entry) in ctts.entries.iter().enumerate() {
-            if sample_id <= sample_count + entry.sample_count - 1 {
-                return Ok((i, sample_count));
-            }
-            sample_count += entry.sample_count;
+    fn traf_sample_size(&self, traf: &TrafBox, sample_idx: usize) -> u32 {
+        if let Some(size) = traf.trun.as_ref().and_then(|t| t.sample_sizes.get(sample_idx)) {
+            *size
+        } else if let Some(size) = traf.tfhd.default_sample_size {
+            size
+        } else if let Some(ref trex) = self.trex {
+            trex.default_sample_size
+        } else {
+            0
         }
-
-        return Err(Error::EntryInStblNotFound(
-            self.track_id(),
-            BoxType::CttsBox,
-            sample_id,
-        ));
     }
 
-    /// return `(traf_idx, sample_idx_in_trun)`
-    fn find_traf_idx_and_sample_idx(&self, sample_id: u32) -> Option<(usize, usize)>{
-        let global_idx = sample_id - 1;
-        let mut offset = 0;
-        for traf_idx in 0..self.trafs.len() {
-            if let Some(trun) = &self.trafs[traf_idx].trun {
-                let sample_count = trun.sample_count;
-                if sample_count > (global_idx - offset) {
-                    return Some((traf_idx, (global_idx - offset) as _));
-                }
-                offset += sample_count;
-            }
+    fn traf_sample_duration(&self, traf: &TrafBox, sample_idx: usize) -> u32 {
+        if let Some(duration) = traf.trun.as_ref().and_then(|t| t.sample_durations.get(sample_idx)) {
+            *duration
+        } else if let Some(duration) = traf.tfhd.default_sample_duration {
+            duration
+        } else if let Some(ref trex) = self.trex {
+            trex.default_sample_duration
+        } else {
+            0
         }
-        None
     }
 
-    fn sample_size(&self, sample_id: u32) -> Result<u32> {
-        if self.trafs.len() > 0 {
-            if let Some((traf_idx, sample_idx)) = self.find_traf_idx_and_sample_idx(sample_id) {
-                if let Some(size) = self.trafs[traf_idx].trun.as_ref().unwrap().sample_sizes.get(sample_idx) {
-                    Ok(*size)
-                } else {
-                    Err(Error::EntryInTrunNotFound(
-                        self.track_id(),
-                        BoxType::TrunBox,
-                        sample_id,
-                    ))
+    fn traf_sample_flags(&self, traf: &TrafBox, sample_idx: usize) -> u32 {
+        if let Some(ref trun) = traf.trun {
+            if sample_idx == 0 {
+                if let Some(flags) = trun.first_sample_flags {
+                    return flags;
                 }
-            } else {
-                Err(Error::BoxInTrafNotFound(
-                    self.track_id(),
-                    BoxType::TrafBox,
-                ))
             }
-        } else {
-            let stsz = &self.trak.mdia.minf.stbl.stsz;
-            if stsz.sample_size > 0 {
-                return Ok(stsz.sample_size);
-            }
-            if let Some(size) = stsz.sample_sizes.get(sample_id as usize - 1) {
-                Ok(*size)
-            } else {
-                return Err(Error::EntryInStblNotFound(
-                    self.track_id(),
-                    BoxType::StszBox,
-                    sample_id,
-                ));
+            if let Some(flags) = trun.sample_flags.get(sample_idx) {
+                return *flags;
             }
         }
-    }
-
-    fn total_sample_size(&self) -> u64 {
-        let stsz = &self.trak.mdia.minf.stbl.stsz;
-        if stsz.sample_size > 0 {
-            stsz.sample_size as u64 * self.sample_count() as u64
+        if let Some(flags) = traf.tfhd.default_sample_flags {
+            flags
+        } else if let Some(ref trex) = self.trex {
+            trex.default_sample_flags
         } else {
-            let mut total_size = 0;
-            for size in stsz.sample_sizes.iter() {
-                total_size += *size as u64;
-            }
-            total_size
+            0
         }
     }
 
-    fn sample_offset(&self, sample_id: u32) -> Result<u64> {
-        if self.trafs.len() > 0 {
-            if let Some((traf_idx, _sample_idx)) = self.find_traf_idx_and_sample_idx(sample_id) {
-                Ok(self.trafs[traf_idx].tfhd.base_data_offset as u64)
-            } else {
-                Err(Error::BoxInTrafNotFound(
-                    self.track_id(),
-                    BoxType::TrafBox,
-                ))
-            }
-        } else {
-            let stsc_index = self.stsc_index(sample_id)?;
-
-            let stsc = &self.trak.mdia.minf.stbl.stsc;
-            let stsc_entry = stsc.entries.get(stsc_index).unwrap();
-
-            let first_chunk = stsc_entry.first_chunk;
-            let first_sample = stsc_entry.first_sample;
-            let samples_per_chunk = stsc_entry.samples_per_chunk;
-
-            let chunk_id = first_chunk + (sample_id - first_sample) / samples_per_chunk;
+    fn total_sample_size(&self) -> u64 {
+        self.samples.iter().map(|sample| sample.size as u64).sum()
+    }
 
-            let chunk_offset = self.chunk_offset(chunk_id)?;
+    fn edit_list(&self) -> Option<&[ElstEntry]> {
+        self.trak
+            .edts
+            .as_ref()
+            .and_then(|edts| edts.elst.as_ref())
+            .map(|elst| elst.entries.as_slice())
+            .filter(|entries| !entries.is_empty())
+    }
 
-            let first_sample_in_chunk = sample_id - (sample_id - first_sample) % samples_per_chunk;
+    /// Maps a composition time to the presentation timeline, both in the media
+    /// timescale. Edits are applied in order; a composition time outside every
+    /// edit is shifted like the first non-empty edit.
+    fn media_to_presentation_time(&self, cts: i64, movie_timescale: u32) -> i64 {
+        let edits = match self.edit_list() {
+            Some(edits) => edits,
+            None => return cts,
+        };
 
-            let mut sample_offset = 0;
-            for i in first_sample_in_chunk..sample_id {
-                sample_offset += self.sample_size(i)?;
+        let mut edit_start = 0;
+        let mut shift = None;
+        for edit in edits.iter() {
+            let segment_duration =
+                rescale(edit.segment_duration as i64, movie_timescale, self.timescale());
+            if !edit.is_empty_edit() {
+                if cts >= edit.media_time
+                    && (segment_duration == 0 || cts < edit.media_time + segment_duration)
+                {
+                    return edit_start + cts - edit.media_time;
+                }
+                shift.get_or_insert(edit_start - edit.media_time);
             }
-
-            Ok(chunk_offset + sample_offset as u64)
+            edit_start += segment_duration;
         }
+        cmp::max(cts + shift.unwrap_or(0), 0)
     }
 
-    fn sample_time(&self, sample_id: u32) -> Result<(u64, u32)> {
-        let stts = &self.trak.mdia.minf.stbl.stts;
-
-        let mut sample_count = 1;
-        let mut elapsed = 0;
-
-        if self.trafs.len() > 0 {
-            let start_time =  ((sample_id - 1) * self.default_sample_duration) as u64;
-            return Ok((start_time, self.default_sample_duration))
-        } else {
-            for entry in stts.entries.iter() {
-                if sample_id <= sample_count + entry.sample_count - 1 {
-                    let start_time =
-                        (sample_id - sample_count) as u64 * entry.sample_delta as u64 + elapsed;
-                    return Ok((start_time, entry.sample_delta));
-                }
+    /// Maps a presentation time to a composition time, both in the media
+    /// timescale. A time inside an empty edit maps to the start of the next
+    /// edit's media.
+    fn presentation_to_media_time(&self, mut time: i64, movie_timescale: u32) -> i64 {
+        let edits = match self.edit_list() {
+            Some(edits) => edits,
+            None => return time,
+        };
 
-                sample_count += entry.sample_count;
-                elapsed += entry.sample_count as u64 * entry.sample_delta as u64;
+        let mut edit_start = 0;
+        let mut last_edit = None;
+        for edit in edits.iter() {
+            let segment_duration =
+                rescale(edit.segment_duration as i64, movie_timescale, self.timescale());
+            if edit.is_empty_edit() {
+                time = cmp::max(time, edit_start + segment_duration);
+            } else if segment_duration == 0 || time < edit_start + segment_duration {
+                return edit.media_time + time - edit_start;
+            } else {
+                last_edit = Some((edit_start, edit.media_time));
             }
-
-            return Err(Error::EntryInStblNotFound(
-                self.track_id(),
-                BoxType::SttsBox,
-                sample_id,
-            ));
+            edit_start += segment_duration;
+        }
+        match last_edit {
+            Some((edit_start, media_time)) => media_time + time - edit_start,
+            None => time,
         }
     }
 
-    fn sample_rendering_offset(&self, sample_id: u32) -> i32 {
-        if let Some(ref ctts) = self.trak.mdia.minf.stbl.ctts {
-            if let Ok((ctts_index, _)) = self.ctts_index(sample_id) {
-                let ctts_entry = ctts.entries.get(ctts_index).unwrap();
-                return ctts_entry.sample_offset;
+    /// Returns the presentation time of a sample, after applying the edit list.
+    pub(crate) fn presentation_time(&self, sample_id: u32, movie_timescale: u32) -> Result<Duration> {
+        let sample = match sample_id.checked_sub(1).and_then(|i| self.samples.get(i as usize)) {
+            Some(sample) => sample,
+            None => {
+                return Err(Error::EntryInStblNotFound(
+                    self.track_id(),
+                    BoxType::StszBox,
+                    sample_id,
+                ))
             }
-        }
-        0
+        };
+
+        let cts = sample.start_time as i64 + sample.rendering_offset as i64;
+        let time = cmp::max(self.media_to_presentation_time(cts, movie_timescale), 0);
+        Ok(Duration::from_nanos(rescale(time, self.timescale(), 1_000_000_000) as u64))
     }
 
-    fn is_sync_sample(&self, sample_id: u32) -> bool {
-        if self.trafs.len() > 0 {
-            let sample_sizes_count = self.sample_count() / self.trafs.len() as u32;
-            return sample_id == 1 || sample_id % sample_sizes_count == 0
+    /// Finds the sample presented at `time`. Only samples decoded at or before
+    /// `time` back to the previous sync sample are considered, which covers the
+    /// composition reordering of a regular GOP.
+    pub(crate) fn seek(&self, time: Duration, mode: SeekMode, movie_timescale: u32) -> Result<u32> {
+        if self.samples.is_empty() {
+            return Err(Error::InvalidData("cannot seek in a track without samples"));
         }
 
-        if let Some(ref stss) = self.trak.mdia.minf.stbl.stss {
-            match stss.entries.binary_search(&sample_id) {
-                Ok(_) => true,
-                Err(_) => false,
+        let time = rescale(time.as_nanos() as i64, 1_000_000_000, self.timescale());
+        let target = self.presentation_to_media_time(time, movie_timescale);
+
+        let decoded = self
+            .samples
+            .partition_point(|sample| sample.start_time as i64 <= target);
+        let mut exact = 0;
+        let mut exact_cts = None;
+        for idx in (0..decoded).rev() {
+            let sample = &self.samples[idx];
+            let cts = sample.start_time as i64 + sample.rendering_offset as i64;
+            let later = match exact_cts {
+                Some(best) => cts > best,
+                None => true,
+            };
+            if cts <= target && later {
+                exact = idx;
+                exact_cts = Some(cts);
+            }
+            if sample.is_sync {
+                break;
             }
-        } else {
-            true
         }
+
+        let sample_idx = match mode {
+            SeekMode::Exact => exact,
+            SeekMode::PreviousSync => (0..=exact)
+                .rev()
+                .find(|idx| self.samples[*idx].is_sync)
+                .unwrap_or(0),
+        };
+        Ok(sample_idx as u32 + 1)
     }
 
     pub(crate) fn read_sample<R: Read + Seek>(
@@ -523,32 +831,80 @@ impl Mp4Track {
         reader: &mut R,
         sample_id: u32,
     ) -> Result<Option<Mp4Sample>> {
-        let sample_offset = match self.sample_offset(sample_id) {
-            Ok(offset) => offset,
-            Err(Error::EntryInStblNotFound(_, _, _)) => return Ok(None),
-            Err(err) => return Err(err),
+        let sample = match sample_id.checked_sub(1).and_then(|i| self.samples.get(i as usize)) {
+            Some(sample) => sample,
+            None => return Ok(None),
         };
-        let sample_size = self.sample_size(sample_id).unwrap();
 
-        let mut buffer = vec![0x0u8; sample_size as usize];
-        reader.seek(SeekFrom::Start(sample_offset))?;
+        let mut buffer = vec![0x0u8; sample.size as usize];
+        reader.seek(SeekFrom::Start(sample.offset))?;
         reader.read_exact(&mut buffer)?;
 
-        let (start_time, duration) = self.sample_time(sample_id).unwrap(); // XXX
-        let rendering_offset = self.sample_rendering_offset(sample_id);
-        let is_sync = self.is_sync_sample(sample_id);
-
         Ok(Some(Mp4Sample {
-            start_time,
-            duration,
-            rendering_offset,
-            is_sync,
+            start_time: sample.start_time,
+            duration: sample.duration,
+            rendering_offset: sample.rendering_offset,
+            is_sync: sample.is_sync,
+            sample_description_index: sample.sample_description_index,
             bytes: Bytes::from(buffer),
         }))
     }
 }
 
-// TODO creation_time, modification_time
+fn sample_entry(media_conf: &MediaConfig) -> Result<SampleEntry> {
+    let entry = match media_conf {
+        MediaConfig::AvcConfig(ref avc_config) => {
+            let avc = Avc1Box::new(avc_config);
+            match avc_config.box_type {
+                AvcBoxType::Avc1 => SampleEntry::Avc1(avc),
+                AvcBoxType::Avc2 => SampleEntry::Avc2(avc),
+                AvcBoxType::Avc3 => SampleEntry::Avc3(avc),
+                AvcBoxType::Avc4 => SampleEntry::Avc4(avc),
+            }
+        }
+        MediaConfig::HevcConfig(ref hevc_config) => match hevc_config.box_type {
+            HevcBoxType::Hev1 => SampleEntry::Hev1(Hev1Box::new(hevc_config)),
+            HevcBoxType::Hvc1 => SampleEntry::Hvc1(Hev1Box::new(hevc_config)),
+        },
+        MediaConfig::Vp9Config(ref config) => SampleEntry::Vp09(Vp09Box::new(config)),
+        MediaConfig::Av1Config(ref config) => SampleEntry::Av01(Av01Box::new(config)),
+        MediaConfig::AacConfig(ref aac_config) => SampleEntry::Mp4a(Mp4aBox::new(aac_config)),
+        MediaConfig::OpusConfig(ref opus_config) => {
+            check_opus_config(opus_config)?;
+            SampleEntry::Opus(OpusBox::new(opus_config))
+        }
+        MediaConfig::TtxtConfig(_) => SampleEntry::Tx3g(Tx3gBox::default()),
+    };
+    Ok(entry)
+}
+
+// dOps has a channel mapping table, with an entry per output channel, exactly
+// when the channel mapping family isn't 0.
+fn check_opus_config(config: &OpusConfig) -> Result<()> {
+    match config.channel_mapping_table {
+        None if config.channel_mapping_family != 0 => Err(Error::InvalidData(
+            "opus channel mapping family requires a channel mapping table",
+        )),
+        Some(_) if config.channel_mapping_family == 0 => Err(Error::InvalidData(
+            "opus channel mapping family 0 has no channel mapping table",
+        )),
+        Some(ref table) if table.channel_mapping.len() != config.channel_count as usize => {
+            Err(Error::InvalidData(
+                "opus channel mapping doesn't match the channel count",
+            ))
+        }
+        _ => Ok(()),
+    }
+}
+
+/// Converts `value` from one timescale to another.
+fn rescale(value: i64, from: u32, to: u32) -> i64 {
+    if from == 0 {
+        return 0;
+    }
+    (value as i128 * to as i128 / from as i128) as i64
+}
+
 #[derive(Debug, Default)]
 pub(crate) struct Mp4TrackWriter {
     trak: TrakBox,
@@ -557,21 +913,56 @@ pub(crate) struct Mp4TrackWriter {
     fixed_sample_size: u32,
     is_fixed_sample_size: bool,
     chunk_samples: u32,
-    chunk_duration: u32,
+    chunk_duration: u64,
     chunk_buffer: BytesMut,
+    chunk_first_sample: u32,
+    chunk_start_time: u64,
+    chunk_description_index: u32,
+    chunk_policy: ChunkPolicy,
 
-    samples_per_chunk: u32,
-    duration_per_chunk: u32,
+    // Closed chunks that haven't been written to mdat yet.
+    pending_chunks: VecDeque<Chunk>,
+
+    // Decode times and sizes of the samples in the last second, for the
+    // peak bitrate.
+    bitrate_window: VecDeque<(u64, u32)>,
+    bitrate_window_size: u64,
+    max_window_size: u64,
+
+    edit_list: Vec<EditListEntry>,
+}
+
+#[derive(Debug)]
+struct Chunk {
+    data: Bytes,
+    sample_count: u32,
+    first_sample: u32,
+    start_time: u64,
+    sample_description_index: u32,
 }
 
 impl Mp4TrackWriter {
     pub(crate) fn new(track_id: u32, config: &TrackConfig) -> Result<Self> {
         let mut trak = TrakBox::default();
         trak.tkhd.track_id = track_id;
+        trak.tkhd.flags = 0;
+        if config.header.enabled {
+            trak.tkhd.flags |= TrackFlag::TrackEnabled as u32;
+        }
+        if config.header.in_movie {
+            trak.tkhd.flags |= TrackFlag::InMovie as u32;
+        }
+        if config.header.in_preview {
+            trak.tkhd.flags |= TrackFlag::InPreview as u32;
+        }
+        trak.tkhd.alternate_group = config.header.alternate_group;
+        trak.tkhd.layer = config.header.layer;
+        trak.tkhd.volume = config.header.volume;
+        trak.tkhd.matrix = config.header.matrix.clone();
         trak.mdia.mdhd.timescale = config.timescale;
         trak.mdia.mdhd.language = config.language.to_owned();
         trak.mdia.hdlr.handler_type = config.track_type.into();
-        // XXX largesize
+        // Switched to co64 once a chunk offset needs 64 bits.
         trak.mdia.minf.stbl.stco = Some(StcoBox::default());
         match config.media_conf {
             MediaConfig::AvcConfig(ref avc_config) => {
@@ -580,9 +971,6 @@ impl Mp4TrackWriter {
 
                 let vmhd = VmhdBox::default();
                 trak.mdia.minf.vmhd = Some(vmhd);
-
-                let avc1 = Avc1Box::new(avc_config);
-                trak.mdia.minf.stbl.stsd.avc1 = Some(avc1);
             }
             MediaConfig::HevcConfig(ref hevc_config) => {
                 trak.tkhd.set_width(hevc_config.width);
@@ -590,38 +978,71 @@ impl Mp4TrackWriter {
 
                 let vmhd = VmhdBox::default();
                 trak.mdia.minf.vmhd = Some(vmhd);
-
-                let hev1 = Hev1Box::new(hevc_config);
-                trak.mdia.minf.stbl.stsd.hev1 = Some(hev1);
             }
             MediaConfig::Vp9Config(ref config) => {
                 trak.tkhd.set_width(config.width);
                 trak.tkhd.set_height(config.height);
+            }
+            MediaConfig::Av1Config(ref config) => {
+                trak.tkhd.set_width(config.width);
+                trak.tkhd.set_height(config.height);
 
-                trak.mdia.minf.stbl.stsd.vp09 = Some(Vp09Box::new(config));
+                let vmhd = VmhdBox::default();
+                trak.mdia.minf.vmhd = Some(vmhd);
             }
-            MediaConfig::AacConfig(ref aac_config) => {
+            MediaConfig::AacConfig(_) | MediaConfig::OpusConfig(_) => {
                 let smhd = SmhdBox::default();
                 trak.mdia.minf.smhd = Some(smhd);
-
-                let mp4a = Mp4aBox::new(aac_config);
-                trak.mdia.minf.stbl.stsd.mp4a = Some(mp4a);
-            }
-            MediaConfig::TtxtConfig(ref _ttxt_config) => {
-                let tx3g = Tx3gBox::default();
-                trak.mdia.minf.stbl.stsd.tx3g = Some(tx3g);
             }
-
+            MediaConfig::TtxtConfig(_) => {}
         }
+        trak.mdia.minf.stbl.stsd.entries.push(sample_entry(&config.media_conf)?);
         Ok(Mp4TrackWriter {
             trak,
             chunk_buffer: BytesMut::new(),
             sample_id: 1,
-            duration_per_chunk: config.timescale, // 1 second
+            chunk_policy: config.chunk_policy,
+            edit_list: config.edit_list.clone(),
             ..Self::default()
         })
     }
 
+    pub(crate) fn track_id(&self) -> u32 {
+        self.trak.tkhd.track_id
+    }
+
+    /// Adds a sample entry for `media_conf` and returns its sample
+    /// description index.
+    pub(crate) fn add_sample_description(&mut self, media_conf: &MediaConfig) -> Result<u32> {
+        let track_type = TrackConfig::from(media_conf.clone()).track_type;
+        if TrackType::try_from(&self.trak.mdia.hdlr.handler_type)? != track_type {
+            return Err(Error::InvalidData("sample description doesn't match the track type"));
+        }
+        let stsd = &mut self.trak.mdia.minf.stbl.stsd;
+        stsd.entries.push(sample_entry(media_conf)?);
+        Ok(stsd.entries.len() as u32)
+    }
+
+    pub(crate) fn add_reference(&mut self, reference_type: FourCC, track_id: u32) {
+        self.trak
+            .tref
+            .get_or_insert_with(TrefBox::default)
+            .add(reference_type, track_id);
+    }
+
+    /// Sets the creation and modification time, in seconds since the MP4
+    /// epoch, of tkhd and mdhd.
+    pub(crate) fn set_times(&mut self, creation_time: u64, modification_time: u64) {
+        self.trak.tkhd.creation_time = creation_time;
+        self.trak.tkhd.modification_time = modification_time;
+        self.trak.mdia.mdhd.creation_time = creation_time;
+        self.trak.mdia.mdhd.modification_time = modification_time;
+    }
+
+    pub(crate) fn trak(&self) -> &TrakBox {
+        &self.trak
+    }
+
     fn update_sample_sizes(&mut self, size: u32) {
         if self.trak.mdia.minf.stbl.stsz.sample_count == 0 {
             if size == 0 {
@@ -725,11 +1146,51 @@ impl Mp4TrackWriter {
         };
     }
 
-    fn is_chunk_full(&self) -> bool {
-        if self.samples_per_chunk > 0 {
-            self.chunk_samples >= self.samples_per_chunk
+    fn update_bitrate_window(&mut self, size: u32) {
+        let time = self.trak.mdia.mdhd.duration;
+        let timescale = self.trak.mdia.mdhd.timescale as u64;
+        while let Some(&(start, start_size)) = self.bitrate_window.front() {
+            if start + timescale > time {
+                break;
+            }
+            self.bitrate_window.pop_front();
+            self.bitrate_window_size -= start_size as u64;
+        }
+        self.bitrate_window.push_back((time, size));
+        self.bitrate_window_size += size as u64;
+        self.max_window_size = cmp::max(self.max_window_size, self.bitrate_window_size);
+    }
+
+    /// Returns the average bitrate and the peak bitrate over any one second
+    /// window, in bits per second.
+    fn bitrates(&self) -> (u32, u32) {
+        let stsz = &self.trak.mdia.minf.stbl.stsz;
+        let duration = self.trak.mdia.mdhd.duration;
+        if duration == 0 {
+            return (0, 0);
+        }
+        let total_size = if stsz.sample_size > 0 {
+            stsz.sample_size as u64 * stsz.sample_count as u64
         } else {
-            self.chunk_duration >= self.duration_per_chunk
+            stsz.sample_sizes.iter().map(|size| *size as u64).sum()
+        };
+        let timescale = self.trak.mdia.mdhd.timescale as u128;
+        let avg_bitrate = total_size as u128 * 8 * timescale / duration as u128;
+        let max_bitrate = cmp::max(self.max_window_size as u128 * 8, avg_bitrate);
+        (
+            cmp::min(avg_bitrate, u32::MAX as u128) as u32,
+            cmp::min(max_bitrate, u32::MAX as u128) as u32,
+        )
+    }
+
+    fn is_chunk_full(&self) -> bool {
+        match self.chunk_policy {
+            ChunkPolicy::Duration(duration) => {
+                let timescale = self.trak.mdia.mdhd.timescale as u128;
+                self.chunk_duration as u128 * 1_000_000_000 >= duration.as_nanos() * timescale
+            }
+            ChunkPolicy::Samples(samples) => self.chunk_samples >= samples,
+            ChunkPolicy::Bytes(bytes) => self.chunk_buffer.len() as u64 >= bytes as u64,
         }
     }
 
@@ -739,21 +1200,34 @@ impl Mp4TrackWriter {
             dur as u64 * movie_timescale as u64 / self.trak.mdia.mdhd.timescale as u64;
     }
 
-    pub(crate) fn write_sample<W: Write + Seek>(
-        &mut self,
-        writer: &mut W,
-        sample: &Mp4Sample,
-        movie_timescale: u32,
-    ) -> Result<u64> {
+    /// Adds the sample to the current chunk, closing the chunk once it's
+    /// full. Closed chunks are written with [Mp4TrackWriter::write_chunk].
+    pub(crate) fn write_sample(&mut self, sample: &Mp4Sample, movie_timescale: u32) -> Result<u64> {
+        let description_count = self.trak.mdia.minf.stbl.stsd.entries.len() as u32;
+        if sample.sample_description_index == 0
+            || sample.sample_description_index > description_count
+        {
+            return Err(Error::InvalidData("invalid sample description index"));
+        }
+        // A chunk only holds samples of one sample description.
+        if self.chunk_description_index != sample.sample_description_index {
+            self.close_chunk();
+            self.chunk_description_index = sample.sample_description_index;
+        }
+        if self.chunk_samples == 0 {
+            self.chunk_first_sample = self.sample_id;
+            self.chunk_start_time = self.trak.mdia.mdhd.duration;
+        }
         self.chunk_buffer.extend_from_slice(&sample.bytes);
         self.chunk_samples += 1;
-        self.chunk_duration += sample.duration;
+        self.chunk_duration += sample.duration as u64;
         self.update_sample_sizes(sample.bytes.len() as u32);
         self.update_sample_times(sample.duration);
         self.update_rendering_offsets(sample.rendering_offset);
         self.update_sync_samples(sample.is_sync);
+        self.update_bitrate_window(sample.bytes.len() as u32);
         if self.is_chunk_full() {
-            self.write_chunk(writer)?;
+            self.close_chunk();
         }
         self.update_durations(sample.duration, movie_timescale);
 
@@ -762,48 +1236,92 @@ impl Mp4TrackWriter {
         Ok(self.trak.tkhd.duration)
     }
 
-    // XXX largesize
+    pub(crate) fn close_chunk(&mut self) {
+        if self.chunk_samples == 0 {
+            return;
+        }
+        self.pending_chunks.push_back(Chunk {
+            data: self.chunk_buffer.split().freeze(),
+            sample_count: self.chunk_samples,
+            first_sample: self.chunk_first_sample,
+            start_time: self.chunk_start_time,
+            sample_description_index: self.chunk_description_index,
+        });
+        self.chunk_samples = 0;
+        self.chunk_duration = 0;
+    }
+
+    pub(crate) fn has_pending_chunk(&self) -> bool {
+        !self.pending_chunks.is_empty()
+    }
+
+    /// Decode time of the next chunk to be written, which is the chunk being
+    /// filled if none are pending.
+    pub(crate) fn next_chunk_time(&self) -> Duration {
+        let time = match self.pending_chunks.front() {
+            Some(chunk) => chunk.start_time,
+            None if self.chunk_samples > 0 => self.chunk_start_time,
+            None => self.trak.mdia.mdhd.duration,
+        };
+        let nanos = rescale(time as i64, self.trak.mdia.mdhd.timescale, 1_000_000_000);
+        Duration::from_nanos(nanos as u64)
+    }
+
     fn chunk_count(&self) -> u32 {
-        let stco = self.trak.mdia.minf.stbl.stco.as_ref().unwrap();
-        stco.entries.len() as u32
+        let stbl = &self.trak.mdia.minf.stbl;
+        if let Some(ref co64) = stbl.co64 {
+            co64.entries.len() as u32
+        } else {
+            stbl.stco.as_ref().unwrap().entries.len() as u32
+        }
     }
 
-    fn update_sample_to_chunk(&mut self, chunk_id: u32) {
+    fn update_sample_to_chunk(&mut self, chunk_id: u32, chunk: &Chunk) {
         if let Some(ref entry) = self.trak.mdia.minf.stbl.stsc.entries.last() {
-            if entry.samples_per_chunk == self.chunk_samples {
+            if entry.samples_per_chunk == chunk.sample_count
+                && entry.sample_description_index == chunk.sample_description_index
+            {
                 return;
             }
         }
 
         let entry = StscEntry {
             first_chunk: chunk_id,
-            samples_per_chunk: self.chunk_samples,
-            sample_description_index: 1,
-            first_sample: self.sample_id - self.chunk_samples + 1,
+            samples_per_chunk: chunk.sample_count,
+            sample_description_index: chunk.sample_description_index,
+            first_sample: chunk.first_sample,
         };
         self.trak.mdia.minf.stbl.stsc.entries.push(entry);
     }
 
     fn update_chunk_offsets(&mut self, offset: u64) {
-        let stco = self.trak.mdia.minf.stbl.stco.as_mut().unwrap();
-        stco.entries.push(offset as u32);
-    }
+        let stbl = &mut self.trak.mdia.minf.stbl;
+        if offset > u32::MAX as u64 {
+            if let Some(stco) = stbl.stco.take() {
+                stbl.co64 = Some(Co64Box::from(&stco));
+            }
+        }
 
-    fn write_chunk<W: Write + Seek>(&mut self, writer: &mut W) -> Result<()> {
-        if self.chunk_buffer.is_empty() {
-            return Ok(());
+        if let Some(ref mut co64) = stbl.co64 {
+            co64.entries.push(offset);
+        } else {
+            stbl.stco.as_mut().unwrap().entries.push(offset as u32);
         }
+    }
+
+    /// Writes the oldest pending chunk, if any.
+    pub(crate) fn write_chunk<W: Write + Seek>(&mut self, writer: &mut W) -> Result<()> {
+        let chunk = match self.pending_chunks.pop_front() {
+            Some(chunk) => chunk,
+            None => return Ok(()),
+        };
         let chunk_offset = writer.seek(SeekFrom::Current(0))?;
 
-        writer.write_all(&self.chunk_buffer)?;
+        writer.write_all(&chunk.data)?;
 
-        self.update_sample_to_chunk(self.chunk_count() + 1);
+        self.update_sample_to_chunk(self.chunk_count() + 1, &chunk);
         self.update_chunk_offsets(chunk_offset);
 
-        self.chunk_buffer.clear();
-        self.chunk_samples = 0;
-        self.chunk_duration = 0;
-
         Ok(())
     }
 
@@ -819,17 +1337,93 @@ impl Mp4TrackWriter {
         }
     }
 
-    pub(crate) fn write_end<W: Write + Seek>(&mut self, writer: &mut W) -> Result<TrakBox> {
-        self.write_chunk(writer)?;
+    /// Builds the edts box for the configured edit list, with segment
+    /// durations in the movie timescale. Open-ended edits run to the end of
+    /// the media written so far.
+    pub(crate) fn edts(&self, movie_timescale: u32) -> Option<EdtsBox> {
+        if self.edit_list.is_empty() {
+            return None;
+        }
+
+        let timescale = self.trak.mdia.mdhd.timescale;
+        let media_duration = self.trak.mdia.mdhd.duration;
+        let entries: Vec<ElstEntry> = self
+            .edit_list
+            .iter()
+            .map(|edit| {
+                let duration = edit.duration.unwrap_or_else(|| {
+                    media_duration.saturating_sub(edit.media_time.unwrap_or(0))
+                });
+                ElstEntry {
+                    segment_duration: rescale(duration as i64, timescale, movie_timescale) as u64,
+                    media_time: edit.media_time.map_or(-1, |media_time| media_time as i64),
+                    media_rate: edit.media_rate,
+                    media_rate_fraction: 0,
+                }
+            })
+            .collect();
+        let version = if entries.iter().any(|entry| {
+            entry.segment_duration > u32::MAX as u64 || entry.media_time > i32::MAX as i64
+        }) {
+            1
+        } else {
+            0
+        };
+
+        let mut edts = EdtsBox::new();
+        edts.elst = Some(ElstBox {
+            version,
+            flags: 0,
+            entries,
+        });
+        Some(edts)
+    }
+
+    pub(crate) fn write_end<W: Write + Seek>(
+        &mut self,
+        writer: &mut W,
+        movie_timescale: u32,
+    ) -> Result<TrakBox> {
+        self.close_chunk();
+        while self.has_pending_chunk() {
+            self.write_chunk(writer)?;
+        }
+
+        self.trak.edts = self.edts(movie_timescale);
+        if let Some(elst) = self.trak.edts.as_ref().and_then(|edts| edts.elst.as_ref()) {
+            // The edit list defines the presentation duration.
+            self.trak.tkhd.duration = elst.entries.iter().map(|entry| entry.segment_duration).sum();
+        }
 
         let max_sample_size = self.max_sample_size();
-        if let Some(ref mut mp4a) = self.trak.mdia.minf.stbl.stsd.mp4a {
-            if let Some(ref mut esds) = mp4a.esds {
-                esds.es_desc.dec_config.buffer_size_db = max_sample_size;
+        let (avg_bitrate, max_bitrate) = self.bitrates();
+        let btrt = BtrtBox {
+            buffer_size_db: max_sample_size,
+            max_bitrate,
+            avg_bitrate,
+        };
+        for entry in self.trak.mdia.minf.stbl.stsd.entries.iter_mut() {
+            match entry {
+                SampleEntry::Avc1(ref mut avc)
+                | SampleEntry::Avc2(ref mut avc)
+                | SampleEntry::Avc3(ref mut avc)
+                | SampleEntry::Avc4(ref mut avc) => avc.btrt = Some(btrt.clone()),
+                SampleEntry::Hev1(ref mut hevc) | SampleEntry::Hvc1(ref mut hevc) => {
+                    hevc.btrt = Some(btrt.clone())
+                }
+                SampleEntry::Vp09(ref mut vp09) => vp09.btrt = Some(btrt.clone()),
+                SampleEntry::Av01(ref mut av01) => av01.btrt = Some(btrt.clone()),
+                SampleEntry::Mp4a(ref mut mp4a) => {
+                    if let Some(ref mut esds) = mp4a.esds {
+                        esds.es_desc.dec_config.buffer_size_db = max_sample_size;
+                        esds.es_desc.dec_config.max_bitrate = max_bitrate;
+                        esds.es_desc.dec_config.avg_bitrate = avg_bitrate;
+                    }
+                    mp4a.btrt = Some(btrt.clone());
+                }
+                SampleEntry::Opus(ref mut opus) => opus.btrt = Some(btrt.clone()),
+                SampleEntry::Tx3g(_) | SampleEntry::Unknown(_) => {}
             }
-            // TODO
-            // mp4a.esds.es_desc.dec_config.max_bitrate
-            // mp4a.esds.es_desc.dec_config.avg_bitrate
         }
 
         Ok(self.trak.clone())
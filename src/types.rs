@@ -1,5 +1,6 @@
 use std::convert::TryFrom;
 use std::fmt;
+use std::time::Duration;
 use serde::{Serialize};
 
 use crate::mp4box::*;
@@ -215,7 +216,9 @@ impl Into<FourCC> for TrackType {
 const MEDIA_TYPE_H264: &str = "h264";
 const MEDIA_TYPE_H265: &str = "h265";
 const MEDIA_TYPE_VP9: &str = "vp9";
+const MEDIA_TYPE_AV1: &str = "av1";
 const MEDIA_TYPE_AAC: &str = "aac";
+const MEDIA_TYPE_OPUS: &str = "opus";
 const MEDIA_TYPE_TTXT: &str = "ttxt";
 
 #[derive(Debug, Clone, Copy, PartialEq)]
@@ -223,7 +226,9 @@ pub enum MediaType {
     H264,
     H265,
     VP9,
+    AV1,
     AAC,
+    OPUS,
     TTXT,
 }
 
@@ -241,7 +246,9 @@ impl TryFrom<&str> for MediaType {
             MEDIA_TYPE_H264 => Ok(MediaType::H264),
             MEDIA_TYPE_H265 => Ok(MediaType::H265),
             MEDIA_TYPE_VP9 => Ok(MediaType::VP9),
+            MEDIA_TYPE_AV1 => Ok(MediaType::AV1),
             MEDIA_TYPE_AAC => Ok(MediaType::AAC),
+            MEDIA_TYPE_OPUS => Ok(MediaType::OPUS),
             MEDIA_TYPE_TTXT => Ok(MediaType::TTXT),
             _ => Err(Error::InvalidData("unsupported media type")),
         }
@@ -254,7 +261,9 @@ impl Into<&str> for MediaType {
             MediaType::H264 => MEDIA_TYPE_H264,
             MediaType::H265 => MEDIA_TYPE_H265,
             MediaType::VP9 => MEDIA_TYPE_VP9,
+            MediaType::AV1 => MEDIA_TYPE_AV1,
             MediaType::AAC => MEDIA_TYPE_AAC,
+            MediaType::OPUS => MEDIA_TYPE_OPUS,
             MediaType::TTXT => MEDIA_TYPE_TTXT,
         }
     }
@@ -266,7 +275,9 @@ impl Into<&str> for &MediaType {
             MediaType::H264 => MEDIA_TYPE_H264,
             MediaType::H265 => MEDIA_TYPE_H265,
             MediaType::VP9 => MEDIA_TYPE_VP9,
+            MediaType::AV1 => MEDIA_TYPE_AV1,
             MediaType::AAC => MEDIA_TYPE_AAC,
+            MediaType::OPUS => MEDIA_TYPE_OPUS,
             MediaType::TTXT => MEDIA_TYPE_TTXT,
         }
     }
@@ -565,12 +576,45 @@ pub struct AvcConfig {
     pub height: u16,
     pub seq_param_set: Vec<u8>,
     pub pic_param_set: Vec<u8>,
+    /// SPS extension for the high profiles, left out if empty.
+    pub seq_param_set_ext: Vec<u8>,
+    pub box_type: AvcBoxType,
+}
+
+/// Sample entry used for AVC tracks.
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Default)]
+pub enum AvcBoxType {
+    /// avc1, parameter sets are only in the sample entry.
+    #[default]
+    Avc1,
+    /// avc2, like avc1 but with extractors or aggregators.
+    Avc2,
+    /// avc3, parameter sets may also be sent in the samples. Used for live
+    /// streams where they can change.
+    Avc3,
+    /// avc4, like avc3 but with extractors or aggregators.
+    Avc4,
 }
 
 #[derive(Debug, PartialEq, Clone, Default)]
 pub struct HevcConfig {
     pub width: u16,
     pub height: u16,
+    pub video_param_set: Vec<u8>,
+    pub seq_param_set: Vec<u8>,
+    pub pic_param_set: Vec<u8>,
+    pub box_type: HevcBoxType,
+}
+
+/// Sample entry used for HEVC tracks.
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Default)]
+pub enum HevcBoxType {
+    /// hev1, parameter sets may also be sent in the samples.
+    #[default]
+    Hev1,
+    /// hvc1, parameter sets are only in the sample entry. Required by
+    /// QuickTime and Safari.
+    Hvc1,
 }
 
 #[derive(Debug, PartialEq, Clone, Default)]
@@ -579,6 +623,45 @@ pub struct Vp9Config {
     pub height: u16,
 }
 
+/// AV1 codec configuration, see the av1C box of the AV1-ISOBMFF binding.
+/// The fields must match the sequence header OBU.
+#[derive(Debug, PartialEq, Clone)]
+pub struct Av1Config {
+    pub width: u16,
+    pub height: u16,
+    pub seq_profile: u8,
+    pub seq_level_idx: u8,
+    /// seq_tier of operating point 0, true for the high tier.
+    pub seq_tier: bool,
+    /// 8, 10 or 12.
+    pub bit_depth: u8,
+    pub monochrome: bool,
+    pub chroma_subsampling_x: bool,
+    pub chroma_subsampling_y: bool,
+    pub chroma_sample_position: u8,
+    /// Sequence header and metadata OBUs, may be empty.
+    pub config_obus: Vec<u8>,
+}
+
+impl Default for Av1Config {
+    fn default() -> Self {
+        // Main profile, 4:2:0 8-bit.
+        Self {
+            width: 0,
+            height: 0,
+            seq_profile: 0,
+            seq_level_idx: 0,
+            seq_tier: false,
+            bit_depth: 8,
+            monochrome: false,
+            chroma_subsampling_x: true,
+            chroma_subsampling_y: true,
+            chroma_sample_position: 0,
+            config_obus: Vec::new(),
+        }
+    }
+}
+
 #[derive(Debug, PartialEq, Clone)]
 pub struct AacConfig {
     pub bitrate: u32,
@@ -598,6 +681,46 @@ impl Default for AacConfig {
     }
 }
 
+/// Opus codec configuration, the fields of the dOps box. Opus tracks are
+/// always decoded at 48kHz.
+#[derive(Debug, PartialEq, Clone)]
+pub struct OpusConfig {
+    pub channel_count: u8,
+    /// Samples at 48kHz to discard from the decoder output at the start.
+    pub pre_skip: u16,
+    /// Sample rate of the original input, informational only.
+    pub input_sample_rate: u32,
+    /// Gain in dB to apply to the output, Q7.8 fixed point.
+    pub output_gain: i16,
+    pub channel_mapping_family: u8,
+    /// Required if, and only if, `channel_mapping_family` isn't 0, with one
+    /// entry per channel.
+    pub channel_mapping_table: Option<OpusChannelMappingTable>,
+}
+
+impl Default for OpusConfig {
+    fn default() -> Self {
+        Self {
+            channel_count: 2,
+            pre_skip: 0,
+            input_sample_rate: 48000,
+            output_gain: 0,
+            channel_mapping_family: 0,
+            channel_mapping_table: None,
+        }
+    }
+}
+
+/// How the Opus streams of a packet map to output channels.
+#[derive(Debug, PartialEq, Clone, Default, Serialize)]
+pub struct OpusChannelMappingTable {
+    pub stream_count: u8,
+    pub coupled_count: u8,
+
+    /// One entry per output channel.
+    pub channel_mapping: Vec<u8>,
+}
+
 #[derive(Debug, PartialEq, Clone, Default)]
 pub struct TtxtConfig {}
 
@@ -606,25 +729,196 @@ pub enum MediaConfig {
     AvcConfig(AvcConfig),
     HevcConfig(HevcConfig),
     Vp9Config(Vp9Config),
+    Av1Config(Av1Config),
     AacConfig(AacConfig),
+    OpusConfig(OpusConfig),
     TtxtConfig(TtxtConfig),
 }
 
+/// How [Mp4Reader::seek](crate::Mp4Reader::seek) picks a sample for a timestamp.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SeekMode {
+    /// The sync sample at or before the sample presented at the timestamp.
+    PreviousSync,
+    /// The sample presented at the timestamp, which may not be a sync sample.
+    Exact,
+}
+
+/// Order in which [Mp4Reader::interleaved_samples](crate::Mp4Reader::interleaved_samples)
+/// yields the samples of all tracks.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SampleOrder {
+    /// Ascending file offset, for sequential reads through mdat.
+    FileOffset,
+    /// Ascending decode time in the movie timescale, for muxing and playback.
+    DecodeTime,
+}
+
+/// An entry of the edit list written for a track, see
+/// [TrackConfig::edit_list](crate::TrackConfig::edit_list). Times are in the
+/// track's timescale; the writer converts durations to the movie timescale.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct EditListEntry {
+    /// Length of the edit, or `None` to play until the end of the media.
+    pub duration: Option<u64>,
+    /// Media time the edit starts at, or `None` for an empty edit.
+    pub media_time: Option<u64>,
+    pub media_rate: u16,
+}
+
+impl EditListEntry {
+    /// A gap of `duration` with nothing presented, e.g. to delay a track.
+    pub fn empty(duration: u64) -> Self {
+        Self {
+            duration: Some(duration),
+            media_time: None,
+            media_rate: 1,
+        }
+    }
+
+    /// Presents the media from `media_time` on at normal rate, e.g. to skip
+    /// composition delay or encoder priming.
+    pub fn media(media_time: u64, duration: Option<u64>) -> Self {
+        Self {
+            duration,
+            media_time: Some(media_time),
+            media_rate: 1,
+        }
+    }
+}
+
+/// How [Mp4Writer](crate::Mp4Writer) groups the samples of a track into
+/// chunks, set with [TrackConfig::chunk_policy](crate::TrackConfig::chunk_policy).
+/// A chunk is closed once it reaches the limit.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ChunkPolicy {
+    Duration(Duration),
+    Samples(u32),
+    Bytes(u32),
+}
+
+impl Default for ChunkPolicy {
+    fn default() -> Self {
+        ChunkPolicy::Duration(Duration::from_secs(1))
+    }
+}
+
+/// Transformation matrix of tkhd and mvhd, in the order `a b u c d v x y w`
+/// of the spec. `u`, `v` and `w` are 2.30 fixed point, the rest 16.16.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Matrix {
+    pub a: i32,
+    pub b: i32,
+    pub u: i32,
+    pub c: i32,
+    pub d: i32,
+    pub v: i32,
+    pub x: i32,
+    pub y: i32,
+    pub w: i32,
+}
+
+impl Matrix {
+    /// Rotates the video clockwise by 90, 180 or 270 degrees when displayed.
+    pub fn rotation(degrees: u16) -> Result<Self> {
+        let (cos, sin) = match degrees {
+            0 => (1, 0),
+            90 => (0, 1),
+            180 => (-1, 0),
+            270 => (0, -1),
+            _ => return Err(Error::InvalidData("rotation must be a multiple of 90 degrees")),
+        };
+        Ok(Matrix {
+            a: cos << 16,
+            b: sin << 16,
+            c: -sin << 16,
+            d: cos << 16,
+            ..Matrix::default()
+        })
+    }
+}
+
+impl Default for Matrix {
+    /// The identity matrix.
+    fn default() -> Self {
+        Matrix {
+            a: 0x00010000,
+            b: 0,
+            u: 0,
+            c: 0,
+            d: 0x00010000,
+            v: 0,
+            x: 0,
+            y: 0,
+            w: 0x40000000,
+        }
+    }
+}
+
+/// Track header fields written by [Mp4Writer](crate::Mp4Writer), set with
+/// [TrackConfig::header](crate::TrackConfig::header).
+#[derive(Debug, Clone, PartialEq)]
+pub struct TrackHeader {
+    pub enabled: bool,
+    pub in_movie: bool,
+    pub in_preview: bool,
+    /// Tracks that share a non-zero group are alternatives to each other,
+    /// e.g. audio in different languages, of which one is played.
+    pub alternate_group: u16,
+    pub layer: u16,
+    pub volume: FixedPointU8,
+    pub matrix: Matrix,
+}
+
+impl From<TrackType> for TrackHeader {
+    fn from(track_type: TrackType) -> Self {
+        // Volume is only meaningful for audio and zero otherwise.
+        let volume = match track_type {
+            TrackType::Audio => FixedPointU8::new(1),
+            _ => FixedPointU8::new(0),
+        };
+        TrackHeader {
+            enabled: true,
+            in_movie: true,
+            in_preview: false,
+            alternate_group: 0,
+            layer: 0,
+            volume,
+            matrix: Matrix::default(),
+        }
+    }
+}
+
 #[derive(Debug)]
 pub struct Mp4Sample {
     pub start_time: u64,
     pub duration: u32,
     pub rendering_offset: i32,
     pub is_sync: bool,
+    /// 1-based index of the sample's entry in the track's stsd.
+    pub sample_description_index: u32,
     pub bytes: Bytes,
 }
 
+/// Location, timing and flags of a sample, without its payload.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SampleInfo {
+    pub offset: u64,
+    pub size: u32,
+    pub start_time: u64,
+    pub duration: u32,
+    pub rendering_offset: i32,
+    pub is_sync: bool,
+    pub sample_description_index: u32,
+}
+
 impl PartialEq for Mp4Sample {
     fn eq(&self, other: &Self) -> bool {
         self.start_time == other.start_time
             && self.duration == other.duration
             && self.rendering_offset == other.rendering_offset
             && self.is_sync == other.is_sync
+            && self.sample_description_index == other.sample_description_index
             && self.bytes.len() == other.bytes.len() // XXX for easy check
     }
 }
@@ -643,6 +937,16 @@ impl fmt::Display for Mp4Sample {
     }
 }
 
+/// Converts seconds since the Unix epoch to the MP4 epoch, keeping 0 as
+/// unset.
+pub(crate) fn mp4_time(unix_time: u64) -> u64 {
+    if unix_time == 0 {
+        0
+    } else {
+        unix_time + 2082844800
+    }
+}
+
 pub fn creation_time(creation_time: u64) -> u64 {
     // convert from MP4 epoch (1904-01-01) to Unix epoch (1970-01-01)
     if creation_time >= 2082844800 {
@@ -1,6 +1,8 @@
 use byteorder::{BigEndian, WriteBytesExt};
-use std::io::{Seek, SeekFrom, Write};
+use std::cmp;
+use std::io::{Read, Seek, SeekFrom, Write};
 
+use crate::faststart::shift_chunk_offsets;
 use crate::mp4box::*;
 use crate::track::Mp4TrackWriter;
 use crate::*;
@@ -11,6 +13,21 @@ pub struct Mp4Config {
     pub minor_version: u32,
     pub compatible_brands: Vec<FourCC>,
     pub timescale: u32,
+
+    /// Creation and modification time of the movie and its tracks, in
+    /// seconds since the Unix epoch. 0 leaves them unset.
+    pub creation_time: u64,
+    pub modification_time: u64,
+}
+
+/// Options for [Mp4Writer::write_start_with_options].
+#[derive(Debug, Clone, Default)]
+pub struct WriteOptions {
+    /// Write the chunks of all tracks in decode time order, so that mdat is
+    /// interleaved for progressive playback. Closed chunks are held back
+    /// until every other track has caught up with them, which buffers
+    /// samples in memory when tracks are written far apart.
+    pub interleave: bool,
 }
 
 #[derive(Debug)]
@@ -20,6 +37,9 @@ pub struct Mp4Writer<W> {
     mdat_pos: u64,
     timescale: u32,
     duration: u64,
+    creation_time: u64,
+    modification_time: u64,
+    interleave: bool,
 }
 
 impl<W> Mp4Writer<W> {
@@ -45,6 +65,8 @@ impl<W> Mp4Writer<W> {
     ///         str::parse("mp41").unwrap(),
     ///     ],
     ///     timescale: 1000,
+    ///     creation_time: 0,
+    ///     modification_time: 0,
     /// };
     ///
     /// let data = Cursor::new(Vec::<u8>::new());
@@ -60,7 +82,15 @@ impl<W> Mp4Writer<W> {
 }
 
 impl<W: Write + Seek> Mp4Writer<W> {
-    pub fn write_start(mut writer: W, config: &Mp4Config) -> Result<Self> {
+    pub fn write_start(writer: W, config: &Mp4Config) -> Result<Self> {
+        Self::write_start_with_options(writer, config, &WriteOptions::default())
+    }
+
+    pub fn write_start_with_options(
+        mut writer: W,
+        config: &Mp4Config,
+        options: &WriteOptions,
+    ) -> Result<Self> {
         let ftyp = FtypBox {
             major_brand: config.major_brand.clone(),
             minor_version: config.minor_version.clone(),
@@ -68,8 +98,10 @@ impl<W: Write + Seek> Mp4Writer<W> {
         };
         ftyp.write_box(&mut writer)?;
 
-        // TODO largesize
+        // Reserve room for a largesize mdat header with a free box, which
+        // update_mdat_size overwrites if mdat grows past 4 GiB.
         let mdat_pos = writer.seek(SeekFrom::Current(0))?;
+        BoxHeader::new(BoxType::FreeBox, HEADER_SIZE).write(&mut writer)?;
         BoxHeader::new(BoxType::MdatBox, HEADER_SIZE).write(&mut writer)?;
 
         let tracks = Vec::new();
@@ -81,16 +113,80 @@ impl<W: Write + Seek> Mp4Writer<W> {
             mdat_pos,
             timescale,
             duration,
+            creation_time: mp4_time(config.creation_time),
+            modification_time: mp4_time(config.modification_time),
+            interleave: options.interleave,
         })
     }
 
-    pub fn add_track(&mut self, config: &TrackConfig) -> Result<()> {
-        let track_id = self.tracks.len() as u32 + 1;
-        let track = Mp4TrackWriter::new(track_id, config)?;
+    /// Adds a track with the next free track ID, one past the highest ID in
+    /// use, and returns that ID.
+    pub fn add_track(&mut self, config: &TrackConfig) -> Result<u32> {
+        let track_id = self.next_track_id();
+        self.add_track_with_id(track_id, config)?;
+        Ok(track_id)
+    }
+
+    /// Adds a track with an explicit, non-zero track ID.
+    pub fn add_track_with_id(&mut self, track_id: u32, config: &TrackConfig) -> Result<()> {
+        if track_id == 0 {
+            return Err(Error::InvalidData("track id must be non-zero"));
+        }
+        if self.tracks.iter().any(|track| track.track_id() == track_id) {
+            return Err(Error::InvalidData("duplicate track id"));
+        }
+        let mut track = Mp4TrackWriter::new(track_id, config)?;
+        track.set_times(self.creation_time, self.modification_time);
         self.tracks.push(track);
         Ok(())
     }
 
+    /// Adds another sample description to a track, e.g. for a change of
+    /// resolution or parameter sets mid-stream, and returns its index.
+    /// Samples written with that [Mp4Sample::sample_description_index] use
+    /// the new description.
+    pub fn add_sample_description(&mut self, track_id: u32, media_conf: &MediaConfig) -> Result<u32> {
+        let track = self
+            .tracks
+            .iter_mut()
+            .find(|track| track.track_id() == track_id)
+            .ok_or(Error::TrakNotFound(track_id))?;
+        track.add_sample_description(media_conf)
+    }
+
+    /// Adds a reference of `reference_type` from one track to another, such
+    /// as `chap` from a video track to its chapter text track.
+    pub fn add_track_reference(
+        &mut self,
+        track_id: u32,
+        reference_type: FourCC,
+        referenced_track_id: u32,
+    ) -> Result<()> {
+        if !self
+            .tracks
+            .iter()
+            .any(|track| track.track_id() == referenced_track_id)
+        {
+            return Err(Error::TrakNotFound(referenced_track_id));
+        }
+        let track = self
+            .tracks
+            .iter_mut()
+            .find(|track| track.track_id() == track_id)
+            .ok_or(Error::TrakNotFound(track_id))?;
+        track.add_reference(reference_type, referenced_track_id);
+        Ok(())
+    }
+
+    fn next_track_id(&self) -> u32 {
+        self.tracks
+            .iter()
+            .map(|track| track.track_id())
+            .max()
+            .unwrap_or(0)
+            + 1
+    }
+
     fn update_durations(&mut self, track_dur: u64) {
         if track_dur > self.duration {
             self.duration = track_dur;
@@ -98,44 +194,815 @@ impl<W: Write + Seek> Mp4Writer<W> {
     }
 
     pub fn write_sample(&mut self, track_id: u32, sample: &Mp4Sample) -> Result<()> {
-        if track_id == 0 {
-            return Err(Error::TrakNotFound(track_id));
-        }
-
-        let track_dur = if let Some(ref mut track) = self.tracks.get_mut(track_id as usize - 1) {
-            track.write_sample(&mut self.writer, sample, self.timescale)?
+        let track_dur = if let Some(track) = self
+            .tracks
+            .iter_mut()
+            .find(|track| track.track_id() == track_id)
+        {
+            let track_dur = track.write_sample(sample, self.timescale)?;
+            if !self.interleave {
+                while track.has_pending_chunk() {
+                    track.write_chunk(&mut self.writer)?;
+                }
+            }
+            track_dur
         } else {
             return Err(Error::TrakNotFound(track_id));
         };
+        if self.interleave {
+            self.write_interleaved_chunks(false)?;
+        }
 
         self.update_durations(track_dur);
 
         Ok(())
     }
 
+    /// Writes pending chunks of all tracks in decode time order, until the
+    /// earliest chunk is one that's still being filled. With `flush`, only
+    /// tracks with pending chunks are considered, so everything is written.
+    fn write_interleaved_chunks(&mut self, flush: bool) -> Result<()> {
+        loop {
+            let next = self
+                .tracks
+                .iter_mut()
+                .filter(|track| !flush || track.has_pending_chunk())
+                .min_by_key(|track| track.next_chunk_time());
+            match next {
+                Some(track) if track.has_pending_chunk() => track.write_chunk(&mut self.writer)?,
+                _ => return Ok(()),
+            }
+        }
+    }
+
     fn update_mdat_size(&mut self) -> Result<()> {
         let mdat_end = self.writer.seek(SeekFrom::Current(0))?;
-        let mdat_size = mdat_end - self.mdat_pos;
+        let mdat_size = mdat_end - self.mdat_pos - HEADER_SIZE;
         if mdat_size > std::u32::MAX as u64 {
-            return Err(Error::InvalidData("mdat size too large"));
+            // Take over the free box for a largesize header.
+            self.writer.seek(SeekFrom::Start(self.mdat_pos))?;
+            let data_size = mdat_end - self.mdat_pos - HEADER_LARGE_SIZE;
+            BoxHeader::for_data(BoxType::MdatBox, data_size).write(&mut self.writer)?;
+        } else {
+            self.writer.seek(SeekFrom::Start(self.mdat_pos + HEADER_SIZE))?;
+            self.writer.write_u32::<BigEndian>(mdat_size as u32)?;
         }
-        self.writer.seek(SeekFrom::Start(self.mdat_pos))?;
-        self.writer.write_u32::<BigEndian>(mdat_size as u32)?;
         self.writer.seek(SeekFrom::Start(mdat_end))?;
         Ok(())
     }
 
-    pub fn write_end(&mut self) -> Result<()> {
+    fn end_tracks(&mut self) -> Result<MoovBox> {
         let mut moov = MoovBox::default();
 
+        if self.interleave {
+            for track in self.tracks.iter_mut() {
+                track.close_chunk();
+            }
+            self.write_interleaved_chunks(true)?;
+        }
+
+        self.duration = 0;
         for track in self.tracks.iter_mut() {
-            moov.traks.push(track.write_end(&mut self.writer)?);
+            let trak = track.write_end(&mut self.writer, self.timescale)?;
+            self.duration = cmp::max(self.duration, trak.tkhd.duration);
+            moov.traks.push(trak);
         }
-        self.update_mdat_size()?;
 
         moov.mvhd.timescale = self.timescale;
         moov.mvhd.duration = self.duration;
+        moov.mvhd.creation_time = self.creation_time;
+        moov.mvhd.modification_time = self.modification_time;
+        moov.mvhd.next_track_id = self.next_track_id();
+        Ok(moov)
+    }
+
+    pub fn write_end(&mut self) -> Result<()> {
+        let moov = self.end_tracks()?;
+        self.update_mdat_size()?;
+
         moov.write_box(&mut self.writer)?;
         Ok(())
     }
 }
+
+// Size of the blocks mdat is moved in by write_end_fast_start.
+const MOVE_BUFFER_SIZE: u64 = 1024 * 1024;
+
+impl<W: Read + Write + Seek> Mp4Writer<W> {
+    /// Like [Mp4Writer::write_end], but places moov in front of mdat so that
+    /// playback can start before the whole file is downloaded. The samples
+    /// already written are moved up to make room, so the writer must also be
+    /// readable.
+    pub fn write_end_fast_start(&mut self) -> Result<()> {
+        let mut moov = self.end_tracks()?;
+
+        let data_start = self.mdat_pos + HEADER_LARGE_SIZE;
+        let data_end = self.writer.stream_position()?;
+        let data_size = data_end - data_start;
+        let mdat = BoxHeader::for_data(BoxType::MdatBox, data_size);
+
+        // moov and the mdat header replace the free box and the reserved
+        // mdat header.
+        shift_chunk_offsets(&mut moov, |offset, moov_size| {
+            offset + moov_size + mdat.header_size - HEADER_LARGE_SIZE
+        });
+        let shift = moov.box_size() + mdat.header_size - HEADER_LARGE_SIZE;
+
+        let mut buf = vec![0; cmp::min(data_size, MOVE_BUFFER_SIZE) as usize];
+        let mut end = data_end;
+        while end > data_start {
+            let len = cmp::min(end - data_start, buf.len() as u64);
+            let pos = end - len;
+            self.writer.seek(SeekFrom::Start(pos))?;
+            self.writer.read_exact(&mut buf[..len as usize])?;
+            self.writer.seek(SeekFrom::Start(pos + shift))?;
+            self.writer.write_all(&buf[..len as usize])?;
+            end = pos;
+        }
+
+        self.writer.seek(SeekFrom::Start(self.mdat_pos))?;
+        moov.write_box(&mut self.writer)?;
+        mdat.write(&mut self.writer)?;
+        self.writer.seek(SeekFrom::Start(data_end + shift))?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mp4box::stsd::SampleEntry;
+    use crate::test_util::{read_all_samples, test_config, top_level_boxes, write_two_tracks};
+    use bytes::Bytes;
+    use std::io::{self, Cursor};
+    use std::time::Duration;
+
+    const HEAD_SIZE: u64 = 64;
+
+    // Keeps the first bytes and everything past `tail_start`, so files over
+    // 4 GiB can be written without holding them in memory.
+    struct SparseWriter {
+        pos: u64,
+        len: u64,
+        head: Vec<u8>,
+        tail_start: u64,
+        tail: Vec<u8>,
+    }
+
+    fn store(region: &mut Vec<u8>, start: u64, end: u64, pos: u64, buf: &[u8]) {
+        let from = pos.max(start);
+        let to = (pos + buf.len() as u64).min(end);
+        if from >= to {
+            return;
+        }
+        let src = &buf[(from - pos) as usize..(to - pos) as usize];
+        let offset = (from - start) as usize;
+        if region.len() < offset + src.len() {
+            region.resize(offset + src.len(), 0);
+        }
+        region[offset..offset + src.len()].copy_from_slice(src);
+    }
+
+    impl Write for SparseWriter {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            store(&mut self.head, 0, HEAD_SIZE, self.pos, buf);
+            store(&mut self.tail, self.tail_start, u64::MAX, self.pos, buf);
+            self.pos += buf.len() as u64;
+            self.len = self.len.max(self.pos);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    impl Seek for SparseWriter {
+        fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+            self.pos = match pos {
+                SeekFrom::Start(pos) => pos,
+                SeekFrom::Current(offset) => (self.pos as i64 + offset) as u64,
+                SeekFrom::End(offset) => (self.len as i64 + offset) as u64,
+            };
+            Ok(self.pos)
+        }
+    }
+
+    #[test]
+    fn test_write_fast_start() {
+        let config = test_config();
+        let mut writer = Mp4Writer::write_start(Cursor::new(Vec::new()), &config).unwrap();
+        write_two_tracks(&mut writer);
+        writer.write_end().unwrap();
+        let src = writer.into_writer().into_inner();
+
+        let mut writer = Mp4Writer::write_start(Cursor::new(Vec::new()), &config).unwrap();
+        write_two_tracks(&mut writer);
+        writer.write_end_fast_start().unwrap();
+        let dst = writer.into_writer().into_inner();
+
+        assert_eq!(dst.len(), src.len() - HEADER_SIZE as usize);
+        assert_eq!(
+            top_level_boxes(&dst),
+            vec![BoxType::FtypBox, BoxType::MoovBox, BoxType::MdatBox]
+        );
+        assert_eq!(read_all_samples(&dst), read_all_samples(&src));
+    }
+
+    fn write_video_then_audio(options: &WriteOptions) -> Vec<u8> {
+        let config = test_config();
+        let mut writer =
+            Mp4Writer::write_start_with_options(Cursor::new(Vec::new()), &config, options)
+                .unwrap();
+        // One second chunks on both tracks.
+        let video = writer
+            .add_track(&TrackConfig {
+                chunk_policy: ChunkPolicy::Samples(2),
+                ..TrackConfig::from(TtxtConfig {})
+            })
+            .unwrap();
+        let audio = writer
+            .add_track(&TrackConfig {
+                timescale: 48000,
+                ..TrackConfig::from(TtxtConfig {})
+            })
+            .unwrap();
+        for (track_id, duration) in [(video, 500), (audio, 24000)].iter() {
+            for i in 0..6 {
+                let sample = Mp4Sample {
+                    start_time: i * *duration as u64,
+                    duration: *duration,
+                    rendering_offset: 0,
+                    is_sync: true,
+                    sample_description_index: 1,
+                    bytes: Bytes::from(vec![i as u8; 10]),
+                };
+                writer.write_sample(*track_id, &sample).unwrap();
+            }
+        }
+        writer.write_end().unwrap();
+        writer.into_writer().into_inner()
+    }
+
+    #[test]
+    fn test_write_interleaved() {
+        let buf = write_video_then_audio(&WriteOptions::default());
+        let track_ids: Vec<u32> = read_all_samples(&buf).iter().map(|s| s.0).collect();
+        assert_eq!(track_ids, vec![1, 1, 1, 1, 1, 1, 2, 2, 2, 2, 2, 2]);
+
+        let buf = write_video_then_audio(&WriteOptions { interleave: true });
+        let track_ids: Vec<u32> = read_all_samples(&buf).iter().map(|s| s.0).collect();
+        assert_eq!(track_ids, vec![1, 1, 2, 2, 1, 1, 2, 2, 1, 1, 2, 2]);
+
+        let size = buf.len() as u64;
+        let mut mp4 = Mp4Reader::read_header(Cursor::new(&buf), size).unwrap();
+        for trak in mp4.moov.traks.iter() {
+            assert_eq!(trak.mdia.minf.stbl.stco.as_ref().unwrap().entries.len(), 3);
+        }
+        let sample = mp4.read_sample(2, 6).unwrap().unwrap();
+        assert_eq!(sample.start_time, 5 * 24000);
+        assert_eq!(sample.bytes, Bytes::from(vec![5; 10]));
+    }
+
+    #[test]
+    fn test_chunk_policy_bytes() {
+        let config = test_config();
+        let mut writer = Mp4Writer::write_start(Cursor::new(Vec::new()), &config).unwrap();
+        let track_conf = TrackConfig {
+            chunk_policy: ChunkPolicy::Bytes(25),
+            ..TrackConfig::from(TtxtConfig {})
+        };
+        writer.add_track(&track_conf).unwrap();
+        for i in 0..7 {
+            let sample = Mp4Sample {
+                start_time: i * 500,
+                duration: 500,
+                rendering_offset: 0,
+                is_sync: true,
+                sample_description_index: 1,
+                bytes: Bytes::from(vec![0; 10]),
+            };
+            writer.write_sample(1, &sample).unwrap();
+        }
+        writer.write_end().unwrap();
+        let buf = writer.into_writer().into_inner();
+
+        let size = buf.len() as u64;
+        let mp4 = Mp4Reader::read_header(Cursor::new(buf), size).unwrap();
+        let stbl = &mp4.moov.traks[0].mdia.minf.stbl;
+        assert_eq!(stbl.stco.as_ref().unwrap().entries.len(), 3);
+        let samples_per_chunk: Vec<u32> = stbl
+            .stsc
+            .entries
+            .iter()
+            .map(|entry| entry.samples_per_chunk)
+            .collect();
+        assert_eq!(samples_per_chunk, vec![3, 1]);
+    }
+
+    #[test]
+    fn test_write_sample_descriptions() {
+        let config = test_config();
+        let mut writer = Mp4Writer::write_start(Cursor::new(Vec::new()), &config).unwrap();
+        let avc_config = AvcConfig {
+            width: 1280,
+            height: 720,
+            seq_param_set: vec![0x67, 0x64, 0x00, 0x1F],
+            pic_param_set: vec![0x68, 0xEB],
+            seq_param_set_ext: Vec::new(),
+            box_type: AvcBoxType::Avc1,
+        };
+        let track_id = writer
+            .add_track(&TrackConfig::from(avc_config.clone()))
+            .unwrap();
+        // Switch to 1080p halfway.
+        let switched = writer
+            .add_sample_description(
+                track_id,
+                &MediaConfig::AvcConfig(AvcConfig {
+                    width: 1920,
+                    height: 1080,
+                    ..avc_config
+                }),
+            )
+            .unwrap();
+        assert_eq!(switched, 2);
+        assert!(writer
+            .add_sample_description(track_id, &MediaConfig::TtxtConfig(TtxtConfig {}))
+            .is_err());
+
+        let mut samples = Vec::new();
+        for i in 0..6 {
+            samples.push(Mp4Sample {
+                start_time: i * 1000,
+                duration: 1000,
+                rendering_offset: 0,
+                is_sync: i % 3 == 0,
+                sample_description_index: if i < 3 { 1 } else { switched },
+                bytes: Bytes::from(vec![i as u8; 10]),
+            });
+            writer.write_sample(track_id, &samples[i as usize]).unwrap();
+        }
+        let sample = Mp4Sample {
+            sample_description_index: 3,
+            bytes: Bytes::new(),
+            ..samples[5]
+        };
+        assert!(writer.write_sample(track_id, &sample).is_err());
+        writer.write_end().unwrap();
+        let buf = writer.into_writer().into_inner();
+
+        let size = buf.len() as u64;
+        let mut mp4 = Mp4Reader::read_header(Cursor::new(buf), size).unwrap();
+        let stsd = &mp4.moov.traks[0].mdia.minf.stbl.stsd;
+        assert_eq!(stsd.entries.len(), 2);
+        match stsd.entry(2) {
+            Some(SampleEntry::Avc1(avc1)) => assert_eq!(avc1.width, 1920),
+            entry => panic!("unexpected sample entry {:?}", entry),
+        }
+        assert_eq!(mp4.tracks()[0].width(), 1280);
+
+        for (i, sample) in samples.iter().enumerate() {
+            let read = mp4.read_sample(track_id, i as u32 + 1).unwrap().unwrap();
+            assert_eq!(&read, sample);
+        }
+    }
+
+    #[test]
+    fn test_write_track_header() {
+        let config = Mp4Config {
+            creation_time: 1_700_000_000,
+            modification_time: 1_700_000_100,
+            ..test_config()
+        };
+        let mut writer = Mp4Writer::write_start(Cursor::new(Vec::new()), &config).unwrap();
+        let video_conf = TrackConfig {
+            track_type: TrackType::Video,
+            header: TrackHeader {
+                matrix: Matrix::rotation(90).unwrap(),
+                ..TrackHeader::from(TrackType::Video)
+            },
+            ..TrackConfig::from(TtxtConfig {})
+        };
+        writer.add_track(&video_conf).unwrap();
+        // Two alternative audio tracks, of which the second is disabled.
+        for enabled in [true, false].iter() {
+            let audio_conf = TrackConfig {
+                track_type: TrackType::Audio,
+                header: TrackHeader {
+                    enabled: *enabled,
+                    alternate_group: 1,
+                    ..TrackHeader::from(TrackType::Audio)
+                },
+                ..TrackConfig::from(TtxtConfig {})
+            };
+            writer.add_track(&audio_conf).unwrap();
+        }
+        writer.write_end().unwrap();
+        let buf = writer.into_writer().into_inner();
+
+        let size = buf.len() as u64;
+        let mp4 = Mp4Reader::read_header(Cursor::new(buf), size).unwrap();
+        let mvhd = &mp4.moov.mvhd;
+        assert_eq!(creation_time(mvhd.creation_time), 1_700_000_000);
+        assert_eq!(creation_time(mvhd.modification_time), 1_700_000_100);
+        assert_eq!(mvhd.next_track_id, 4);
+        assert_eq!(mvhd.matrix, Matrix::default());
+
+        let tkhds: Vec<_> = mp4.moov.traks.iter().map(|trak| &trak.tkhd).collect();
+        assert_eq!(tkhds[0].creation_time, mvhd.creation_time);
+        assert_eq!(mp4.moov.traks[0].mdia.mdhd.creation_time, mvhd.creation_time);
+        assert_eq!(tkhds[0].flags, 0x03);
+        assert_eq!(tkhds[0].volume.value(), 0);
+        assert_eq!((tkhds[0].matrix.a, tkhds[0].matrix.b), (0, 0x10000));
+        assert_eq!((tkhds[0].matrix.c, tkhds[0].matrix.d), (-0x10000, 0));
+        assert_eq!(tkhds[1].flags, 0x03);
+        assert_eq!(tkhds[1].volume.value(), 1);
+        assert_eq!(tkhds[1].alternate_group, 1);
+        assert_eq!(tkhds[2].flags, 0x02);
+        assert_eq!(tkhds[2].alternate_group, 1);
+    }
+
+    #[test]
+    fn test_write_bitrate() {
+        let config = test_config();
+        let mut writer = Mp4Writer::write_start(Cursor::new(Vec::new()), &config).unwrap();
+        let video = writer
+            .add_track(&TrackConfig::from(AvcConfig {
+                width: 1280,
+                height: 720,
+                seq_param_set: vec![0x67, 0x64, 0x00, 0x1F],
+                pic_param_set: vec![0x68, 0xEB],
+                seq_param_set_ext: Vec::new(),
+                box_type: AvcBoxType::Avc1,
+            }))
+            .unwrap();
+        let audio = writer
+            .add_track(&TrackConfig::from(AacConfig::default()))
+            .unwrap();
+        // 3 seconds of 100ms samples, with a burst of larger ones in the
+        // second second.
+        for i in 0..30 {
+            let size = if (10..15).contains(&i) { 5000 } else { 1000 };
+            for track_id in [video, audio].iter() {
+                let sample = Mp4Sample {
+                    start_time: i * 100,
+                    duration: 100,
+                    rendering_offset: 0,
+                    is_sync: true,
+                    sample_description_index: 1,
+                    bytes: Bytes::from(vec![0; size]),
+                };
+                writer.write_sample(*track_id, &sample).unwrap();
+            }
+        }
+        writer.write_end().unwrap();
+        let buf = writer.into_writer().into_inner();
+
+        let size = buf.len() as u64;
+        let mp4 = Mp4Reader::read_header(Cursor::new(buf), size).unwrap();
+        let stsd = &mp4.moov.traks[0].mdia.minf.stbl.stsd;
+        let btrt = stsd.avc1().unwrap().btrt.as_ref().unwrap();
+        assert_eq!(btrt.buffer_size_db, 5000);
+        assert_eq!(btrt.avg_bitrate, 50000 * 8 / 3);
+        assert_eq!(btrt.max_bitrate, 30000 * 8);
+        assert_eq!(mp4.tracks()[0].bitrate(), 50000 * 8 / 3);
+
+        let mp4a = mp4.moov.traks[1].mdia.minf.stbl.stsd.mp4a().unwrap();
+        assert_eq!(mp4a.btrt.as_ref(), Some(btrt));
+        let dec_config = &mp4a.esds.as_ref().unwrap().es_desc.dec_config;
+        assert_eq!(dec_config.avg_bitrate, 50000 * 8 / 3);
+        assert_eq!(dec_config.max_bitrate, 30000 * 8);
+        assert_eq!(mp4.tracks()[1].bitrate(), 50000 * 8 / 3);
+    }
+
+    #[test]
+    fn test_write_track_references() {
+        let config = test_config();
+        let mut writer = Mp4Writer::write_start(Cursor::new(Vec::new()), &config).unwrap();
+        let video = writer.add_track(&TrackConfig::from(TtxtConfig {})).unwrap();
+        let chapters = writer.add_track(&TrackConfig::from(TtxtConfig {})).unwrap();
+        let chap = str::parse("chap").unwrap();
+        writer.add_track_reference(video, chap, chapters).unwrap();
+        assert!(writer.add_track_reference(video, chap, 5).is_err());
+        assert!(writer.add_track_reference(5, chap, chapters).is_err());
+        writer.write_end().unwrap();
+        let buf = writer.into_writer().into_inner();
+
+        let size = buf.len() as u64;
+        let mp4 = Mp4Reader::read_header(Cursor::new(buf), size).unwrap();
+        let references = mp4.tracks()[0].references();
+        assert_eq!(references.len(), 1);
+        assert_eq!(references[0].reference_type, chap);
+        assert_eq!(references[0].track_ids, vec![chapters]);
+        assert!(mp4.tracks()[1].references().is_empty());
+    }
+
+    #[test]
+    fn test_write_opus() {
+        let config = Mp4Config {
+            compatible_brands: vec![str::parse("isom").unwrap(), str::parse("opus").unwrap()],
+            ..test_config()
+        };
+        let opus_config = OpusConfig {
+            channel_count: 6,
+            pre_skip: 312,
+            input_sample_rate: 44100,
+            output_gain: 0,
+            channel_mapping_family: 1,
+            channel_mapping_table: Some(OpusChannelMappingTable {
+                stream_count: 4,
+                coupled_count: 2,
+                channel_mapping: vec![0, 4, 1, 2, 3, 5],
+            }),
+        };
+        let mut writer = Mp4Writer::write_start(Cursor::new(Vec::new()), &config).unwrap();
+        let track_id = writer
+            .add_track(&TrackConfig::from(opus_config.clone()))
+            .unwrap();
+        // 20ms packets.
+        for i in 0..50 {
+            let sample = Mp4Sample {
+                start_time: i * 960,
+                duration: 960,
+                rendering_offset: 0,
+                is_sync: true,
+                bytes: Bytes::from(vec![0xFC; 100]),
+                sample_description_index: 1,
+            };
+            writer.write_sample(track_id, &sample).unwrap();
+        }
+        writer.write_end().unwrap();
+        let buf = writer.into_writer().into_inner();
+
+        let size = buf.len() as u64;
+        let mp4 = Mp4Reader::read_header(Cursor::new(buf), size).unwrap();
+        let track = &mp4.tracks()[0];
+        assert_eq!(track.media_type().unwrap(), MediaType::OPUS);
+        assert_eq!(track.box_type().unwrap(), str::parse("Opus").unwrap());
+        assert_eq!(track.track_type().unwrap(), TrackType::Audio);
+        assert_eq!(track.timescale(), 48000);
+        assert_eq!(track.duration(), Duration::from_secs(1));
+        assert_eq!(track.bitrate(), 40000);
+        assert!(track.trak.mdia.minf.smhd.is_some());
+        assert_eq!(track.opus_config().unwrap(), opus_config);
+    }
+
+    #[test]
+    fn test_write_opus_invalid_channel_mapping() {
+        let table = OpusChannelMappingTable {
+            stream_count: 4,
+            coupled_count: 2,
+            channel_mapping: vec![0, 4, 1, 2, 3, 5],
+        };
+        let invalid = [
+            // Family 1 without a table.
+            OpusConfig {
+                channel_count: 6,
+                channel_mapping_family: 1,
+                ..OpusConfig::default()
+            },
+            // A table for family 0.
+            OpusConfig {
+                channel_count: 6,
+                channel_mapping_family: 0,
+                channel_mapping_table: Some(table.clone()),
+                ..OpusConfig::default()
+            },
+            // A table with fewer entries than channels.
+            OpusConfig {
+                channel_count: 8,
+                channel_mapping_family: 1,
+                channel_mapping_table: Some(table.clone()),
+                ..OpusConfig::default()
+            },
+        ];
+
+        let mut writer = Mp4Writer::write_start(Cursor::new(Vec::new()), &test_config()).unwrap();
+        let track_id = writer
+            .add_track(&TrackConfig::from(OpusConfig::default()))
+            .unwrap();
+        for opus_config in invalid.iter() {
+            let err = writer
+                .add_track(&TrackConfig::from(opus_config.clone()))
+                .unwrap_err();
+            assert!(matches!(err, Error::InvalidData(_)));
+            let err = writer
+                .add_sample_description(track_id, &MediaConfig::OpusConfig(opus_config.clone()))
+                .unwrap_err();
+            assert!(matches!(err, Error::InvalidData(_)));
+        }
+    }
+
+    #[test]
+    fn test_write_av1() {
+        let config = Mp4Config {
+            compatible_brands: vec![str::parse("isom").unwrap(), str::parse("av01").unwrap()],
+            ..test_config()
+        };
+        let av1_config = Av1Config {
+            width: 1920,
+            height: 1080,
+            seq_level_idx: 8,
+            bit_depth: 10,
+            config_obus: vec![0x0A, 0x0B, 0x00, 0x00, 0x00, 0x42, 0xAB, 0xBF, 0xC3, 0x70, 0x0B, 0xE0, 0x80],
+            ..Av1Config::default()
+        };
+        let mut writer = Mp4Writer::write_start(Cursor::new(Vec::new()), &config).unwrap();
+        let track_id = writer
+            .add_track(&TrackConfig::from(av1_config.clone()))
+            .unwrap();
+        let sample = Mp4Sample {
+            start_time: 0,
+            duration: 1000,
+            rendering_offset: 0,
+            is_sync: true,
+            bytes: Bytes::from_static(&[0x12, 0x00, 0x32, 0x00]),
+            sample_description_index: 1,
+        };
+        writer.write_sample(track_id, &sample).unwrap();
+        writer.write_end().unwrap();
+        let buf = writer.into_writer().into_inner();
+
+        let size = buf.len() as u64;
+        let mut mp4 = Mp4Reader::read_header(Cursor::new(buf), size).unwrap();
+        let track = &mp4.tracks()[0];
+        assert_eq!(track.media_type().unwrap(), MediaType::AV1);
+        assert_eq!(track.box_type().unwrap(), str::parse("av01").unwrap());
+        assert_eq!(track.width(), 1920);
+        assert!(track.trak.mdia.minf.vmhd.is_some());
+        assert_eq!(track.av1_config().unwrap(), av1_config);
+        assert_eq!(mp4.read_sample(track_id, 1).unwrap(), Some(sample));
+    }
+
+    #[test]
+    fn test_write_avc3() {
+        let config = test_config();
+        let avc_config = AvcConfig {
+            width: 1920,
+            height: 1080,
+            seq_param_set: vec![0x67, 0x7A, 0x00, 0x28, 0xB6, 0xC0],
+            pic_param_set: vec![0x68, 0xEB],
+            seq_param_set_ext: vec![0x6D, 0x00],
+            box_type: AvcBoxType::Avc3,
+        };
+        let mut writer = Mp4Writer::write_start(Cursor::new(Vec::new()), &config).unwrap();
+        writer
+            .add_track(&TrackConfig::from(avc_config.clone()))
+            .unwrap();
+        writer.write_end().unwrap();
+        let buf = writer.into_writer().into_inner();
+
+        let size = buf.len() as u64;
+        let mp4 = Mp4Reader::read_header(Cursor::new(buf), size).unwrap();
+        let track = &mp4.tracks()[0];
+        assert_eq!(track.media_type().unwrap(), MediaType::H264);
+        assert_eq!(track.box_type().unwrap(), str::parse("avc3").unwrap());
+        assert_eq!(track.avc_box_type().unwrap(), AvcBoxType::Avc3);
+        assert_eq!(track.width(), 1920);
+        assert_eq!(track.sequence_parameter_set().unwrap(), avc_config.seq_param_set);
+        assert_eq!(track.picture_parameter_set().unwrap(), avc_config.pic_param_set);
+        assert_eq!(
+            track.sequence_parameter_set_ext(),
+            Some(&avc_config.seq_param_set_ext[..])
+        );
+        let ext = track.trak.mdia.minf.stbl.stsd.avc().unwrap().avcc.ext.as_ref();
+        assert_eq!(ext.unwrap().chroma_format, 2);
+    }
+
+    #[test]
+    fn test_write_hevc_parameter_sets() {
+        let config = test_config();
+        let hevc_config = HevcConfig {
+            width: 1920,
+            height: 1080,
+            video_param_set: vec![0x40, 0x01, 0x0C, 0x01],
+            seq_param_set: vec![
+                0x42, 0x01, 0x01, 0x01, 0x60, 0x00, 0x00, 0x03, 0x00, 0x90, 0x00, 0x00, 0x03,
+                0x00, 0x00, 0x03, 0x00, 0x78, 0xA0, 0x03, 0xC0, 0x80,
+            ],
+            pic_param_set: vec![0x44, 0x01, 0xC1],
+            box_type: HevcBoxType::Hvc1,
+        };
+        let mut writer = Mp4Writer::write_start(Cursor::new(Vec::new()), &config).unwrap();
+        writer
+            .add_track(&TrackConfig::from(hevc_config.clone()))
+            .unwrap();
+        writer.write_end().unwrap();
+        let buf = writer.into_writer().into_inner();
+
+        let size = buf.len() as u64;
+        let mp4 = Mp4Reader::read_header(Cursor::new(buf), size).unwrap();
+        let track = &mp4.tracks()[0];
+        assert_eq!(track.media_type().unwrap(), MediaType::H265);
+        assert_eq!(track.box_type().unwrap(), str::parse("hvc1").unwrap());
+        assert_eq!(track.hevc_box_type().unwrap(), HevcBoxType::Hvc1);
+        assert_eq!(track.video_parameter_set().unwrap(), hevc_config.video_param_set);
+        assert_eq!(track.sequence_parameter_set().unwrap(), hevc_config.seq_param_set);
+        assert_eq!(track.picture_parameter_set().unwrap(), hevc_config.pic_param_set);
+        let hvcc = &track.trak.mdia.minf.stbl.stsd.hvc1().unwrap().hvcc;
+        assert_eq!(hvcc.general_profile_idc, 1);
+        assert_eq!(hvcc.general_level_idc, 120);
+        assert_eq!(hvcc.length_size_minus_one, 3);
+    }
+
+    #[test]
+    fn test_write_edit_list() {
+        let config = test_config();
+        let mut writer = Mp4Writer::write_start(Cursor::new(Vec::new()), &config).unwrap();
+        // Delay the track by 500ms and skip 1024 samples of priming.
+        let track_conf = TrackConfig {
+            timescale: 48000,
+            edit_list: vec![
+                EditListEntry::empty(24000),
+                EditListEntry::media(1024, None),
+            ],
+            ..TrackConfig::from(TtxtConfig {})
+        };
+        writer.add_track(&track_conf).unwrap();
+        for i in 0..10 {
+            let sample = Mp4Sample {
+                start_time: i * 1024,
+                duration: 1024,
+                rendering_offset: 0,
+                is_sync: true,
+                sample_description_index: 1,
+                bytes: Bytes::from(vec![0; 4]),
+            };
+            writer.write_sample(1, &sample).unwrap();
+        }
+        writer.write_end().unwrap();
+        let buf = writer.into_writer().into_inner();
+
+        let size = buf.len() as u64;
+        let mp4 = Mp4Reader::read_header(Cursor::new(buf), size).unwrap();
+        let trak = &mp4.moov.traks[0];
+        let elst = trak.edts.as_ref().unwrap().elst.as_ref().unwrap();
+        assert_eq!(elst.entries.len(), 2);
+        assert_eq!(elst.entries[0].segment_duration, 500);
+        assert!(elst.entries[0].is_empty_edit());
+        assert_eq!(elst.entries[1].segment_duration, 192); // 9216 / 48
+        assert_eq!(elst.entries[1].media_time, 1024);
+        assert_eq!(trak.tkhd.duration, 692);
+        assert_eq!(mp4.moov.mvhd.duration, 692);
+
+        assert_eq!(
+            mp4.presentation_time(1, 2).unwrap(),
+            Duration::from_millis(500)
+        );
+    }
+
+    #[test]
+    fn test_write_large_mdat() {
+        let config = test_config();
+        let sample_size = 64 << 20;
+        let sample_count = 65;
+        let data = SparseWriter {
+            pos: 0,
+            len: 0,
+            head: Vec::new(),
+            tail_start: sample_size * sample_count,
+            tail: Vec::new(),
+        };
+        let mut writer = Mp4Writer::write_start(data, &config).unwrap();
+        writer.add_track(&TrackConfig::from(TtxtConfig {})).unwrap();
+        let bytes = Bytes::from(vec![0; sample_size as usize]);
+        for i in 0..sample_count {
+            let sample = Mp4Sample {
+                start_time: i * 1000,
+                duration: 1000,
+                rendering_offset: 0,
+                is_sync: true,
+                sample_description_index: 1,
+                bytes: bytes.clone(),
+            };
+            writer.write_sample(1, &sample).unwrap();
+        }
+        writer.write_end().unwrap();
+        let data = writer.into_writer();
+
+        let mut reader = Cursor::new(&data.head);
+        let ftyp = BoxHeader::read(&mut reader).unwrap();
+        reader.set_position(ftyp.size);
+        let mdat = BoxHeader::read(&mut reader).unwrap();
+        assert_eq!(mdat.name, BoxType::MdatBox);
+        assert_eq!(mdat.header_size, HEADER_LARGE_SIZE);
+        assert_eq!(mdat.size, HEADER_LARGE_SIZE + sample_size * sample_count);
+
+        let mut reader = Cursor::new(&data.tail);
+        reader.set_position(ftyp.size + mdat.size - data.tail_start);
+        let header = BoxHeader::read(&mut reader).unwrap();
+        assert_eq!(header.name, BoxType::MoovBox);
+        let moov = MoovBox::read_box(&mut reader, header.size).unwrap();
+
+        let stbl = &moov.traks[0].mdia.minf.stbl;
+        assert!(stbl.stco.is_none());
+        let co64 = stbl.co64.as_ref().unwrap();
+        assert_eq!(co64.entries.len(), sample_count as usize);
+        assert_eq!(co64.entries[0], ftyp.size + HEADER_LARGE_SIZE);
+        assert_eq!(
+            co64.entries[64],
+            ftyp.size + HEADER_LARGE_SIZE + 64 * sample_size
+        );
+    }
+}
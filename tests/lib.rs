@@ -1,6 +1,6 @@
 use mp4::{AudioObjectType, AvcProfile, ChannelConfig, MediaType, SampleFreqIndex, TrackType};
 use std::fs::File;
-use std::io::BufReader;
+use std::io::{BufReader, Cursor};
 use std::time::Duration;
 
 #[test]
@@ -45,6 +45,7 @@ fn test_read_mp4() {
             duration: 512,
             rendering_offset: 0,
             is_sync: true,
+            sample_description_index: 1,
             bytes: mp4::Bytes::from(vec![0x0u8; 751]),
         }
     );
@@ -62,6 +63,7 @@ fn test_read_mp4() {
             duration: 1024,
             rendering_offset: 0,
             is_sync: true,
+            sample_description_index: 1,
             bytes: mp4::Bytes::from(vec![0x0u8; 179]),
         }
     );
@@ -74,6 +76,7 @@ fn test_read_mp4() {
             duration: 1024,
             rendering_offset: 0,
             is_sync: true,
+            sample_description_index: 1,
             bytes: mp4::Bytes::from(vec![0x0u8; 180]),
         }
     );
@@ -86,6 +89,7 @@ fn test_read_mp4() {
             duration: 896,
             rendering_offset: 0,
             is_sync: true,
+            sample_description_index: 1,
             bytes: mp4::Bytes::from(vec![0x0u8; 160]),
         }
     );
@@ -119,3 +123,102 @@ fn test_read_mp4() {
     assert_eq!(track2.channel_config().unwrap(), ChannelConfig::Mono);
     assert_eq!(track2.bitrate(), 67695);
 }
+
+fn test_config() -> mp4::Mp4Config {
+    mp4::Mp4Config {
+        major_brand: str::parse("isom").unwrap(),
+        minor_version: 512,
+        compatible_brands: vec![str::parse("isom").unwrap()],
+        timescale: 1000,
+        creation_time: 0,
+        modification_time: 0,
+    }
+}
+
+#[test]
+fn test_write_read_samples() {
+    let config = test_config();
+    let data = Cursor::new(Vec::<u8>::new());
+    let mut writer = mp4::Mp4Writer::write_start(data, &config).unwrap();
+    writer
+        .add_track(&mp4::TrackConfig::from(mp4::TtxtConfig {}))
+        .unwrap();
+
+    let samples: Vec<mp4::Mp4Sample> = (0..50u32)
+        .map(|i| mp4::Mp4Sample {
+            start_time: i as u64 * 100,
+            duration: 100,
+            rendering_offset: if i % 3 == 1 { 200 } else { 0 },
+            is_sync: i % 10 == 0,
+            sample_description_index: 1,
+            bytes: mp4::Bytes::from(vec![i as u8; 10 + i as usize]),
+        })
+        .collect();
+    for sample in samples.iter() {
+        writer.write_sample(1, sample).unwrap();
+    }
+    writer.write_end().unwrap();
+
+    let data = writer.into_writer().into_inner();
+    let size = data.len() as u64;
+    let mut mp4 = mp4::Mp4Reader::read_header(Cursor::new(data), size).unwrap();
+    assert_eq!(mp4.sample_count(1).unwrap(), 50);
+
+    // Read backwards to exercise random access.
+    for (i, expected) in samples.iter().enumerate().rev() {
+        let sample = mp4.read_sample(1, i as u32 + 1).unwrap().unwrap();
+        assert_eq!(&sample, expected);
+        assert_eq!(sample.bytes, expected.bytes);
+    }
+    assert!(mp4.read_sample(1, 51).unwrap().is_none());
+}
+
+#[test]
+fn test_write_read_track_ids() {
+    let config = test_config();
+    let data = Cursor::new(Vec::<u8>::new());
+    let mut writer = mp4::Mp4Writer::write_start(data, &config).unwrap();
+    let track_config = mp4::TrackConfig::from(mp4::TtxtConfig {});
+    writer.add_track_with_id(257, &track_config).unwrap();
+    assert_eq!(writer.add_track(&track_config).unwrap(), 258);
+    assert!(writer.add_track_with_id(257, &track_config).is_err());
+    assert!(writer.add_track_with_id(0, &track_config).is_err());
+
+    for (track_id, count) in [(257, 3), (258, 2)] {
+        for i in 0..count {
+            let sample = mp4::Mp4Sample {
+                start_time: i * 1000,
+                duration: 1000,
+                rendering_offset: 0,
+                is_sync: true,
+                sample_description_index: 1,
+                bytes: mp4::Bytes::from(vec![track_id as u8; 8]),
+            };
+            writer.write_sample(track_id, &sample).unwrap();
+        }
+    }
+    assert!(writer
+        .write_sample(1, &mp4::Mp4Sample {
+            start_time: 0,
+            duration: 1000,
+            rendering_offset: 0,
+            is_sync: true,
+            sample_description_index: 1,
+            bytes: mp4::Bytes::new(),
+        })
+        .is_err());
+    writer.write_end().unwrap();
+
+    let data: Vec<u8> = writer.into_writer().into_inner();
+    let size = data.len() as u64;
+    let mut mp4 = mp4::Mp4Reader::read_header(Cursor::new(data), size).unwrap();
+
+    let track_ids: Vec<u32> = mp4.tracks().iter().map(|track| track.track_id()).collect();
+    assert_eq!(track_ids, vec![257, 258]);
+    assert_eq!(mp4.track(258).unwrap().track_id(), 258);
+    assert_eq!(mp4.sample_count(257).unwrap(), 3);
+    assert_eq!(mp4.sample_count(258).unwrap(), 2);
+    let sample = mp4.read_sample(258, 2).unwrap().unwrap();
+    assert_eq!(sample.bytes.as_ref(), &[2; 8]);
+    assert!(mp4.read_sample(1, 1).is_err());
+}